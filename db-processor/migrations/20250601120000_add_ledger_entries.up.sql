@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS ledger_entries (
+    id              TEXT        PRIMARY KEY,
+    transaction_id  TEXT        NOT NULL,
+    user_id         TEXT        NOT NULL,
+    asset           VARCHAR(50) NOT NULL,
+    account         VARCHAR(20) NOT NULL,
+    direction       VARCHAR(10) NOT NULL,
+    amount          NUMERIC     NOT NULL,
+    reason          VARCHAR(20) NOT NULL,
+    reference_id    TEXT        NOT NULL,
+    "time"          TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_ledger_entries_user_time ON ledger_entries (user_id, "time" DESC);
+CREATE INDEX IF NOT EXISTS idx_ledger_entries_transaction ON ledger_entries (transaction_id);
@@ -1,25 +1,95 @@
-use std::time::Duration;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
 use dotenv::dotenv;
+use metrics::{counter, gauge, histogram};
+use metrics_exporter_prometheus::PrometheusBuilder;
 use redis::Commands;
 use sqlx::PgPool;
+use tokio::signal::unix::{signal, SignalKind};
 use tracing::{error, info};
 
-use services::{process_trade_dynamically, RedisManager};
-use types::IncomingMessage;
+use services::{insert_ledger_entries, process_trade_dynamically, RedisManager};
+use types::MessageFromEngine;
 
 mod services;
 mod types;
 
+const DB_PROCESSOR_QUEUE: &str = "db_processor";
+
+/// Longest the processor blocks on the queue, so shutdown is noticed promptly.
+const QUEUE_POLL_TIMEOUT_SECS: f64 = 1.0;
+
+/// Longest a graceful shutdown may take before the process exits anyway.
+const SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+
+/// Port Prometheus scrapes db-processor on, unless `METRICS_PORT` is set.
+const DEFAULT_METRICS_PORT: u16 = 9101;
+
+/// Histogram buckets for insert latency, in seconds.
+const INSERT_DURATION_BUCKETS: [f64; 10] =
+    [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+/// List that messages Postgres rejects are moved to, newest first.
+const DEAD_LETTER_QUEUE: &str = "db_processor_dead_letter";
+
+/// First wait before retrying an insert that failed on the connection.
+const INITIAL_RETRY_BACKOFF_MS: u64 = 100;
+
+/// Longest wait between retries of the same insert.
+const MAX_RETRY_BACKOFF_MS: u64 = 5000;
+
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
     dotenv().ok();
 
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let shutdown_flag = shutdown.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        info!("Shutting down, finishing the message in flight");
+        shutdown_flag.store(true, Ordering::Relaxed);
+
+        tokio::time::sleep(Duration::from_secs(SHUTDOWN_TIMEOUT_SECS)).await;
+        error!(
+            "Shutdown took longer than {}s, exiting",
+            SHUTDOWN_TIMEOUT_SECS
+        );
+        std::process::exit(1);
+    });
+
+    let metrics_port = std::env::var("METRICS_PORT")
+        .ok()
+        .and_then(|port| port.parse().ok())
+        .unwrap_or(DEFAULT_METRICS_PORT);
+    PrometheusBuilder::new()
+        .with_http_listener(([0, 0, 0, 0], metrics_port))
+        .set_buckets(&INSERT_DURATION_BUCKETS)?
+        .install()?;
+    info!("Serving metrics on port {}", metrics_port);
+
     let redis_manager = RedisManager::new();
     let mut conn = redis_manager.get_connection()?;
 
+    let mut backlog_conn = redis_manager.get_connection()?;
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(5));
+        loop {
+            interval.tick().await;
+            match backlog_conn.llen::<_, u64>(DB_PROCESSOR_QUEUE) {
+                Ok(backlog) => gauge!("db_processor_backlog").set(backlog as f64),
+                Err(e) => error!("Failed to read {} backlog: {:?}", DB_PROCESSOR_QUEUE, e),
+            }
+        }
+    });
+
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let pool = PgPool::connect(&database_url).await?;
 
@@ -36,23 +106,92 @@ async fn main() -> Result<()> {
         }
     });
 
-    loop {
-        let response: Option<(String, String)> = conn.brpop("db_processor", 0.0)?;
+    while !shutdown.load(Ordering::Relaxed) {
+        let response: Option<(String, String)> =
+            conn.brpop(DB_PROCESSOR_QUEUE, QUEUE_POLL_TIMEOUT_SECS)?;
         if let Some((_, message)) = response {
-            let parsed: IncomingMessage = serde_json::from_str(&message)?;
-
-            if parsed.message_type == "TRADE_ADDED" {
-                if let Err(e) = process_trade_dynamically(&pool, &parsed.data).await {
-                    error!(
-                        "Failed to process trade for {}: {:?}",
-                        parsed.data.ticker, e
-                    );
-                } else {
-                    info!("Processed trade for {}", parsed.data.ticker);
+            let parsed: MessageFromEngine = match serde_json::from_str(&message) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    error!("Skipping malformed message {}: {:?}", message, e);
+                    counter!("db_processor_malformed_messages_total").increment(1);
+                    continue;
                 }
+            };
+
+            let mut backoff = Duration::from_millis(INITIAL_RETRY_BACKOFF_MS);
+            loop {
+                let started = Instant::now();
+                let (kind, rows, result) = persist(&pool, &parsed).await;
+                histogram!("db_insert_duration_seconds", "kind" => kind).record(started.elapsed());
+                let outcome = if result.is_ok() { "ok" } else { "error" };
+                counter!("db_inserted_rows_total", "kind" => kind, "outcome" => outcome)
+                    .increment(rows);
+
+                let Err(e) = result else { break };
+                if !is_transient(&e) {
+                    // Postgres refused the rows, so retrying won't help. Keep
+                    // them for an operator instead of dropping them
+                    error!("Moving {} to {}: {:?}", kind, DEAD_LETTER_QUEUE, e);
+                    conn.lpush::<_, _, ()>(DEAD_LETTER_QUEUE, &message)?;
+                    counter!("db_processor_dead_letters_total", "kind" => kind).increment(1);
+                    break;
+                }
+                if shutdown.load(Ordering::Relaxed) {
+                    // Back on the consuming end, so the next start takes it first
+                    error!("Requeueing {} on shutdown after: {:?}", kind, e);
+                    conn.rpush::<_, _, ()>(DB_PROCESSOR_QUEUE, &message)?;
+                    break;
+                }
+                error!("Retrying {} in {:?}: {:?}", kind, backoff, e);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(Duration::from_millis(MAX_RETRY_BACKOFF_MS));
             }
         }
     }
+
+    // Whatever is still queued stays in Redis for the next start
+    pool.close().await;
+    info!("Shutdown complete");
+    Ok(())
+}
+
+/// Writes one message, returning its metric kind and row count with the result.
+async fn persist(pool: &PgPool, message: &MessageFromEngine) -> (&'static str, u64, Result<()>) {
+    match message {
+        MessageFromEngine::AddTrade { data } => {
+            let result = process_trade_dynamically(pool, data).await;
+            if result.is_ok() {
+                info!("Processed trade for {}", data.ticker);
+            }
+            ("trade", 1, result)
+        }
+        MessageFromEngine::LedgerEntries { data } => {
+            let result = insert_ledger_entries(pool, data).await;
+            if result.is_ok() {
+                info!("Persisted {} ledger entries", data.len());
+            }
+            ("ledger_entry", data.len() as u64, result)
+        }
+    }
+}
+
+/// Whether an insert failed on the way to Postgres rather than being refused by
+/// it. Ledger inserts skip ids already written, so retrying them is safe.
+fn is_transient(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Database(_)) | None => false,
+        Some(_) => true,
+    }
+}
+
+/// Resolves on Ctrl-C or SIGTERM.
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
 }
 
 async fn refresh_views(pool: &PgPool) -> Result<(), sqlx::Error> {
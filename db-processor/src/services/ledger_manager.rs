@@ -0,0 +1,35 @@
+use anyhow::Result;
+use sqlx::PgPool;
+
+use crate::types::LedgerEntry;
+
+pub async fn insert_ledger_entries(pool: &PgPool, entries: &[LedgerEntry]) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    for entry in entries {
+        sqlx::query(
+            r#"
+            INSERT INTO ledger_entries
+              (id, transaction_id, user_id, asset, account, direction, amount, reason, reference_id, "time")
+            VALUES
+              ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            ON CONFLICT (id) DO NOTHING
+            "#,
+        )
+        .bind(&entry.id)
+        .bind(&entry.transaction_id)
+        .bind(&entry.user_id)
+        .bind(&entry.asset)
+        .bind(&entry.account)
+        .bind(&entry.direction)
+        .bind(entry.amount)
+        .bind(&entry.reason)
+        .bind(&entry.reference_id)
+        .bind(entry.time)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
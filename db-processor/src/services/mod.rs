@@ -3,3 +3,6 @@ pub use redis_manager::*;
 
 pub mod timescale_dynamic_manager;
 pub use timescale_dynamic_manager::*;
+
+pub mod ledger_manager;
+pub use ledger_manager::*;
@@ -38,9 +38,9 @@ pub async fn ensure_market_resources(pool: &PgPool, raw_ticker: &str) -> Result<
     match pool.execute(create_hypertable_sql.as_str()).await {
         Ok(_) => info!("Ensured public.{} is a hypertable.", table_name),
         Err(e) => {
-            if e.as_database_error().map_or(false, |db_err| {
-                db_err.message().contains("already a hypertable")
-            }) {
+            if e.as_database_error()
+                .is_some_and(|db_err| db_err.message().contains("already a hypertable"))
+            {
                 warn!(
                     "Table public.{} was already a hypertable. Proceeding.",
                     table_name
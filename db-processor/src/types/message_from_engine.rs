@@ -7,6 +7,8 @@ use serde::Deserialize;
 pub enum MessageFromEngine {
     #[serde(rename = "TRADE_ADDED")]
     AddTrade { data: AddTradePayload },
+    #[serde(rename = "LEDGER_ENTRIES")]
+    LedgerEntries { data: Vec<LedgerEntry> },
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -16,3 +18,17 @@ pub struct AddTradePayload {
     pub quantity: Decimal,
     pub price: Decimal,
 }
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LedgerEntry {
+    pub id: String,
+    pub transaction_id: String,
+    pub user_id: String,
+    pub asset: String,
+    pub account: String,
+    pub direction: String,
+    pub amount: Decimal,
+    pub reason: String,
+    pub reference_id: String,
+    pub time: DateTime<Utc>,
+}
@@ -0,0 +1 @@
+ALTER TABLE markets DROP COLUMN amm_subsidy;
@@ -0,0 +1 @@
+ALTER TABLE markets ADD COLUMN amm_subsidy NUMERIC;
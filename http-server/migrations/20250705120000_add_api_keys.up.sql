@@ -0,0 +1,11 @@
+CREATE TABLE api_keys (
+    key_id       VARCHAR(64)  PRIMARY KEY,
+    -- SHA-256 of the secret, which is derived from API_KEY_PEPPER and never stored
+    secret_hash  VARCHAR(64)  NOT NULL,
+    user_id      VARCHAR(255) NOT NULL,
+    scopes       TEXT[]       NOT NULL,
+    created_at   TIMESTAMPTZ  NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    revoked_at   TIMESTAMPTZ
+);
+
+CREATE INDEX api_keys_user_id_idx ON api_keys (user_id);
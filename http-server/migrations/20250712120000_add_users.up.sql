@@ -0,0 +1,22 @@
+CREATE TABLE users (
+    id             UUID         PRIMARY KEY DEFAULT uuid_generate_v4(),
+    email          VARCHAR(255) NOT NULL,
+    password_hash  TEXT         NOT NULL,
+    created_at     TIMESTAMPTZ  NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX users_email_idx ON users (LOWER(email));
+
+-- Each login starts a family of refresh tokens, every refresh replaces the
+-- token with the next one in its family
+CREATE TABLE refresh_tokens (
+    token_hash  VARCHAR(64)  PRIMARY KEY,
+    user_id     UUID         NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    family_id   UUID         NOT NULL,
+    expires_at  TIMESTAMPTZ  NOT NULL,
+    used_at     TIMESTAMPTZ,
+    created_at  TIMESTAMPTZ  NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX refresh_tokens_family_id_idx ON refresh_tokens (family_id);
+CREATE INDEX refresh_tokens_user_id_idx ON refresh_tokens (user_id);
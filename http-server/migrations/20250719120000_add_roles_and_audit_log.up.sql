@@ -0,0 +1,18 @@
+ALTER TABLE users
+    ADD COLUMN role VARCHAR(32) NOT NULL DEFAULT 'trader'
+    CHECK (role IN ('trader', 'market-operator', 'admin', 'auditor'));
+
+-- One row per privileged request, whether it was allowed or not
+CREATE TABLE audit_log (
+    id          BIGSERIAL    PRIMARY KEY,
+    actor       VARCHAR(255) NOT NULL,
+    role        VARCHAR(32)  NOT NULL,
+    action      VARCHAR(255) NOT NULL,
+    params      JSONB        NOT NULL,
+    -- succeeded, failed or denied
+    outcome     VARCHAR(16)  NOT NULL,
+    detail      TEXT,
+    created_at  TIMESTAMPTZ  NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX audit_log_actor_idx ON audit_log (actor, id);
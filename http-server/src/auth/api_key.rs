@@ -0,0 +1,391 @@
+//! Authentication of private routes, with an access token in
+//! `Authorization: Bearer` or an HMAC-signed request with an API key.
+//!
+//! A client sends its key id in `X-API-KEY`, the time in milliseconds since
+//! the epoch in `X-API-TIMESTAMP`, and in `X-API-SIGNATURE` the hex
+//! HMAC-SHA256, keyed with its secret, of
+//! `timestamp + METHOD + path_and_query + body`.
+//!
+//! The server has to hold the signing key to check a signature, so instead
+//! of storing secrets it derives each one from `API_KEY_PEPPER` and the key
+//! id, and keeps only a hash of it to notice a changed pepper. A leaked
+//! `api_keys` table alone can't sign requests, a leaked pepper can sign for
+//! every key.
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{OriginalUri, Request, State},
+    http::{header::AUTHORIZATION, HeaderMap},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::state::AppState;
+
+use super::{record_denial, role_of, verify_access_token, AuthError, AuthUser, Scope};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+pub const TIMESTAMP_HEADER: &str = "x-api-timestamp";
+pub const SIGNATURE_HEADER: &str = "x-api-signature";
+
+/// Largest body a signed request may carry.
+pub(super) const MAX_SIGNED_BODY_BYTES: usize = 1024 * 1024;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub fn new_key_id() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+/// Secret of `key_id`, handed to its owner once and recomputed on every request.
+pub fn derive_secret(pepper: &str, key_id: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(pepper.as_bytes()).expect("HMAC takes any key size");
+    mac.update(key_id.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+pub fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+/// MAC over `timestamp + METHOD + path_and_query + body`, which a request's
+/// signature must match.
+pub(super) fn request_mac(
+    secret: &str,
+    timestamp: i64,
+    method: &str,
+    path_and_query: &str,
+    body: &[u8],
+) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC takes any key size");
+    mac.update(format!("{}{}{}", timestamp, method, path_and_query).as_bytes());
+    mac.update(body);
+    mac
+}
+
+fn check_signature(
+    secret: &str,
+    timestamp: i64,
+    method: &str,
+    path_and_query: &str,
+    body: &[u8],
+    signature: &[u8],
+) -> Result<(), AuthError> {
+    request_mac(secret, timestamp, method, path_and_query, body)
+        .verify_slice(signature)
+        .map_err(|_| AuthError::Unauthorized(String::from("Invalid signature")))
+}
+
+/// Rejects timestamps further than `window` from `now_ms` either way, so a
+/// captured request can't be replayed once its signature is forgotten.
+fn check_timestamp(timestamp: i64, now_ms: i64, window: Duration) -> Result<(), AuthError> {
+    let skew = now_ms.abs_diff(timestamp);
+    if Duration::from_millis(skew) > window {
+        return Err(AuthError::Unauthorized(String::from(
+            "Request timestamp is outside the signature window",
+        )));
+    }
+    Ok(())
+}
+
+pub async fn require_read(state: State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    authenticate(state, Scope::Read, request, next).await
+}
+
+pub async fn require_trade(state: State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    authenticate(state, Scope::Trade, request, next).await
+}
+
+pub async fn require_withdraw(
+    state: State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    authenticate(state, Scope::Withdraw, request, next).await
+}
+
+pub async fn require_admin(state: State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    authenticate(state, Scope::Admin, request, next).await
+}
+
+/// Checks the request's access token, or its signature and that its key has
+/// `scope`, then hands the user to the handler as an `AuthUser` extension.
+async fn authenticate(
+    State(state): State<Arc<AppState>>,
+    scope: Scope,
+    request: Request,
+    next: Next,
+) -> Response {
+    match verify(&state, scope, request).await {
+        Ok(request) => next.run(request).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn verify(
+    state: &AppState,
+    scope: Scope,
+    mut request: Request,
+) -> Result<Request, AuthError> {
+    let bearer = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if let Some(token) = bearer {
+        let user_id = verify_access_token(&state.auth, token)?;
+        let role = role_of(state, &user_id).await?;
+        if !role.session_scopes().contains(&scope) {
+            let denied = AuthError::Forbidden(format!(
+                "Sessions of the {} role lack the {} scope",
+                role.as_str(),
+                scope.as_str()
+            ));
+            // Only privileged routes need it, and they audit every refusal
+            if scope == Scope::Admin {
+                record_denial(state, user_id, role, request, &denied).await;
+            }
+            return Err(denied);
+        }
+        request.extensions_mut().insert(AuthUser { user_id });
+        return Ok(request);
+    }
+
+    let headers = request.headers();
+    let key_id = header(headers, API_KEY_HEADER)?.to_string();
+    let timestamp: i64 = header(headers, TIMESTAMP_HEADER)?.parse().map_err(|_| {
+        AuthError::Unauthorized(format!("{} must be in milliseconds", TIMESTAMP_HEADER))
+    })?;
+    let signature = hex::decode(header(headers, SIGNATURE_HEADER)?)
+        .map_err(|_| AuthError::Unauthorized(format!("{} must be hex", SIGNATURE_HEADER)))?;
+
+    let window = state.auth.signature_window;
+    check_timestamp(timestamp, Utc::now().timestamp_millis(), window)?;
+
+    // Routes are nested, so the handler's URI has lost its prefix
+    let path_and_query = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|uri| &uri.0)
+        .unwrap_or(request.uri())
+        .path_and_query()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_default();
+    let method = request.method().as_str().to_string();
+
+    let (parts, body) = request.into_parts();
+    let body = to_bytes(body, MAX_SIGNED_BODY_BYTES)
+        .await
+        .map_err(|_| AuthError::Unauthorized(String::from("Request body is too large to sign")))?;
+
+    let key = sqlx::query!(
+        r#"
+        SELECT user_id, scopes, secret_hash
+        FROM api_keys
+        WHERE key_id = $1 AND revoked_at IS NULL
+        "#,
+        key_id
+    )
+    .fetch_optional(&*state.db_pool)
+    .await
+    .map_err(|e| AuthError::Internal(format!("DB error: {}", e)))?
+    .ok_or_else(|| AuthError::Unauthorized(String::from("Unknown API key")))?;
+
+    let secret = derive_secret(&state.auth.api_key_pepper, &key_id);
+    if hash_secret(&secret) != key.secret_hash {
+        error!(
+            key_id,
+            "API key doesn't match the configured API_KEY_PEPPER"
+        );
+        return Err(AuthError::Unauthorized(String::from("Unknown API key")));
+    }
+
+    check_signature(
+        &secret,
+        timestamp,
+        &method,
+        &path_and_query,
+        &body,
+        &signature,
+    )?;
+
+    if !key.scopes.iter().any(|s| Scope::parse(s) == Some(scope)) {
+        return Err(AuthError::Forbidden(format!(
+            "API key lacks the {} scope",
+            scope.as_str()
+        )));
+    }
+
+    // A signature stays valid for the whole window, so each is accepted once
+    let fresh = state
+        .redis_manager
+        .claim_signature(&hex::encode(&signature), window * 2)
+        .await
+        .map_err(|e| AuthError::Internal(format!("Redis error: {}", e)))?;
+    if !fresh {
+        return Err(AuthError::Unauthorized(String::from(
+            "Signature was already used",
+        )));
+    }
+
+    let mut request = Request::from_parts(parts, Body::from(body));
+    request.extensions_mut().insert(AuthUser {
+        user_id: key.user_id,
+    });
+    Ok(request)
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Result<&'a str, AuthError> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| AuthError::Unauthorized(format!("Missing {} header", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEPPER: &str = "pepper";
+    const KEY_ID: &str = "0123456789abcdef0123456789abcdef";
+    const TIMESTAMP: i64 = 1_700_000_000_000;
+    const WINDOW: Duration = Duration::from_secs(5);
+
+    fn sign(secret: &str, timestamp: i64, method: &str, path: &str, body: &[u8]) -> Vec<u8> {
+        request_mac(secret, timestamp, method, path, body)
+            .finalize()
+            .into_bytes()
+            .to_vec()
+    }
+
+    fn is_unauthorized(result: Result<(), AuthError>) -> bool {
+        matches!(result, Err(AuthError::Unauthorized(_)))
+    }
+
+    #[test]
+    fn secrets_are_derived_per_key_and_pepper() {
+        let secret = derive_secret(PEPPER, KEY_ID);
+        assert_eq!(secret, derive_secret(PEPPER, KEY_ID));
+        assert_eq!(secret.len(), 64);
+        assert_ne!(secret, derive_secret(PEPPER, &new_key_id()));
+        assert_ne!(secret, derive_secret("other pepper", KEY_ID));
+        assert_eq!(hash_secret(&secret), hash_secret(&secret));
+        assert_ne!(hash_secret(&secret), secret);
+    }
+
+    #[test]
+    fn accepts_the_signature_of_the_exact_request() {
+        let secret = derive_secret(PEPPER, KEY_ID);
+        let body = br#"{"market":"YES_USDC"}"#;
+        let signature = sign(&secret, TIMESTAMP, "POST", "/api/v1/order", body);
+
+        assert!(check_signature(
+            &secret,
+            TIMESTAMP,
+            "POST",
+            "/api/v1/order",
+            body,
+            &signature
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn rejects_a_tampered_request() {
+        let secret = derive_secret(PEPPER, KEY_ID);
+        let path = "/api/v1/orders?market=YES_USDC";
+        let body = br#"{"quantity":"1"}"#;
+        let signature = sign(&secret, TIMESTAMP, "DELETE", path, body);
+        let check = |secret: &str, timestamp, method, path, body: &[u8]| {
+            check_signature(secret, timestamp, method, path, body, &signature)
+        };
+
+        assert!(check(&secret, TIMESTAMP, "DELETE", path, body).is_ok());
+        assert!(is_unauthorized(check(
+            &secret,
+            TIMESTAMP + 1,
+            "DELETE",
+            path,
+            body
+        )));
+        assert!(is_unauthorized(check(
+            &secret, TIMESTAMP, "POST", path, body
+        )));
+        assert!(is_unauthorized(check(
+            &secret,
+            TIMESTAMP,
+            "DELETE",
+            "/api/v1/orders?market=NO_USDC",
+            body
+        )));
+        assert!(is_unauthorized(check(
+            &secret,
+            TIMESTAMP,
+            "DELETE",
+            path,
+            br#"{"quantity":"100"}"#
+        )));
+        let other_secret = derive_secret(PEPPER, &new_key_id());
+        assert!(is_unauthorized(check(
+            &other_secret,
+            TIMESTAMP,
+            "DELETE",
+            path,
+            body
+        )));
+
+        let mut truncated = signature.clone();
+        truncated.pop();
+        assert!(is_unauthorized(check_signature(
+            &secret, TIMESTAMP, "DELETE", path, body, &truncated
+        )));
+    }
+
+    #[test]
+    fn accepts_timestamps_inside_the_window_either_way() {
+        let window_ms = WINDOW.as_millis() as i64;
+        for now in [TIMESTAMP, TIMESTAMP + window_ms, TIMESTAMP - window_ms] {
+            assert!(check_timestamp(TIMESTAMP, now, WINDOW).is_ok(), "{}", now);
+        }
+    }
+
+    #[test]
+    fn rejects_replays_once_the_window_has_passed() {
+        let window_ms = WINDOW.as_millis() as i64;
+        // Redis only remembers a signature for twice the window, after which
+        // the timestamp alone has to turn the replay away
+        for now in [TIMESTAMP + window_ms + 1, TIMESTAMP + 2 * window_ms + 1] {
+            assert!(
+                is_unauthorized(check_timestamp(TIMESTAMP, now, WINDOW)),
+                "{}",
+                now
+            );
+        }
+        // Clocks running ahead don't buy a longer window either
+        assert!(is_unauthorized(check_timestamp(
+            TIMESTAMP,
+            TIMESTAMP - window_ms - 1,
+            WINDOW
+        )));
+    }
+
+    #[test]
+    fn rejects_timestamps_at_the_ends_of_the_range() {
+        // The timestamp comes straight from a header, so the skew must not
+        // overflow however far it is from now
+        for timestamp in [i64::MIN, i64::MAX] {
+            assert!(
+                is_unauthorized(check_timestamp(timestamp, TIMESTAMP, WINDOW)),
+                "{}",
+                timestamp
+            );
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::{fmt, time::Duration};
+
+use axum::{
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+pub mod api_key;
+pub use api_key::*;
+
+pub mod session;
+pub use session::*;
+
+pub mod role;
+pub use role::*;
+
+/// How far a signed request's timestamp may be from the server clock, unless
+/// `API_SIGNATURE_WINDOW_MS` is set.
+pub const DEFAULT_SIGNATURE_WINDOW_MS: u64 = 5_000;
+
+/// Lifetime of an access token, unless `ACCESS_TOKEN_TTL_SECS` is set.
+pub const DEFAULT_ACCESS_TOKEN_TTL_SECS: u64 = 15 * 60;
+
+/// Lifetime of a refresh token, unless `REFRESH_TOKEN_TTL_SECS` is set.
+pub const DEFAULT_REFRESH_TOKEN_TTL_SECS: u64 = 30 * 24 * 60 * 60;
+
+#[derive(Clone)]
+pub struct AuthConfig {
+    /// Server-side secret every API key secret is derived from
+    pub api_key_pepper: String,
+    pub signature_window: Duration,
+    /// Signs access tokens, shared with wss so it can check them too
+    pub jwt_secret: String,
+    pub access_token_ttl: Duration,
+    pub refresh_token_ttl: Duration,
+}
+
+impl AuthConfig {
+    pub fn from_env() -> Self {
+        let api_key_pepper = std::env::var("API_KEY_PEPPER").expect("API_KEY_PEPPER must be set");
+        let jwt_secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+        AuthConfig {
+            api_key_pepper,
+            signature_window: Duration::from_millis(env_or(
+                "API_SIGNATURE_WINDOW_MS",
+                DEFAULT_SIGNATURE_WINDOW_MS,
+            )),
+            jwt_secret,
+            access_token_ttl: Duration::from_secs(env_or(
+                "ACCESS_TOKEN_TTL_SECS",
+                DEFAULT_ACCESS_TOKEN_TTL_SECS,
+            )),
+            refresh_token_ttl: Duration::from_secs(env_or(
+                "REFRESH_TOKEN_TTL_SECS",
+                DEFAULT_REFRESH_TOKEN_TTL_SECS,
+            )),
+        }
+    }
+}
+
+fn env_or(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// What an API key may be used for. Sessions get the scopes of their user's
+/// role. Either way the role has to allow the route as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Balances, open orders, withdrawals and the private stream
+    Read,
+    /// Placing and cancelling orders, deposits and the faucet
+    Trade,
+    Withdraw,
+    /// Privileged routes: markets, withdrawal review and everything under
+    /// `/admin`
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Trade => "trade",
+            Scope::Withdraw => "withdraw",
+            Scope::Admin => "admin",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "read" => Some(Scope::Read),
+            "trade" => Some(Scope::Trade),
+            "withdraw" => Some(Scope::Withdraw),
+            "admin" => Some(Scope::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// The user a request acts for, set by the authentication middleware from
+/// an access token or a signed API key.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub user_id: String,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<AuthUser>()
+            .cloned()
+            .ok_or_else(|| AuthError::Unauthorized(String::from("Authentication required")))
+    }
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    BadRequest(String),
+    /// Missing, unknown or badly signed credentials
+    Unauthorized(String),
+    /// Valid credentials without the scope or role the route needs
+    Forbidden(String),
+    Internal(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::BadRequest(reason)
+            | AuthError::Unauthorized(reason)
+            | AuthError::Forbidden(reason)
+            | AuthError::Internal(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            AuthError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AuthError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AuthError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AuthError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, Json(json!({ "error": self.to_string() }))).into_response()
+    }
+}
@@ -0,0 +1,692 @@
+//! Roles and what each may do.
+//!
+//! Authentication decides which user a request acts for, the `authorize_*`
+//! middleware then checks that user's role against the route's permission.
+//! Privileged routes are also recorded in `audit_log` with the actor, the
+//! request and its outcome, including requests the role doesn't allow.
+
+use std::sync::Arc;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{MatchedPath, OriginalUri, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::state::AppState;
+
+use super::{AuthError, AuthUser, Scope, MAX_SIGNED_BODY_BYTES};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    Trader,
+    /// Trades, creates, closes and restores markets, and inspects their AMMs
+    MarketOperator,
+    /// May do everything
+    Admin,
+    /// Reads reconciliation reports, AMM state, API keys and the audit log,
+    /// can't trade
+    Auditor,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Trader => "trader",
+            Role::MarketOperator => "market-operator",
+            Role::Admin => "admin",
+            Role::Auditor => "auditor",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "trader" => Some(Role::Trader),
+            "market-operator" => Some(Role::MarketOperator),
+            "admin" => Some(Role::Admin),
+            "auditor" => Some(Role::Auditor),
+            _ => None,
+        }
+    }
+
+    /// Scopes a session of this role carries.
+    pub fn session_scopes(&self) -> &'static [Scope] {
+        match self {
+            Role::Trader => &[Scope::Read, Scope::Trade, Scope::Withdraw],
+            Role::MarketOperator | Role::Admin => {
+                &[Scope::Read, Scope::Trade, Scope::Withdraw, Scope::Admin]
+            }
+            Role::Auditor => &[Scope::Read],
+        }
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        match self {
+            Role::Trader => permission == Permission::Trade,
+            Role::MarketOperator => matches!(
+                permission,
+                Permission::Trade | Permission::OperateMarkets | Permission::InspectMarkets
+            ),
+            Role::Admin => true,
+            Role::Auditor => matches!(permission, Permission::Audit | Permission::InspectMarkets),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// Orders, the faucet and withdrawals
+    Trade,
+    /// Creating, closing and restoring markets
+    OperateMarkets,
+    /// AMM liquidity, subsidy and inventory
+    InspectMarkets,
+    /// Crediting deposits and approving and rejecting withdrawals
+    ManageFunds,
+    /// Issuing and revoking API keys and assigning roles
+    ManageAccess,
+    /// Reconciliation, API key listings and the audit log
+    Audit,
+}
+
+impl Permission {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Permission::Trade => "trade",
+            Permission::OperateMarkets => "operate markets",
+            Permission::InspectMarkets => "inspect markets",
+            Permission::ManageFunds => "manage funds",
+            Permission::ManageAccess => "manage access",
+            Permission::Audit => "audit",
+        }
+    }
+
+    /// Whether requests needing the permission go to the audit log.
+    fn privileged(&self) -> bool {
+        !matches!(
+            self,
+            Permission::Trade | Permission::InspectMarkets | Permission::Audit
+        )
+    }
+}
+
+/// Role of `user_id`. Users without an account, such as the owners of API
+/// keys issued before accounts existed, are traders.
+pub async fn role_of(state: &AppState, user_id: &str) -> Result<Role, AuthError> {
+    let Ok(id) = Uuid::parse_str(user_id) else {
+        return Ok(Role::Trader);
+    };
+    let row = sqlx::query!("SELECT role FROM users WHERE id = $1", id)
+        .fetch_optional(&*state.db_pool)
+        .await
+        .map_err(|e| AuthError::Internal(format!("DB error: {}", e)))?;
+
+    Ok(row
+        .and_then(|r| Role::parse(&r.role))
+        .unwrap_or(Role::Trader))
+}
+
+pub async fn authorize_trade(
+    state: State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    authorize(state, Permission::Trade, request, next).await
+}
+
+pub async fn authorize_market_operations(
+    state: State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    authorize(state, Permission::OperateMarkets, request, next).await
+}
+
+pub async fn authorize_market_inspection(
+    state: State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    authorize(state, Permission::InspectMarkets, request, next).await
+}
+
+pub async fn authorize_funds_management(
+    state: State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    authorize(state, Permission::ManageFunds, request, next).await
+}
+
+pub async fn authorize_access_management(
+    state: State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    authorize(state, Permission::ManageAccess, request, next).await
+}
+
+pub async fn authorize_audit(
+    state: State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    authorize(state, Permission::Audit, request, next).await
+}
+
+/// Runs the handler if the authenticated user's role has `permission`. Must
+/// be layered inside one of the `require_*` authentication middlewares.
+async fn authorize(
+    State(state): State<Arc<AppState>>,
+    permission: Permission,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(user) = request.extensions().get::<AuthUser>().cloned() else {
+        return AuthError::Unauthorized(String::from("Authentication required")).into_response();
+    };
+    let role = match role_of(&state, &user.user_id).await {
+        Ok(role) => role,
+        Err(e) => return e.into_response(),
+    };
+    let denied = AuthError::Forbidden(format!(
+        "The {} role may not {}",
+        role.as_str(),
+        permission.as_str()
+    ));
+
+    if !permission.privileged() {
+        return match role.can(permission) {
+            true => next.run(request).await,
+            false => denied.into_response(),
+        };
+    }
+
+    let (mut entry, request) = match AuditEntry::of(user.user_id, role, request).await {
+        Ok(audited) => audited,
+        Err(e) => return e.into_response(),
+    };
+    if !role.can(permission) {
+        entry.detail = Some(denied.to_string());
+        record(&state, entry).await;
+        return denied.into_response();
+    }
+
+    let response = next.run(request).await;
+    let (parts, body) = response.into_parts();
+    let body = to_bytes(body, usize::MAX).await.unwrap_or_default();
+    (entry.outcome, entry.detail) = outcome_of(parts.status, &body);
+    record(&state, entry).await;
+
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// Records a privileged request refused before its permission was checked,
+/// i.e. a session whose role lacks the `admin` scope.
+pub(super) async fn record_denial(
+    state: &AppState,
+    user_id: String,
+    role: Role,
+    request: Request,
+    denied: &AuthError,
+) {
+    if let Ok((mut entry, _)) = AuditEntry::of(user_id, role, request).await {
+        entry.detail = Some(denied.to_string());
+        record(state, entry).await;
+    }
+}
+
+struct AuditEntry {
+    actor: String,
+    role: Role,
+    action: String,
+    params: Value,
+    outcome: &'static str,
+    detail: Option<String>,
+}
+
+impl AuditEntry {
+    /// A denial of `request` by `actor`, until the outcome is known. Hands
+    /// the request back with its body, which had to be read.
+    async fn of(actor: String, role: Role, request: Request) -> Result<(Self, Request), AuthError> {
+        let action = format!(
+            "{} {}",
+            request.method(),
+            request
+                .extensions()
+                .get::<MatchedPath>()
+                .map(|p| p.as_str())
+                .unwrap_or(request.uri().path())
+        );
+        let uri = request
+            .extensions()
+            .get::<OriginalUri>()
+            .map(|uri| uri.0.to_string())
+            .unwrap_or_else(|| request.uri().to_string());
+
+        let (parts, body) = request.into_parts();
+        let body = to_bytes(body, MAX_SIGNED_BODY_BYTES)
+            .await
+            .map_err(|_| AuthError::BadRequest(String::from("Request body is too large")))?;
+        let entry = AuditEntry {
+            actor,
+            role,
+            action,
+            params: json!({
+                "uri": uri,
+                "body": serde_json::from_slice::<Value>(&body).unwrap_or(Value::Null),
+            }),
+            outcome: "denied",
+            detail: None,
+        };
+        Ok((entry, Request::from_parts(parts, Body::from(body))))
+    }
+}
+
+/// Handlers answer most failures with `200` and an `error` field, and the
+/// engine with a `REJECTED` message, so the body decides the outcome too.
+fn outcome_of(status: StatusCode, body: &[u8]) -> (&'static str, Option<String>) {
+    let body = serde_json::from_slice::<Value>(body).unwrap_or(Value::Null);
+    if let Some(error) = body.get("error").and_then(Value::as_str) {
+        return ("failed", Some(error.to_string()));
+    }
+    if body.get("type").and_then(Value::as_str) == Some("REJECTED") {
+        let payload = &body["payload"];
+        return (
+            "failed",
+            Some(format!(
+                "{}: {}",
+                payload["code"].as_str().unwrap_or_default(),
+                payload["message"].as_str().unwrap_or_default()
+            )),
+        );
+    }
+    if !status.is_success() {
+        return ("failed", Some(status.to_string()));
+    }
+    ("succeeded", None)
+}
+
+/// The action has already happened or been refused, so a failed write is
+/// only logged.
+async fn record(state: &AppState, entry: AuditEntry) {
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO audit_log (actor, role, action, params, outcome, detail)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        entry.actor,
+        entry.role.as_str(),
+        entry.action,
+        entry.params,
+        entry.outcome,
+        entry.detail,
+    )
+    .execute(&*state.db_pool)
+    .await;
+
+    if let Err(e) = inserted {
+        error!(
+            actor = entry.actor,
+            action = entry.action,
+            outcome = entry.outcome,
+            "Failed to write audit log: {}",
+            e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{
+        http::{
+            header::{AUTHORIZATION, CONTENT_TYPE},
+            Method,
+        },
+        Router,
+    };
+    use chrono::Utc;
+    use hmac::Mac;
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        auth::{
+            derive_secret, hash_secret, issue_access_token, request_mac, AuthConfig,
+            API_KEY_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER,
+        },
+        services::stub_redis,
+    };
+
+    const ROLES: [Role; 4] = [
+        Role::Trader,
+        Role::MarketOperator,
+        Role::Admin,
+        Role::Auditor,
+    ];
+
+    const PERMISSIONS: [Permission; 6] = [
+        Permission::Trade,
+        Permission::OperateMarkets,
+        Permission::InspectMarkets,
+        Permission::ManageFunds,
+        Permission::ManageAccess,
+        Permission::Audit,
+    ];
+
+    /// Every privileged route, each called with a body it would accept.
+    const PRIVILEGED_ROUTES: [(&str, &str, &str); 9] = [
+        (
+            "POST",
+            "/api/v1/market/create",
+            r#"{"name":"m","baseAsset":"YES","quoteAsset":"USDC"}"#,
+        ),
+        ("POST", "/api/v1/market/{id}/close", ""),
+        (
+            "POST",
+            "/api/v1/withdrawal/approve",
+            r#"{"withdrawalId":"w"}"#,
+        ),
+        (
+            "POST",
+            "/api/v1/withdrawal/reject",
+            r#"{"withdrawalId":"w"}"#,
+        ),
+        ("POST", "/api/v1/admin/markets/{market}/restore", ""),
+        (
+            "POST",
+            "/api/v1/admin/deposits",
+            r#"{"userId":"u","asset":"USDC","amount":"1","reference":"r"}"#,
+        ),
+        (
+            "POST",
+            "/api/v1/admin/apiKeys",
+            r#"{"userId":"u","scopes":["read"]}"#,
+        ),
+        ("DELETE", "/api/v1/admin/apiKeys/{key_id}", ""),
+        (
+            "PUT",
+            "/api/v1/admin/users/{user_id}/role",
+            r#"{"role":"trader"}"#,
+        ),
+    ];
+
+    /// Routes that read what only some roles may see.
+    const RESTRICTED_READS: [&str; 4] = [
+        "/api/v1/market/amm?market=YES_USDC",
+        "/api/v1/admin/reconciliation",
+        "/api/v1/admin/apiKeys",
+        "/api/v1/admin/auditLog",
+    ];
+
+    fn config() -> AuthConfig {
+        AuthConfig {
+            api_key_pepper: String::from("pepper"),
+            signature_window: Duration::from_secs(5),
+            jwt_secret: String::from("jwt secret"),
+            access_token_ttl: Duration::from_secs(15 * 60),
+            refresh_token_ttl: Duration::from_secs(60 * 60),
+        }
+    }
+
+    async fn app(pool: &PgPool) -> Router {
+        let state = AppState::for_tests(pool.clone(), &stub_redis().await, config()).await;
+        crate::app(Arc::new(state))
+    }
+
+    async fn create_user(pool: &PgPool, role: Role) -> String {
+        sqlx::query_scalar!(
+            "INSERT INTO users (email, password_hash, role) VALUES ($1, $2, $3) RETURNING id",
+            format!("{}@example.com", Uuid::new_v4().simple()),
+            "unused",
+            role.as_str(),
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap()
+        .to_string()
+    }
+
+    /// Fills in a route's path parameters.
+    fn uri_of(route: &str) -> String {
+        route
+            .replace("{id}", &Uuid::new_v4().to_string())
+            .replace("{market}", "YES_USDC")
+            .replace("{key_id}", "0123456789abcdef")
+            .replace("{user_id}", &Uuid::new_v4().to_string())
+    }
+
+    async fn call(app: &Router, user_id: &str, method: &str, uri: &str, body: &str) -> StatusCode {
+        let token = issue_access_token(&config(), user_id).unwrap();
+        let request = Request::builder()
+            .method(Method::from_bytes(method.as_bytes()).unwrap())
+            .uri(uri)
+            .header(AUTHORIZATION, format!("Bearer {}", token))
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    /// Calls `uri` signed with a key of `user_id` that has every scope.
+    async fn call_with_key(
+        app: &Router,
+        pool: &PgPool,
+        user_id: &str,
+        method: &str,
+        uri: &str,
+        body: &str,
+    ) -> StatusCode {
+        let key_id = Uuid::new_v4().simple().to_string();
+        let secret = derive_secret(&config().api_key_pepper, &key_id);
+        sqlx::query!(
+            "INSERT INTO api_keys (key_id, secret_hash, user_id, scopes) VALUES ($1, $2, $3, $4)",
+            key_id,
+            hash_secret(&secret),
+            user_id,
+            &[
+                String::from("read"),
+                String::from("trade"),
+                String::from("withdraw"),
+                String::from("admin"),
+            ],
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let timestamp = Utc::now().timestamp_millis();
+        let signature = request_mac(&secret, timestamp, method, uri, body.as_bytes())
+            .finalize()
+            .into_bytes();
+        let request = Request::builder()
+            .method(Method::from_bytes(method.as_bytes()).unwrap())
+            .uri(uri)
+            .header(API_KEY_HEADER, key_id)
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, hex::encode(signature))
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    /// `(action, outcome)` of each audit row written by `actor`, oldest first.
+    async fn audit_rows(pool: &PgPool, actor: &str) -> Vec<(String, String)> {
+        sqlx::query!(
+            "SELECT action, outcome FROM audit_log WHERE actor = $1 ORDER BY id",
+            actor
+        )
+        .fetch_all(pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| (row.action, row.outcome))
+        .collect()
+    }
+
+    fn denied_rows() -> Vec<(String, String)> {
+        PRIVILEGED_ROUTES
+            .iter()
+            .map(|(method, route, _)| (format!("{} {}", method, route), String::from("denied")))
+            .collect()
+    }
+
+    #[test]
+    fn roles_round_trip_through_their_names() {
+        for role in ROLES {
+            assert_eq!(Role::parse(role.as_str()), Some(role));
+        }
+        assert_eq!(Role::parse("superuser"), None);
+    }
+
+    #[test]
+    fn only_privileged_roles_get_the_admin_scope_in_a_session() {
+        for role in ROLES {
+            let privileged = matches!(role, Role::MarketOperator | Role::Admin);
+            assert_eq!(
+                role.session_scopes().contains(&Scope::Admin),
+                privileged,
+                "{}",
+                role.as_str()
+            );
+        }
+        assert_eq!(Role::Auditor.session_scopes(), &[Scope::Read]);
+    }
+
+    #[test]
+    fn roles_have_their_permissions_only() {
+        use Permission::*;
+
+        let allowed = |role| match role {
+            Role::Trader => vec![Trade],
+            Role::MarketOperator => vec![Trade, OperateMarkets, InspectMarkets],
+            Role::Admin => PERMISSIONS.to_vec(),
+            Role::Auditor => vec![InspectMarkets, Audit],
+        };
+        for role in ROLES {
+            for permission in PERMISSIONS {
+                assert_eq!(
+                    role.can(permission),
+                    allowed(role).contains(&permission),
+                    "{} {}",
+                    role.as_str(),
+                    permission.as_str()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn only_permissions_that_change_state_for_others_are_privileged() {
+        for permission in PERMISSIONS {
+            let privileged = matches!(
+                permission,
+                Permission::OperateMarkets | Permission::ManageFunds | Permission::ManageAccess
+            );
+            assert_eq!(
+                permission.privileged(),
+                privileged,
+                "{}",
+                permission.as_str()
+            );
+        }
+    }
+
+    #[sqlx::test]
+    async fn trader_sessions_are_refused_and_audited_on_privileged_routes(pool: PgPool) {
+        let app = app(&pool).await;
+        let trader = create_user(&pool, Role::Trader).await;
+
+        for (method, route, body) in PRIVILEGED_ROUTES {
+            let status = call(&app, &trader, method, &uri_of(route), body).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{} {}", method, route);
+        }
+        for uri in RESTRICTED_READS {
+            let status = call(&app, &trader, "GET", uri, "").await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{}", uri);
+        }
+
+        // Reads aren't privileged, so only the privileged routes show up
+        assert_eq!(audit_rows(&pool, &trader).await, denied_rows());
+    }
+
+    #[sqlx::test]
+    async fn trader_keys_with_the_admin_scope_are_refused_and_audited(pool: PgPool) {
+        let app = app(&pool).await;
+        let trader = create_user(&pool, Role::Trader).await;
+
+        for (method, route, body) in PRIVILEGED_ROUTES {
+            let status = call_with_key(&app, &pool, &trader, method, &uri_of(route), body).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{} {}", method, route);
+        }
+
+        assert_eq!(audit_rows(&pool, &trader).await, denied_rows());
+    }
+
+    #[sqlx::test]
+    async fn allowed_privileged_calls_are_audited_with_their_outcome(pool: PgPool) {
+        let app = app(&pool).await;
+        let admin = create_user(&pool, Role::Admin).await;
+        let operator = create_user(&pool, Role::MarketOperator).await;
+
+        let uri = format!("/api/v1/admin/users/{}/role", operator);
+        let status = call(&app, &admin, "PUT", &uri, r#"{"role":"auditor"}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        let missing = format!("/api/v1/admin/users/{}/role", Uuid::new_v4());
+        let status = call(&app, &admin, "PUT", &missing, r#"{"role":"auditor"}"#).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let role = sqlx::query_scalar!(
+            "SELECT role FROM users WHERE id = $1",
+            Uuid::parse_str(&operator).unwrap()
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(role, "auditor");
+
+        let action = String::from("PUT /api/v1/admin/users/{user_id}/role");
+        assert_eq!(
+            audit_rows(&pool, &admin).await,
+            vec![
+                (action.clone(), String::from("succeeded")),
+                (action, String::from("failed")),
+            ]
+        );
+    }
+
+    #[sqlx::test]
+    async fn market_operators_are_audited_outside_their_permission(pool: PgPool) {
+        let app = app(&pool).await;
+        let operator = create_user(&pool, Role::MarketOperator).await;
+
+        let status = call(
+            &app,
+            &operator,
+            "POST",
+            "/api/v1/admin/deposits",
+            r#"{"userId":"u","asset":"USDC","amount":"1000000","reference":"r"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        assert_eq!(
+            audit_rows(&pool, &operator).await,
+            vec![(
+                String::from("POST /api/v1/admin/deposits"),
+                String::from("denied")
+            )]
+        );
+    }
+}
@@ -0,0 +1,427 @@
+//! Browser sessions: argon2 password hashes, short-lived JWT access tokens
+//! and rotating refresh tokens.
+//!
+//! Refresh tokens are opaque and stored hashed. Each one can be exchanged
+//! once, for a new access token and the next refresh token of the same
+//! family. Presenting a token that was already exchanged means it leaked, so
+//! the whole family is revoked.
+
+use std::sync::LazyLock;
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use chrono::Utc;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::{hash_secret, AuthConfig, AuthError};
+
+/// Claims of an access token.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    /// User id
+    pub sub: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+/// What login, registration and refresh hand back to the client.
+#[derive(Debug, Serialize)]
+pub struct Session {
+    #[serde(rename = "userId")]
+    pub user_id: String,
+    #[serde(rename = "accessToken")]
+    pub access_token: String,
+    #[serde(rename = "tokenType")]
+    pub token_type: &'static str,
+    /// Seconds until the access token expires
+    #[serde(rename = "expiresIn")]
+    pub expires_in: u64,
+    #[serde(rename = "refreshToken")]
+    pub refresh_token: String,
+}
+
+pub fn hash_password(password: &str) -> Result<String, AuthError> {
+    let salt = SaltString::encode_b64(Uuid::new_v4().as_bytes())
+        .map_err(|e| AuthError::Internal(e.to_string()))?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AuthError::Internal(e.to_string()))
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+/// Spends as long as `verify_password` would on a real account and never
+/// matches, so a login for an unknown email can't be told apart by timing.
+pub fn reject_unknown_password(password: &str) -> bool {
+    static DUMMY_HASH: LazyLock<String> =
+        LazyLock::new(|| hash_password(&Uuid::new_v4().to_string()).unwrap_or_default());
+    verify_password(password, &DUMMY_HASH);
+    false
+}
+
+pub fn issue_access_token(config: &AuthConfig, user_id: &str) -> Result<String, AuthError> {
+    let now = Utc::now().timestamp();
+    let claims = Claims {
+        sub: user_id.to_string(),
+        iat: now,
+        exp: now + config.access_token_ttl.as_secs() as i64,
+    };
+    encode(
+        &Header::new(Algorithm::HS256),
+        &claims,
+        &EncodingKey::from_secret(config.jwt_secret.as_bytes()),
+    )
+    .map_err(|e| AuthError::Internal(e.to_string()))
+}
+
+/// User id of a valid, unexpired access token.
+pub fn verify_access_token(config: &AuthConfig, token: &str) -> Result<String, AuthError> {
+    decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(config.jwt_secret.as_bytes()),
+        &Validation::new(Algorithm::HS256),
+    )
+    .map(|data| data.claims.sub)
+    .map_err(|_| AuthError::Unauthorized(String::from("Invalid or expired access token")))
+}
+
+fn new_refresh_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Starts a new refresh token family for `user_id`.
+pub async fn start_session(
+    pool: &PgPool,
+    config: &AuthConfig,
+    user_id: Uuid,
+) -> Result<Session, AuthError> {
+    sqlx::query!(
+        "DELETE FROM refresh_tokens WHERE user_id = $1 AND expires_at < CURRENT_TIMESTAMP",
+        user_id
+    )
+    .execute(pool)
+    .await
+    .map_err(db_error)?;
+
+    let refresh_token = new_refresh_token();
+    sqlx::query!(
+        r#"
+        INSERT INTO refresh_tokens (token_hash, user_id, family_id, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        hash_secret(&refresh_token),
+        user_id,
+        Uuid::new_v4(),
+        OffsetDateTime::now_utc() + config.refresh_token_ttl,
+    )
+    .execute(pool)
+    .await
+    .map_err(db_error)?;
+
+    session(config, &user_id.to_string(), refresh_token)
+}
+
+/// Exchanges `refresh_token` for a new session in the same family.
+pub async fn refresh_session(
+    pool: &PgPool,
+    config: &AuthConfig,
+    refresh_token: &str,
+) -> Result<Session, AuthError> {
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    let token = sqlx::query!(
+        r#"
+        SELECT user_id, family_id, expires_at, used_at
+        FROM refresh_tokens
+        WHERE token_hash = $1
+        FOR UPDATE
+        "#,
+        hash_secret(refresh_token)
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(db_error)?
+    .ok_or_else(|| AuthError::Unauthorized(String::from("Unknown refresh token")))?;
+
+    if token.used_at.is_some() {
+        sqlx::query!(
+            "DELETE FROM refresh_tokens WHERE family_id = $1",
+            token.family_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+        return Err(AuthError::Unauthorized(String::from(
+            "Refresh token was already used, the session has been revoked",
+        )));
+    }
+    if token.expires_at < OffsetDateTime::now_utc() {
+        return Err(AuthError::Unauthorized(String::from(
+            "Refresh token has expired",
+        )));
+    }
+
+    let next_token = new_refresh_token();
+    sqlx::query!(
+        "UPDATE refresh_tokens SET used_at = CURRENT_TIMESTAMP WHERE token_hash = $1",
+        hash_secret(refresh_token)
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO refresh_tokens (token_hash, user_id, family_id, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        hash_secret(&next_token),
+        token.user_id,
+        token.family_id,
+        OffsetDateTime::now_utc() + config.refresh_token_ttl,
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+    tx.commit().await.map_err(db_error)?;
+
+    session(config, &token.user_id.to_string(), next_token)
+}
+
+/// Revokes the family `refresh_token` belongs to. Unknown tokens are ignored.
+pub async fn end_session(pool: &PgPool, refresh_token: &str) -> Result<(), AuthError> {
+    sqlx::query!(
+        r#"
+        DELETE FROM refresh_tokens
+        WHERE family_id = (SELECT family_id FROM refresh_tokens WHERE token_hash = $1)
+        "#,
+        hash_secret(refresh_token)
+    )
+    .execute(pool)
+    .await
+    .map_err(db_error)?;
+    Ok(())
+}
+
+fn session(
+    config: &AuthConfig,
+    user_id: &str,
+    refresh_token: String,
+) -> Result<Session, AuthError> {
+    Ok(Session {
+        user_id: user_id.to_string(),
+        access_token: issue_access_token(config, user_id)?,
+        token_type: "Bearer",
+        expires_in: config.access_token_ttl.as_secs(),
+        refresh_token,
+    })
+}
+
+fn db_error(e: sqlx::Error) -> AuthError {
+    AuthError::Internal(format!("DB error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn config() -> AuthConfig {
+        AuthConfig {
+            api_key_pepper: String::from("pepper"),
+            signature_window: Duration::from_secs(5),
+            jwt_secret: String::from("jwt secret"),
+            access_token_ttl: Duration::from_secs(15 * 60),
+            refresh_token_ttl: Duration::from_secs(60 * 60),
+        }
+    }
+
+    fn is_unauthorized<T>(result: Result<T, AuthError>) -> bool {
+        matches!(result, Err(AuthError::Unauthorized(_)))
+    }
+
+    async fn create_user(pool: &PgPool) -> Uuid {
+        sqlx::query_scalar!(
+            "INSERT INTO users (email, password_hash) VALUES ($1, $2) RETURNING id",
+            format!("{}@example.com", Uuid::new_v4().simple()),
+            "unused",
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    async fn family_size(pool: &PgPool, user_id: Uuid) -> i64 {
+        sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM refresh_tokens WHERE user_id = $1"#,
+            user_id
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn passwords_verify_against_their_own_hash_only() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("correct horse ", &hash));
+        assert!(!verify_password("", &hash));
+        assert!(!verify_password("correct horse", "not a hash"));
+        // Salted, so equal passwords don't give equal hashes
+        assert_ne!(hash, hash_password("correct horse").unwrap());
+    }
+
+    #[test]
+    fn unknown_accounts_reject_every_password() {
+        for password in ["", "correct horse", "password123"] {
+            assert!(!reject_unknown_password(password));
+        }
+    }
+
+    #[test]
+    fn access_tokens_carry_the_user_id() {
+        let config = config();
+        let token = issue_access_token(&config, "user-1").unwrap();
+        assert_eq!(verify_access_token(&config, &token).unwrap(), "user-1");
+    }
+
+    #[test]
+    fn rejects_access_tokens_signed_with_another_secret_or_tampered() {
+        let config = config();
+        let token = issue_access_token(&config, "user-1").unwrap();
+
+        let other = AuthConfig {
+            jwt_secret: String::from("another secret"),
+            ..config.clone()
+        };
+        assert!(is_unauthorized(verify_access_token(&other, &token)));
+
+        // Swapping the claims for another user's keeps the signature
+        let mut parts: Vec<String> = token.split('.').map(str::to_string).collect();
+        let forged = issue_access_token(&other, "user-2").unwrap();
+        parts[1] = forged.split('.').nth(1).unwrap().to_string();
+        assert!(is_unauthorized(verify_access_token(
+            &config,
+            &parts.join(".")
+        )));
+    }
+
+    #[test]
+    fn rejects_expired_access_tokens() {
+        let config = config();
+        // Past the default leeway of a minute
+        let now = Utc::now().timestamp();
+        let claims = Claims {
+            sub: String::from("user-1"),
+            iat: now - 20 * 60,
+            exp: now - 5 * 60,
+        };
+        let token = encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(config.jwt_secret.as_bytes()),
+        )
+        .unwrap();
+        assert!(is_unauthorized(verify_access_token(&config, &token)));
+    }
+
+    #[sqlx::test]
+    async fn refreshing_rotates_the_refresh_token(pool: PgPool) {
+        let config = config();
+        let user_id = create_user(&pool).await;
+        let first = start_session(&pool, &config, user_id).await.unwrap();
+        assert_eq!(first.user_id, user_id.to_string());
+
+        let second = refresh_session(&pool, &config, &first.refresh_token)
+            .await
+            .unwrap();
+        assert_eq!(second.user_id, user_id.to_string());
+        assert_ne!(second.refresh_token, first.refresh_token);
+        assert_eq!(
+            verify_access_token(&config, &second.access_token).unwrap(),
+            user_id.to_string()
+        );
+
+        let third = refresh_session(&pool, &config, &second.refresh_token)
+            .await
+            .unwrap();
+        assert_ne!(third.refresh_token, second.refresh_token);
+        assert_eq!(family_size(&pool, user_id).await, 3);
+    }
+
+    #[sqlx::test]
+    async fn reusing_a_refresh_token_revokes_its_family(pool: PgPool) {
+        let config = config();
+        let user_id = create_user(&pool).await;
+        let first = start_session(&pool, &config, user_id).await.unwrap();
+        // Another login of the same user, which the reuse mustn't touch
+        let other = start_session(&pool, &config, user_id).await.unwrap();
+        let second = refresh_session(&pool, &config, &first.refresh_token)
+            .await
+            .unwrap();
+
+        assert!(is_unauthorized(
+            refresh_session(&pool, &config, &first.refresh_token).await
+        ));
+        // The legitimate holder is logged out along with whoever replayed it
+        assert!(is_unauthorized(
+            refresh_session(&pool, &config, &second.refresh_token).await
+        ));
+        assert!(refresh_session(&pool, &config, &other.refresh_token)
+            .await
+            .is_ok());
+    }
+
+    #[sqlx::test]
+    async fn rejects_unknown_and_expired_refresh_tokens(pool: PgPool) {
+        let config = config();
+        assert!(is_unauthorized(
+            refresh_session(&pool, &config, &new_refresh_token()).await
+        ));
+
+        let user_id = create_user(&pool).await;
+        let expired = AuthConfig {
+            refresh_token_ttl: Duration::ZERO,
+            ..config.clone()
+        };
+        let session = start_session(&pool, &expired, user_id).await.unwrap();
+        assert!(is_unauthorized(
+            refresh_session(&pool, &config, &session.refresh_token).await
+        ));
+    }
+
+    #[sqlx::test]
+    async fn ending_a_session_revokes_its_family(pool: PgPool) {
+        let config = config();
+        let user_id = create_user(&pool).await;
+        let first = start_session(&pool, &config, user_id).await.unwrap();
+        let second = refresh_session(&pool, &config, &first.refresh_token)
+            .await
+            .unwrap();
+
+        end_session(&pool, &second.refresh_token).await.unwrap();
+        assert!(is_unauthorized(
+            refresh_session(&pool, &config, &second.refresh_token).await
+        ));
+        assert_eq!(family_size(&pool, user_id).await, 0);
+        // Unknown tokens are ignored
+        end_session(&pool, &new_refresh_token()).await.unwrap();
+    }
+}
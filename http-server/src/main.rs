@@ -1,25 +1,45 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use anyhow::Result;
+use auth::{
+    authorize_access_management, authorize_audit, authorize_funds_management,
+    authorize_market_inspection, authorize_market_operations, authorize_trade, require_admin,
+    require_read, require_trade, require_withdraw, API_KEY_HEADER, SIGNATURE_HEADER,
+    TIMESTAMP_HEADER,
+};
 use axum::{
-    http::{header::CONTENT_TYPE, HeaderValue, Method},
-    routing::{delete, get, post},
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        HeaderName, HeaderValue, Method,
+    },
+    middleware::from_fn_with_state,
+    routing::{delete, get, post, put},
     Router,
 };
 use dotenv::dotenv;
 use routes::{
-    cancel_order, create_market, create_order, get_all_markets, get_balances, get_depth,
-    get_klines, get_market_by_id, get_quote, get_trades, on_ramp, open_orders,
+    approve_withdrawal, cancel_order, check_invariants, close_listen_key, close_market,
+    create_api_key, create_listen_key, create_market, create_order, credit_deposit, faucet,
+    get_all_markets, get_amm_state, get_api_keys, get_audit_log, get_balances, get_book_ticker,
+    get_depth, get_health, get_klines, get_ledger, get_market_by_id, get_metrics, get_portfolio,
+    get_quote, get_tickers, get_trades, get_withdrawals, keepalive_listen_key, login, logout,
+    open_orders, refresh, register, reject_withdrawal, restore_market, revoke_api_key,
+    set_user_role, withdraw,
 };
 use state::AppState;
+use tokio::signal::unix::{signal, SignalKind};
 use tower_http::{cors::CorsLayer, trace::TraceLayer};
 use tracing::{error, info};
 
+mod auth;
 mod models;
 mod routes;
 mod services;
 mod state;
 
+/// Longest in-flight requests may take to finish once shutdown starts.
+const SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
@@ -27,17 +47,60 @@ async fn main() -> Result<()> {
 
     let app_state = Arc::new(AppState::new().await);
 
-    let app = Router::new()
+    // Histograms only drain their buckets during upkeep
+    let metrics = app_state.metrics.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(5));
+        loop {
+            interval.tick().await;
+            metrics.run_upkeep();
+        }
+    });
+
+    let app = app(app_state);
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
+
+    info!("Listening on {}", listener.local_addr()?);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .unwrap_or_else(|e| {
+            error!("Server error: {}", e);
+            std::process::exit(1);
+        });
+
+    info!("Shutdown complete");
+    Ok(())
+}
+
+fn app(app_state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/metrics", get(get_metrics))
         .nest(
             "/api/v1",
             Router::new()
                 .route("/healthcheck", get(|| async { "success: true" }))
+                .route("/health", get(get_health))
+                .nest(
+                    "/auth",
+                    Router::new()
+                        .route("/register", post(register))
+                        .route("/login", post(login))
+                        .route("/refresh", post(refresh))
+                        .route("/logout", post(logout)),
+                )
                 .nest(
                     "/order",
                     Router::new()
                         .route("/create", post(create_order))
                         .route("/cancel", delete(cancel_order))
-                        .route("/open", get(open_orders))
+                        .route_layer(from_fn_with_state(app_state.clone(), authorize_trade))
+                        .route_layer(from_fn_with_state(app_state.clone(), require_trade))
+                        .merge(
+                            Router::new()
+                                .route("/open", get(open_orders))
+                                .route_layer(from_fn_with_state(app_state.clone(), require_read)),
+                        )
                         .route("/quote", post(get_quote)),
                 )
                 .nest(
@@ -45,35 +108,149 @@ async fn main() -> Result<()> {
                     Router::new()
                         .route("/markets", get(get_all_markets))
                         .route("/{id}", get(get_market_by_id))
-                        .route("/create", post(create_market)),
+                        .merge(
+                            Router::new()
+                                .route("/amm", get(get_amm_state))
+                                .route_layer(from_fn_with_state(
+                                    app_state.clone(),
+                                    authorize_market_inspection,
+                                ))
+                                .route_layer(from_fn_with_state(app_state.clone(), require_read)),
+                        )
+                        .merge(
+                            Router::new()
+                                .route("/{id}/close", post(close_market))
+                                .route("/create", post(create_market))
+                                .route_layer(from_fn_with_state(
+                                    app_state.clone(),
+                                    authorize_market_operations,
+                                ))
+                                .route_layer(from_fn_with_state(app_state.clone(), require_admin)),
+                        ),
                 )
                 .route("/depth", get(get_depth))
-                .route("/create", post(create_market))
                 .route("/klines", get(get_klines))
                 .route("/trades", get(get_trades))
+                .route("/ticker/book", get(get_book_ticker))
+                .route("/tickers", get(get_tickers))
                 .nest(
                     "/user",
                     Router::new()
                         .route("/balances", get(get_balances))
-                        .route("/onramp", post(on_ramp)),
+                        .route("/portfolio", get(get_portfolio))
+                        .route("/ledger", get(get_ledger))
+                        .route("/withdrawals", get(get_withdrawals))
+                        .route(
+                            "/listenKey",
+                            post(create_listen_key)
+                                .put(keepalive_listen_key)
+                                .delete(close_listen_key),
+                        )
+                        .route_layer(from_fn_with_state(app_state.clone(), require_read))
+                        .merge(
+                            Router::new()
+                                .route("/faucet", post(faucet))
+                                .route_layer(from_fn_with_state(app_state.clone(), authorize_trade))
+                                .route_layer(from_fn_with_state(app_state.clone(), require_trade)),
+                        )
+                        .merge(
+                            Router::new()
+                                .route("/withdraw", post(withdraw))
+                                .route_layer(from_fn_with_state(app_state.clone(), authorize_trade))
+                                .route_layer(from_fn_with_state(
+                                    app_state.clone(),
+                                    require_withdraw,
+                                )),
+                        ),
+                )
+                .nest(
+                    "/withdrawal",
+                    Router::new()
+                        .route("/approve", post(approve_withdrawal))
+                        .route("/reject", post(reject_withdrawal))
+                        .route_layer(from_fn_with_state(
+                            app_state.clone(),
+                            authorize_funds_management,
+                        ))
+                        .route_layer(from_fn_with_state(app_state.clone(), require_admin)),
+                )
+                .nest(
+                    "/admin",
+                    Router::new()
+                        .route("/reconciliation", get(check_invariants))
+                        .route("/apiKeys", get(get_api_keys))
+                        .route("/auditLog", get(get_audit_log))
+                        .route_layer(from_fn_with_state(app_state.clone(), authorize_audit))
+                        .route_layer(from_fn_with_state(app_state.clone(), require_read))
+                        .merge(
+                            Router::new()
+                                .route("/markets/{market}/restore", post(restore_market))
+                                .route_layer(from_fn_with_state(
+                                    app_state.clone(),
+                                    authorize_market_operations,
+                                ))
+                                .route_layer(from_fn_with_state(app_state.clone(), require_admin)),
+                        )
+                        .merge(
+                            Router::new()
+                                .route("/deposits", post(credit_deposit))
+                                .route_layer(from_fn_with_state(
+                                    app_state.clone(),
+                                    authorize_funds_management,
+                                ))
+                                .route_layer(from_fn_with_state(app_state.clone(), require_admin)),
+                        )
+                        .merge(
+                            Router::new()
+                                .route("/apiKeys", post(create_api_key))
+                                .route("/apiKeys/{key_id}", delete(revoke_api_key))
+                                .route("/users/{user_id}/role", put(set_user_role))
+                                .route_layer(from_fn_with_state(
+                                    app_state.clone(),
+                                    authorize_access_management,
+                                ))
+                                .route_layer(from_fn_with_state(app_state.clone(), require_admin)),
+                        ),
                 ),
         )
         .layer(TraceLayer::new_for_http())
         .layer(
             CorsLayer::new()
                 .allow_origin("http://localhost:3000".parse::<HeaderValue>().unwrap())
-                .allow_headers([CONTENT_TYPE])
-                .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE]),
+                .allow_headers([
+                    CONTENT_TYPE,
+                    AUTHORIZATION,
+                    HeaderName::from_static(API_KEY_HEADER),
+                    HeaderName::from_static(TIMESTAMP_HEADER),
+                    HeaderName::from_static(SIGNATURE_HEADER),
+                ])
+                .allow_methods([
+                    Method::GET,
+                    Method::POST,
+                    Method::PATCH,
+                    Method::PUT,
+                    Method::DELETE,
+                ]),
         )
-        .with_state(app_state);
+        .with_state(app_state)
+}
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
+/// Resolves on Ctrl-C or SIGTERM, after which no new connections are accepted
+/// and in-flight requests get `SHUTDOWN_TIMEOUT_SECS` to finish.
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+    info!("Shutting down, finishing in-flight requests");
 
-    info!("Listening on {}", listener.local_addr()?);
-    axum::serve(listener, app).await.unwrap_or_else(|e| {
-        error!("Server error: {}", e);
+    tokio::spawn(async {
+        tokio::time::sleep(Duration::from_secs(SHUTDOWN_TIMEOUT_SECS)).await;
+        error!(
+            "Shutdown took longer than {}s, exiting",
+            SHUTDOWN_TIMEOUT_SECS
+        );
         std::process::exit(1);
     });
-
-    Ok(())
 }
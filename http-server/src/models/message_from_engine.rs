@@ -1,7 +1,8 @@
+use chrono::{DateTime, Utc};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
-use super::Order;
+use super::{Order, OrderSide};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -16,10 +17,32 @@ pub enum MessageFromEngine {
     Depth { payload: DepthPayload },
     #[serde(rename = "USER_BALANCES")]
     UserBalances { payload: UserBalancesPayload },
+    #[serde(rename = "USER_PORTFOLIO")]
+    UserPortfolio { payload: UserPortfolioPayload },
     #[serde(rename = "QUOTE")]
     Quote { payload: QuotePayload },
     #[serde(rename = "MARKET_CREATED")]
     MarketCreated { payload: MarketCreated },
+    #[serde(rename = "MARKET_CLOSED")]
+    MarketClosed { payload: MarketClosedPayload },
+    #[serde(rename = "WITHDRAWAL")]
+    Withdrawal { payload: Withdrawal },
+    #[serde(rename = "WITHDRAWALS")]
+    Withdrawals { payload: WithdrawalsPayload },
+    #[serde(rename = "BOOK_TICKER")]
+    BookTicker { payload: BookTickerPayload },
+    #[serde(rename = "TICKERS")]
+    Tickers { payload: TickersPayload },
+    #[serde(rename = "AMM_STATE")]
+    AmmState { payload: AmmStatePayload },
+    #[serde(rename = "RECONCILIATION")]
+    Reconciliation { payload: ReconciliationReport },
+    #[serde(rename = "HEALTH")]
+    Health { payload: HealthPayload },
+    #[serde(rename = "MARKET_RESTORED")]
+    MarketRestored { payload: MarketRestoredPayload },
+    #[serde(rename = "REJECTED")]
+    Rejected { payload: RejectedPayload },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -34,6 +57,12 @@ pub struct OrderCancelledPayload {
     pub message: Option<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RejectedPayload {
+    pub code: String,
+    pub message: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OpenOrdersPayload {
     pub open_orders: Vec<Order>,
@@ -43,6 +72,10 @@ pub struct OpenOrdersPayload {
 pub struct DepthPayload {
     pub bids: Vec<[String; 2]>,
     pub asks: Vec<[String; 2]>,
+    pub best_bid: Option<Decimal>,
+    pub best_ask: Option<Decimal>,
+    pub spread: Option<Decimal>,
+    pub sequence: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -57,15 +90,159 @@ pub struct Balance {
     pub locked_balance: Decimal,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PositionSummary {
+    pub market: String,
+    pub net_quantity: Decimal,
+    pub avg_entry_price: Decimal,
+    pub mark_price: Option<Decimal>,
+    pub realised_pnl: Decimal,
+    pub unrealised_pnl: Decimal,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserPortfolioPayload {
+    pub positions: Vec<PositionSummary>,
+    pub total_realised_pnl: Decimal,
+    pub total_unrealised_pnl: Decimal,
+}
+
 // Quote
 #[derive(Debug, Serialize, Deserialize)]
 pub struct QuotePayload {
+    pub side: OrderSide,
     pub avg_price: Decimal,
     pub quantity: Decimal,
     pub total_cost: Decimal,
+    pub fully_filled: bool,
+    pub worst_price: Option<Decimal>,
+    pub mid_price: Option<Decimal>,
+    pub price_impact: Option<Decimal>,
+    pub levels_consumed: usize,
+    pub estimated_fee: Decimal,
+    pub fee_asset: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MarketCreated {
     pub message: Option<String>,
 }
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MarketClosedPayload {
+    pub market: String,
+    pub end_time: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Withdrawal {
+    pub id: String,
+    pub user_id: String,
+    pub asset: String,
+    pub amount: Decimal,
+    pub destination: String,
+    pub status: String,
+    pub tx_signature: Option<String>,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WithdrawalsPayload {
+    pub withdrawals: Vec<Withdrawal>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AmmStatePayload {
+    pub market: String,
+    pub user_id: String,
+    pub subsidy: Decimal,
+    pub liquidity: Decimal,
+    pub price: Decimal,
+    pub net_sold: Decimal,
+    pub quote_balance: Decimal,
+    pub worst_case_loss: Decimal,
+    pub subsidy_remaining: Decimal,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BookTickerPayload {
+    pub tickers: Vec<BookTicker>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BookTicker {
+    pub market: String,
+    pub bid_price: Option<Decimal>,
+    pub bid_quantity: Option<Decimal>,
+    pub ask_price: Option<Decimal>,
+    pub ask_quantity: Option<Decimal>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TickersPayload {
+    pub tickers: Vec<Ticker24h>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Ticker24h {
+    pub market: String,
+    pub open: Option<Decimal>,
+    pub high: Option<Decimal>,
+    pub low: Option<Decimal>,
+    pub last: Option<Decimal>,
+    pub volume: Decimal,
+    pub quote_volume: Decimal,
+    pub trade_count: u64,
+    pub price_change: Option<Decimal>,
+    pub price_change_percent: Option<Decimal>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReconciliationReport {
+    pub time: DateTime<Utc>,
+    pub markets_checked: usize,
+    pub users_checked: usize,
+    pub assets: Vec<AssetReconciliation>,
+    pub violations: Vec<InvariantViolation>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AssetReconciliation {
+    pub asset: String,
+    pub total_balance: Decimal,
+    pub total_locked: Decimal,
+    pub net_external: Decimal,
+    pub difference: Decimal,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InvariantViolation {
+    pub kind: String,
+    pub user_id: Option<String>,
+    pub asset: String,
+    pub expected: Option<Decimal>,
+    pub actual: Option<Decimal>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MarketHealth {
+    pub market: String,
+    pub status: String,
+    pub reason: Option<String>,
+    pub halted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HealthPayload {
+    pub healthy: bool,
+    pub markets: Vec<MarketHealth>,
+    pub unsettled_withdrawals: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MarketRestoredPayload {
+    pub market: String,
+    pub orders: usize,
+}
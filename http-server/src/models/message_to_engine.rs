@@ -19,15 +19,42 @@ pub enum MessageToEngine {
     GetOpenOrders { data: GetOpenOrdersPayload },
     #[serde(rename = "GET_USER_BALANCES")]
     GetUserBalances { data: GetUserBalancesPayload },
-    #[serde(rename = "ON_RAMP_USER")]
-    OnRampUser { data: OnRampPayload },
+    #[serde(rename = "GET_USER_PORTFOLIO")]
+    GetUserPortfolio { data: GetUserPortfolioPayload },
+    #[serde(rename = "DEPOSIT")]
+    Deposit { data: DepositPayload },
+    #[serde(rename = "WITHDRAW")]
+    Withdraw { data: WithdrawPayload },
+    #[serde(rename = "APPROVE_WITHDRAWAL")]
+    ApproveWithdrawal { data: WithdrawalActionPayload },
+    #[serde(rename = "REJECT_WITHDRAWAL")]
+    RejectWithdrawal { data: WithdrawalActionPayload },
+    #[serde(rename = "GET_WITHDRAWALS")]
+    GetWithdrawals { data: GetWithdrawalsPayload },
+    #[serde(rename = "FAUCET")]
+    Faucet { data: FaucetPayload },
     #[serde(rename = "CREATE_MARKET")]
     CreateMarket { data: CreateMarketPayload },
+    #[serde(rename = "CLOSE_MARKET")]
+    CloseMarket { data: CloseMarketPayload },
+    #[serde(rename = "GET_AMM_STATE")]
+    GetAmmState { data: GetAmmStatePayload },
+    #[serde(rename = "GET_BOOK_TICKER")]
+    GetBookTicker { data: GetBookTickerPayload },
+    #[serde(rename = "GET_TICKERS")]
+    GetTickers { data: GetTickersPayload },
+    #[serde(rename = "CHECK_INVARIANTS")]
+    CheckInvariants,
+    #[serde(rename = "GET_HEALTH")]
+    GetHealth,
+    #[serde(rename = "RESTORE_MARKET")]
+    RestoreMarket { data: RestoreMarketPayload },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateOrderPayload {
-    #[serde(rename = "userId")]
+    /// Set from the authenticated caller, never read from the request
+    #[serde(rename = "userId", skip_deserializing)]
     pub user_id: String,
     pub market: String,
     pub price: Decimal,
@@ -39,7 +66,8 @@ pub struct CreateOrderPayload {
 pub struct CancelOrderPayload {
     #[serde(rename = "orderId")]
     pub order_id: String,
-    #[serde(rename = "userId")]
+    /// Set from the authenticated caller, never read from the request
+    #[serde(rename = "userId", skip_deserializing)]
     pub user_id: String,
     pub market: String,
 }
@@ -47,6 +75,10 @@ pub struct CancelOrderPayload {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GetDepthPayload {
     pub market: String,
+    /// Maximum levels per side
+    pub limit: Option<usize>,
+    /// Price bucket to aggregate levels into, e.g. 0.01 or 0.05
+    pub bucket: Option<Decimal>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -54,6 +86,11 @@ pub struct GetTradesPayload {
     pub market: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct GetLedgerPayload {
+    pub limit: Option<i64>,
+}
+
 #[derive(Deserialize)]
 pub struct GetKlinePayload {
     pub market: String,
@@ -70,12 +107,17 @@ pub struct GetKlinePayload {
 pub struct GetQuotePayload {
     pub market: String,
     pub side: OrderSide,
-    pub quantity: Decimal,
+    /// Base units to buy or sell
+    pub quantity: Option<Decimal>,
+    /// Quote to spend (bid) or receive (ask), instead of `quantity`
+    #[serde(rename = "quoteAmount")]
+    pub quote_amount: Option<Decimal>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GetOpenOrdersPayload {
-    #[serde(rename = "userId")]
+    /// Set from the authenticated caller, never read from the request
+    #[serde(rename = "userId", skip_deserializing)]
     pub user_id: String,
     pub market: String,
 }
@@ -87,11 +129,54 @@ pub struct GetUserBalancesPayload {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct OnRampPayload {
+pub struct GetUserPortfolioPayload {
+    #[serde(rename = "userId")]
+    pub user_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DepositPayload {
+    /// Account credited, chosen by the operator confirming the deposit
+    #[serde(rename = "userId")]
+    pub user_id: String,
+    pub asset: String,
+    pub amount: Decimal,
+    /// Chain transaction the deposit was observed in. The engine credits each
+    /// one once, so a retried request can't credit it twice
+    pub reference: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WithdrawPayload {
+    /// Set from the authenticated caller, never read from the request
+    #[serde(rename = "userId", skip_deserializing)]
+    pub user_id: String,
+    pub asset: String,
+    pub amount: Decimal,
+    pub destination: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WithdrawalActionPayload {
+    #[serde(rename = "withdrawalId")]
+    pub withdrawal_id: String,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetWithdrawalsPayload {
     #[serde(rename = "userId")]
     pub user_id: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FaucetPayload {
+    /// Set from the authenticated caller, never read from the request
+    #[serde(rename = "userId", skip_deserializing)]
+    pub user_id: String,
+    pub asset: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum OrderSide {
     Bid,
@@ -107,12 +192,15 @@ pub struct CreateMarketPayload {
     pub start_time: DateTime<Utc>,
     pub end_time: DateTime<Utc>,
     pub status: Status,
+    /// Seeds the market with an LMSR AMM funded with this much quote
+    pub amm_subsidy: Option<Decimal>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Status {
     Incoming,
     Ongoing,
+    Closed,
 }
 
 impl fmt::Display for Status {
@@ -120,7 +208,35 @@ impl fmt::Display for Status {
         let s = match self {
             Status::Incoming => "incoming",
             Status::Ongoing => "ongoing",
+            Status::Closed => "closed",
         };
         write!(f, "{}", s)
     }
 }
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CloseMarketPayload {
+    pub market: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetAmmStatePayload {
+    pub market: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetBookTickerPayload {
+    /// All markets when unset
+    pub market: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetTickersPayload {
+    /// All markets when unset
+    pub market: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RestoreMarketPayload {
+    pub market: String,
+}
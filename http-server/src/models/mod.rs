@@ -29,3 +29,9 @@ pub struct Market {
     pub end_time: OffsetDateTime,
     pub status: Status,
 }
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListenKeyPayload {
+    #[serde(rename = "listenKey")]
+    pub listen_key: String,
+}
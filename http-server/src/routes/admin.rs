@@ -0,0 +1,148 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use time::format_description::well_known::Rfc3339;
+use uuid::Uuid;
+
+use crate::{
+    auth::{AuthError, AuthUser, Role},
+    models::{DepositPayload, MessageToEngine, RestoreMarketPayload},
+    services::EngineRequestError,
+    state::AppState,
+};
+
+/// Runs the engine's balance invariant check and returns the report.
+pub async fn check_invariants(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Value>, EngineRequestError> {
+    let response = state
+        .redis_manager
+        .send_and_wait(MessageToEngine::CheckInvariants)
+        .await?;
+    Ok(Json(json!(response)))
+}
+
+/// Restarts a halted market with the resting orders of its halted worker.
+pub async fn restore_market(
+    State(state): State<Arc<AppState>>,
+    Path(market): Path<String>,
+) -> Result<Json<Value>, EngineRequestError> {
+    let message = MessageToEngine::RestoreMarket {
+        data: RestoreMarketPayload { market },
+    };
+
+    let response = state.redis_manager.send_and_wait(message).await?;
+    Ok(Json(json!(response)))
+}
+
+/// Credits a deposit an operator confirmed off-chain to `userId`.
+pub async fn credit_deposit(
+    State(state): State<Arc<AppState>>,
+    Json(params): Json<DepositPayload>,
+) -> Result<Json<Value>, EngineRequestError> {
+    let message = MessageToEngine::Deposit { data: params };
+
+    let response = state.redis_manager.send_and_wait(message).await?;
+    Ok(Json(json!(response)))
+}
+
+/// Engine health, including every halted market.
+pub async fn get_health(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Value>, EngineRequestError> {
+    let response = state
+        .redis_manager
+        .send_and_wait(MessageToEngine::GetHealth)
+        .await?;
+    Ok(Json(json!(response)))
+}
+
+/// Most audit log rows a single request returns.
+const MAX_AUDIT_LOG_LIMIT: i64 = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct SetRolePayload {
+    pub role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetAuditLogPayload {
+    pub actor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Gives a user a role. Admins can't change their own, so there is always
+/// one left.
+pub async fn set_user_role(
+    State(state): State<Arc<AppState>>,
+    admin: AuthUser,
+    Path(user_id): Path<Uuid>,
+    Json(params): Json<SetRolePayload>,
+) -> Result<Json<Value>, AuthError> {
+    if admin.user_id == user_id.to_string() {
+        return Err(AuthError::BadRequest(String::from(
+            "Admins can't change their own role",
+        )));
+    }
+
+    let updated = sqlx::query!(
+        "UPDATE users SET role = $1 WHERE id = $2",
+        params.role.as_str(),
+        user_id
+    )
+    .execute(&*state.db_pool)
+    .await
+    .map_err(|e| AuthError::Internal(format!("DB error: {}", e)))?;
+
+    if updated.rows_affected() == 0 {
+        return Ok(Json(json!({ "error": "User not found" })));
+    }
+    Ok(Json(json!({ "userId": user_id, "role": params.role })))
+}
+
+/// Privileged requests, newest first.
+pub async fn get_audit_log(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<GetAuditLogPayload>,
+) -> Json<Value> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, actor, role, action, params, outcome, detail, created_at
+        FROM audit_log
+        WHERE $1::TEXT IS NULL OR actor = $1
+        ORDER BY id DESC
+        LIMIT $2
+        "#,
+        params.actor,
+        params.limit.unwrap_or(100).clamp(1, MAX_AUDIT_LOG_LIMIT)
+    )
+    .fetch_all(&*state.db_pool)
+    .await;
+
+    match rows {
+        Ok(records) => {
+            let entries: Vec<Value> = records
+                .into_iter()
+                .map(|r| {
+                    json!({
+                        "id": r.id,
+                        "actor": r.actor,
+                        "role": r.role,
+                        "action": r.action,
+                        "params": r.params,
+                        "outcome": r.outcome,
+                        "detail": r.detail,
+                        "createdAt": r.created_at.format(&Rfc3339).ok(),
+                    })
+                })
+                .collect();
+            Json(json!(entries))
+        }
+        Err(e) => Json(json!({ "error": format!("DB error: {}", e) })),
+    }
+}
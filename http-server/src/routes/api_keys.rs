@@ -0,0 +1,130 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use time::format_description::well_known::Rfc3339;
+
+use crate::{
+    auth::{derive_secret, hash_secret, new_key_id, Scope},
+    state::AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyPayload {
+    #[serde(rename = "userId")]
+    pub user_id: String,
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListApiKeysPayload {
+    #[serde(rename = "userId")]
+    pub user_id: String,
+}
+
+/// Issues an API key for a user. The secret is only ever shown here.
+pub async fn create_api_key(
+    State(state): State<Arc<AppState>>,
+    Json(params): Json<CreateApiKeyPayload>,
+) -> Json<Value> {
+    if params.scopes.is_empty() {
+        return Json(json!({ "error": "An API key needs at least one scope" }));
+    }
+
+    let key_id = new_key_id();
+    let secret = derive_secret(&state.auth.api_key_pepper, &key_id);
+    let mut scopes: Vec<String> = params
+        .scopes
+        .iter()
+        .map(|s| s.as_str().to_string())
+        .collect();
+    scopes.sort();
+    scopes.dedup();
+
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO api_keys (key_id, secret_hash, user_id, scopes)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        key_id,
+        hash_secret(&secret),
+        params.user_id,
+        &scopes,
+    )
+    .execute(&*state.db_pool)
+    .await;
+
+    match inserted {
+        Ok(_) => Json(json!({
+            "keyId": key_id,
+            "secret": secret,
+            "userId": params.user_id,
+            "scopes": scopes,
+        })),
+        Err(e) => Json(json!({ "error": format!("DB error: {}", e) })),
+    }
+}
+
+/// Keys of a user, without their secrets.
+pub async fn get_api_keys(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ListApiKeysPayload>,
+) -> Json<Value> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT key_id, scopes, created_at, revoked_at
+        FROM api_keys
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        params.user_id
+    )
+    .fetch_all(&*state.db_pool)
+    .await;
+
+    match rows {
+        Ok(records) => {
+            let keys: Vec<Value> = records
+                .into_iter()
+                .map(|r| {
+                    json!({
+                        "keyId": r.key_id,
+                        "scopes": r.scopes,
+                        "createdAt": r.created_at.format(&Rfc3339).ok(),
+                        "revokedAt": r.revoked_at.and_then(|t| t.format(&Rfc3339).ok()),
+                    })
+                })
+                .collect();
+            Json(json!(keys))
+        }
+        Err(e) => Json(json!({ "error": format!("DB error: {}", e) })),
+    }
+}
+
+pub async fn revoke_api_key(
+    State(state): State<Arc<AppState>>,
+    Path(key_id): Path<String>,
+) -> Json<Value> {
+    let revoked = sqlx::query!(
+        r#"
+        UPDATE api_keys
+        SET revoked_at = CURRENT_TIMESTAMP
+        WHERE key_id = $1 AND revoked_at IS NULL
+        "#,
+        key_id
+    )
+    .execute(&*state.db_pool)
+    .await;
+
+    match revoked {
+        Ok(result) if result.rows_affected() == 0 => {
+            Json(json!({ "error": "API key not found or already revoked" }))
+        }
+        Ok(_) => Json(json!({ "success": true })),
+        Err(e) => Json(json!({ "error": format!("DB error: {}", e) })),
+    }
+}
@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use axum::{extract::State, Json};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    auth::{
+        end_session, hash_password, refresh_session, reject_unknown_password, start_session,
+        verify_password, AuthError, Session,
+    },
+    state::AppState,
+};
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+#[derive(Debug, Deserialize)]
+pub struct CredentialsPayload {
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshTokenPayload {
+    #[serde(rename = "refreshToken")]
+    pub refresh_token: String,
+}
+
+/// Creates a user and logs them in.
+pub async fn register(
+    State(state): State<Arc<AppState>>,
+    Json(params): Json<CredentialsPayload>,
+) -> Result<Json<Session>, AuthError> {
+    let email = params.email.trim().to_lowercase();
+    if !email.contains('@') {
+        return Err(AuthError::BadRequest(String::from("Invalid email")));
+    }
+    if params.password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(AuthError::BadRequest(format!(
+            "Password must be at least {} characters",
+            MIN_PASSWORD_LENGTH
+        )));
+    }
+
+    let user = sqlx::query!(
+        "INSERT INTO users (email, password_hash) VALUES ($1, $2) RETURNING id",
+        email,
+        hash_password(&params.password)?,
+    )
+    .fetch_one(&*state.db_pool)
+    .await
+    .map_err(|e| match e.as_database_error() {
+        Some(db) if db.is_unique_violation() => {
+            AuthError::BadRequest(String::from("Email is already registered"))
+        }
+        _ => AuthError::Internal(format!("DB error: {}", e)),
+    })?;
+
+    start_session(&state.db_pool, &state.auth, user.id)
+        .await
+        .map(Json)
+}
+
+pub async fn login(
+    State(state): State<Arc<AppState>>,
+    Json(params): Json<CredentialsPayload>,
+) -> Result<Json<Session>, AuthError> {
+    let user = sqlx::query!(
+        "SELECT id, password_hash FROM users WHERE LOWER(email) = $1",
+        params.email.trim().to_lowercase()
+    )
+    .fetch_optional(&*state.db_pool)
+    .await
+    .map_err(|e| AuthError::Internal(format!("DB error: {}", e)))?;
+
+    // An unknown email still pays for a hash check, so timing doesn't tell
+    // which emails have accounts
+    let verified = match &user {
+        Some(user) => verify_password(&params.password, &user.password_hash),
+        None => reject_unknown_password(&params.password),
+    };
+    match user {
+        Some(user) if verified => start_session(&state.db_pool, &state.auth, user.id)
+            .await
+            .map(Json),
+        _ => Err(AuthError::Unauthorized(String::from(
+            "Invalid email or password",
+        ))),
+    }
+}
+
+/// Swaps a refresh token for a new access token and the next refresh token.
+pub async fn refresh(
+    State(state): State<Arc<AppState>>,
+    Json(params): Json<RefreshTokenPayload>,
+) -> Result<Json<Session>, AuthError> {
+    refresh_session(&state.db_pool, &state.auth, &params.refresh_token)
+        .await
+        .map(Json)
+}
+
+/// Revokes the session. Access tokens already issued stay valid until they
+/// expire.
+pub async fn logout(
+    State(state): State<Arc<AppState>>,
+    Json(params): Json<RefreshTokenPayload>,
+) -> Result<Json<Value>, AuthError> {
+    end_session(&state.db_pool, &params.refresh_token).await?;
+    Ok(Json(json!({ "success": true })))
+}
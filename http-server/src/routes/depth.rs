@@ -8,23 +8,16 @@ use serde_json::{json, Value};
 
 use crate::{
     models::{GetDepthPayload, MessageToEngine},
+    services::EngineRequestError,
     state::AppState,
 };
 
 pub async fn get_depth(
     State(state): State<Arc<AppState>>,
     Query(params): Query<GetDepthPayload>,
-) -> Json<Value> {
-    let message = MessageToEngine::GetDepth {
-        data: GetDepthPayload {
-            market: params.market,
-        },
-    };
+) -> Result<Json<Value>, EngineRequestError> {
+    let message = MessageToEngine::GetDepth { data: params };
 
-    match state.redis_manager.send_and_wait(message) {
-        Ok(response) => Json(json!(response)),
-        Err(e) => Json(json!({
-            "error": format!("Redis error: {}", e)
-        })),
-    }
+    let response = state.redis_manager.send_and_wait(message).await?;
+    Ok(Json(json!(response)))
 }
@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use rust_decimal::Decimal;
+use serde_json::{json, Value};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{auth::AuthUser, models::GetLedgerPayload, state::AppState};
+
+const DEFAULT_LEDGER_LIMIT: i64 = 100;
+const MAX_LEDGER_LIMIT: i64 = 1000;
+
+struct LedgerEntryRow {
+    id: String,
+    transaction_id: String,
+    asset: String,
+    account: String,
+    direction: String,
+    amount: Decimal,
+    reason: String,
+    reference_id: String,
+    time: OffsetDateTime,
+}
+
+pub async fn get_ledger(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Query(params): Query<GetLedgerPayload>,
+) -> Json<Value> {
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_LEDGER_LIMIT)
+        .clamp(1, MAX_LEDGER_LIMIT);
+
+    let rows = sqlx::query_as!(
+        LedgerEntryRow,
+        r#"SELECT id, transaction_id, asset, account, direction, amount, reason, reference_id, "time"
+             FROM ledger_entries
+            WHERE user_id = $1
+            ORDER BY "time" DESC, transaction_id
+            LIMIT $2"#,
+        user.user_id,
+        limit
+    )
+    .fetch_all(&*state.db_pool)
+    .await;
+
+    match rows {
+        Ok(records) => {
+            let data: Vec<Value> = records
+                .into_iter()
+                .map(|r| {
+                    json!({
+                        "id":             r.id,
+                        "transaction_id": r.transaction_id,
+                        "asset":          r.asset,
+                        "account":        r.account,
+                        "direction":      r.direction,
+                        "amount":         r.amount,
+                        "reason":         r.reason,
+                        "reference_id":   r.reference_id,
+                        "time":           r.time.format(&Rfc3339).ok(),
+                    })
+                })
+                .collect();
+
+            Json(json!({
+                "success": true,
+                "data":    data
+            }))
+        }
+        Err(e) => {
+            tracing::error!("DB error fetching ledger: {:?}", e);
+            Json(json!({
+                "success": false,
+                "error":   "Failed to query ledger entries"
+            }))
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     Json,
 };
 use serde_json::{json, Value};
@@ -9,27 +9,32 @@ use time::OffsetDateTime;
 use uuid::Uuid;
 
 use crate::{
-    models::{CreateMarketPayload, Market, MessageToEngine, Status},
+    models::{
+        CloseMarketPayload, CreateMarketPayload, GetAmmStatePayload, Market, MessageFromEngine,
+        MessageToEngine, Status,
+    },
+    services::EngineRequestError,
     state::AppState,
 };
 
 pub async fn create_market(
     State(state): State<Arc<AppState>>,
     Json(market_data): Json<CreateMarketPayload>,
-) -> Json<Value> {
+) -> Result<Json<Value>, EngineRequestError> {
     let redis_msg = MessageToEngine::CreateMarket {
         data: market_data.clone(),
     };
-    if let Err(e) = state.redis_manager.send_and_wait(redis_msg) {
-        return Json(json!({ "error": format!("Redis error: {}", e) }));
+    let response = state.redis_manager.send_and_wait(redis_msg).await?;
+    if let MessageFromEngine::Rejected { .. } = response {
+        return Ok(Json(json!(response)));
     }
 
     let db_msg = sqlx::query!(
         r#"
         INSERT INTO markets
-          (name, description, base_asset, quote_asset, start_time, end_time, status)
+          (name, description, base_asset, quote_asset, start_time, end_time, status, amm_subsidy)
         VALUES
-          ($1, $2, $3, $4, $5, $6, $7)
+          ($1, $2, $3, $4, $5, $6, $7, $8)
         "#,
         market_data.name,
         market_data.description,
@@ -38,17 +43,18 @@ pub async fn create_market(
         OffsetDateTime::from_unix_timestamp(market_data.start_time.timestamp()).unwrap(),
         OffsetDateTime::from_unix_timestamp(market_data.end_time.timestamp()).unwrap(),
         market_data.status.to_string(),
+        market_data.amm_subsidy,
     )
     .execute(&*state.db_pool)
     .await;
 
-    match db_msg {
+    Ok(match db_msg {
         Ok(_) => Json(json!({
             "success": true,
             "message": "Market created successfully"
         })),
         Err(e) => Json(json!({ "error": format!("DB error: {}", e) })),
-    }
+    })
 }
 
 pub async fn get_all_markets(State(state): State<Arc<AppState>>) -> Json<Value> {
@@ -73,8 +79,8 @@ pub async fn get_all_markets(State(state): State<Arc<AppState>>) -> Json<Value>
                     description: r.description,
                     base_asset: r.base_asset,
                     quote_asset: r.quote_asset,
-                    start_time: r.start_time.into(),
-                    end_time: r.end_time.into(),
+                    start_time: r.start_time,
+                    end_time: r.end_time,
                     status: string_to_status(&r.status),
                 })
                 .collect();
@@ -109,8 +115,8 @@ pub async fn get_market_by_id(
                 description: r.description,
                 base_asset: r.base_asset,
                 quote_asset: r.quote_asset,
-                start_time: r.start_time.into(),
-                end_time: r.end_time.into(),
+                start_time: r.start_time,
+                end_time: r.end_time,
                 status: string_to_status(&r.status),
             };
             Json(json!(market))
@@ -120,9 +126,53 @@ pub async fn get_market_by_id(
     }
 }
 
+/// Marks a market closed in the table and tells the engine to stop taking
+/// orders for it. The engine also picks the change up on its next reconcile.
+pub async fn close_market(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Value>, EngineRequestError> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE markets
+        SET status = $1, updated_at = CURRENT_TIMESTAMP
+        WHERE id = $2
+        RETURNING base_asset, quote_asset
+        "#,
+        Status::Closed.to_string(),
+        id
+    )
+    .fetch_one(&*state.db_pool)
+    .await;
+
+    let market = match row {
+        Ok(r) => format!("{}_{}", r.base_asset, r.quote_asset),
+        Err(sqlx::Error::RowNotFound) => return Ok(Json(json!({ "error": "Market not found" }))),
+        Err(e) => return Ok(Json(json!({ "error": format!("DB error: {}", e) }))),
+    };
+
+    let message = MessageToEngine::CloseMarket {
+        data: CloseMarketPayload { market },
+    };
+
+    let response = state.redis_manager.send_and_wait(message).await?;
+    Ok(Json(json!(response)))
+}
+
+pub async fn get_amm_state(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<GetAmmStatePayload>,
+) -> Result<Json<Value>, EngineRequestError> {
+    let message = MessageToEngine::GetAmmState { data: params };
+
+    let response = state.redis_manager.send_and_wait(message).await?;
+    Ok(Json(json!(response)))
+}
+
 fn string_to_status(status_str: &Option<String>) -> Status {
-    match status_str {
-        Some(s) if s == "Ongoing" => Status::Ongoing,
+    match status_str.as_deref().map(str::to_lowercase).as_deref() {
+        Some("ongoing") => Status::Ongoing,
+        Some("closed") => Status::Closed,
         _ => Status::Incoming, // Default to Incoming for any other value or None
     }
 }
@@ -0,0 +1,10 @@
+use std::sync::Arc;
+
+use axum::extract::State;
+
+use crate::state::AppState;
+
+/// Prometheus text exposition of everything http-server records.
+pub async fn get_metrics(State(state): State<Arc<AppState>>) -> String {
+    state.metrics.render()
+}
@@ -15,3 +15,24 @@ pub use trades::*;
 
 pub mod market;
 pub use market::*;
+
+pub mod ledger;
+pub use ledger::*;
+
+pub mod ticker;
+pub use ticker::*;
+
+pub mod user_stream;
+pub use user_stream::*;
+
+pub mod admin;
+pub use admin::*;
+
+pub mod metrics;
+pub use metrics::*;
+
+pub mod api_keys;
+pub use api_keys::*;
+
+pub mod auth;
+pub use auth::*;
@@ -7,65 +7,57 @@ use axum::{
 use serde_json::{json, Value};
 
 use crate::{
+    auth::AuthUser,
     models::{
         CancelOrderPayload, CreateOrderPayload, GetOpenOrdersPayload, GetQuotePayload,
         MessageToEngine,
     },
+    services::EngineRequestError,
     state::AppState,
 };
 
 pub async fn create_order(
     State(state): State<Arc<AppState>>,
-    Json(order_data): Json<CreateOrderPayload>,
-) -> Json<Value> {
+    user: AuthUser,
+    Json(mut order_data): Json<CreateOrderPayload>,
+) -> Result<Json<Value>, EngineRequestError> {
+    order_data.user_id = user.user_id;
     let message = MessageToEngine::CreateOrder { data: order_data };
 
-    match state.redis_manager.send_and_wait(message) {
-        Ok(response) => Json(json!(response)),
-        Err(e) => Json(json!({
-            "error": format!("Redis error: {}", e)
-        })),
-    }
+    let response = state.redis_manager.send_and_wait(message).await?;
+    Ok(Json(json!(response)))
 }
 
 pub async fn cancel_order(
     State(state): State<Arc<AppState>>,
-    Json(order_data): Json<CancelOrderPayload>,
-) -> Json<Value> {
+    user: AuthUser,
+    Json(mut order_data): Json<CancelOrderPayload>,
+) -> Result<Json<Value>, EngineRequestError> {
+    order_data.user_id = user.user_id;
     let message = MessageToEngine::CancelOrder { data: order_data };
 
-    match state.redis_manager.send_and_wait(message) {
-        Ok(response) => Json(json!(response)),
-        Err(e) => Json(json!({
-            "error": format!("Redis error: {}", e)
-        })),
-    }
+    let response = state.redis_manager.send_and_wait(message).await?;
+    Ok(Json(json!(response)))
 }
 
 pub async fn get_quote(
     State(state): State<Arc<AppState>>,
     Json(order_data): Json<GetQuotePayload>,
-) -> Json<Value> {
+) -> Result<Json<Value>, EngineRequestError> {
     let message = MessageToEngine::GetQuote { data: order_data };
 
-    match state.redis_manager.send_and_wait(message) {
-        Ok(response) => Json(json!(response)),
-        Err(e) => Json(json!({
-            "error": format!("Redis error: {}", e)
-        })),
-    }
+    let response = state.redis_manager.send_and_wait(message).await?;
+    Ok(Json(json!(response)))
 }
 
 pub async fn open_orders(
     State(state): State<Arc<AppState>>,
-    Query(order_data): Query<GetOpenOrdersPayload>,
-) -> Json<Value> {
+    user: AuthUser,
+    Query(mut order_data): Query<GetOpenOrdersPayload>,
+) -> Result<Json<Value>, EngineRequestError> {
+    order_data.user_id = user.user_id;
     let message = MessageToEngine::GetOpenOrders { data: order_data };
 
-    match state.redis_manager.send_and_wait(message) {
-        Ok(response) => Json(json!(response)),
-        Err(e) => Json(json!({
-            "error": format!("Redis error: {}", e)
-        })),
-    }
+    let response = state.redis_manager.send_and_wait(message).await?;
+    Ok(Json(json!(response)))
 }
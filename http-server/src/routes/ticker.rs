@@ -0,0 +1,33 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use serde_json::{json, Value};
+
+use crate::{
+    models::{GetBookTickerPayload, GetTickersPayload, MessageToEngine},
+    services::EngineRequestError,
+    state::AppState,
+};
+
+pub async fn get_book_ticker(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<GetBookTickerPayload>,
+) -> Result<Json<Value>, EngineRequestError> {
+    let message = MessageToEngine::GetBookTicker { data: params };
+
+    let response = state.redis_manager.send_and_wait(message).await?;
+    Ok(Json(json!(response)))
+}
+
+pub async fn get_tickers(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<GetTickersPayload>,
+) -> Result<Json<Value>, EngineRequestError> {
+    let message = MessageToEngine::GetTickers { data: params };
+
+    let response = state.redis_manager.send_and_wait(message).await?;
+    Ok(Json(json!(response)))
+}
@@ -1,48 +1,100 @@
 use std::sync::Arc;
 
-use axum::{
-    extract::{Query, State},
-    Json,
-};
+use axum::{extract::State, Json};
 use serde_json::{json, Value};
 
 use crate::{
-    models::{GetUserBalancesPayload, MessageToEngine, OnRampPayload},
+    auth::AuthUser,
+    models::{
+        FaucetPayload, GetUserBalancesPayload, GetUserPortfolioPayload, GetWithdrawalsPayload,
+        MessageToEngine, WithdrawPayload, WithdrawalActionPayload,
+    },
+    services::EngineRequestError,
     state::AppState,
 };
 
 pub async fn get_balances(
     State(state): State<Arc<AppState>>,
-    Query(params): Query<GetUserBalancesPayload>,
-) -> Json<Value> {
+    user: AuthUser,
+) -> Result<Json<Value>, EngineRequestError> {
     let message = MessageToEngine::GetUserBalances {
         data: GetUserBalancesPayload {
-            user_id: params.user_id,
+            user_id: user.user_id,
+        },
+    };
+
+    let response = state.redis_manager.send_and_wait(message).await?;
+    Ok(Json(json!(response)))
+}
+
+pub async fn get_portfolio(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> Result<Json<Value>, EngineRequestError> {
+    let message = MessageToEngine::GetUserPortfolio {
+        data: GetUserPortfolioPayload {
+            user_id: user.user_id,
         },
     };
 
-    match state.redis_manager.send_and_wait(message) {
-        Ok(response) => Json(json!(response)),
-        Err(e) => Json(json!({
-            "error": format!("Redis error: {}", e)
-        })),
-    }
+    let response = state.redis_manager.send_and_wait(message).await?;
+    Ok(Json(json!(response)))
+}
+
+pub async fn withdraw(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(mut params): Json<WithdrawPayload>,
+) -> Result<Json<Value>, EngineRequestError> {
+    params.user_id = user.user_id;
+    let message = MessageToEngine::Withdraw { data: params };
+
+    let response = state.redis_manager.send_and_wait(message).await?;
+    Ok(Json(json!(response)))
 }
 
-pub async fn on_ramp(
+pub async fn get_withdrawals(
     State(state): State<Arc<AppState>>,
-    Json(params): Json<OnRampPayload>,
-) -> Json<Value> {
-    let message = MessageToEngine::OnRampUser {
-        data: OnRampPayload {
-            user_id: params.user_id,
+    user: AuthUser,
+) -> Result<Json<Value>, EngineRequestError> {
+    let message = MessageToEngine::GetWithdrawals {
+        data: GetWithdrawalsPayload {
+            user_id: user.user_id,
         },
     };
 
-    match state.redis_manager.send_and_wait(message) {
-        Ok(response) => Json(json!(response)),
-        Err(e) => Json(json!({
-            "error": format!("Redis error: {}", e)
-        })),
-    }
+    let response = state.redis_manager.send_and_wait(message).await?;
+    Ok(Json(json!(response)))
+}
+
+pub async fn approve_withdrawal(
+    State(state): State<Arc<AppState>>,
+    Json(params): Json<WithdrawalActionPayload>,
+) -> Result<Json<Value>, EngineRequestError> {
+    let message = MessageToEngine::ApproveWithdrawal { data: params };
+
+    let response = state.redis_manager.send_and_wait(message).await?;
+    Ok(Json(json!(response)))
+}
+
+pub async fn reject_withdrawal(
+    State(state): State<Arc<AppState>>,
+    Json(params): Json<WithdrawalActionPayload>,
+) -> Result<Json<Value>, EngineRequestError> {
+    let message = MessageToEngine::RejectWithdrawal { data: params };
+
+    let response = state.redis_manager.send_and_wait(message).await?;
+    Ok(Json(json!(response)))
+}
+
+pub async fn faucet(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(mut params): Json<FaucetPayload>,
+) -> Result<Json<Value>, EngineRequestError> {
+    params.user_id = user.user_id;
+    let message = MessageToEngine::Faucet { data: params };
+
+    let response = state.redis_manager.send_and_wait(message).await?;
+    Ok(Json(json!(response)))
 }
@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use axum::{extract::State, Json};
+use serde_json::{json, Value};
+
+use crate::{
+    auth::AuthUser, models::ListenKeyPayload, services::LISTEN_KEY_TTL_SECS, state::AppState,
+};
+
+pub async fn create_listen_key(State(state): State<Arc<AppState>>, user: AuthUser) -> Json<Value> {
+    match state.redis_manager.create_listen_key(&user.user_id).await {
+        Ok(listen_key) => Json(json!({
+            "listenKey": listen_key,
+            "expiresIn": LISTEN_KEY_TTL_SECS
+        })),
+        Err(e) => Json(json!({
+            "error": format!("Redis error: {}", e)
+        })),
+    }
+}
+
+pub async fn keepalive_listen_key(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(params): Json<ListenKeyPayload>,
+) -> Json<Value> {
+    match owned_by(&state, &params.listen_key, &user).await {
+        Ok(true) => {}
+        Ok(false) => return listen_key_not_found(),
+        Err(e) => return e,
+    }
+
+    match state
+        .redis_manager
+        .keepalive_listen_key(&params.listen_key)
+        .await
+    {
+        Ok(true) => Json(json!({
+            "listenKey": params.listen_key,
+            "expiresIn": LISTEN_KEY_TTL_SECS
+        })),
+        Ok(false) => listen_key_not_found(),
+        Err(e) => Json(json!({
+            "error": format!("Redis error: {}", e)
+        })),
+    }
+}
+
+pub async fn close_listen_key(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    Json(params): Json<ListenKeyPayload>,
+) -> Json<Value> {
+    match owned_by(&state, &params.listen_key, &user).await {
+        Ok(true) => {}
+        Ok(false) => return listen_key_not_found(),
+        Err(e) => return e,
+    }
+
+    match state
+        .redis_manager
+        .delete_listen_key(&params.listen_key)
+        .await
+    {
+        Ok(_) => Json(json!({ "success": true })),
+        Err(e) => Json(json!({
+            "error": format!("Redis error: {}", e)
+        })),
+    }
+}
+
+/// Whether `listen_key` is live and was issued to `user`. Someone else's key
+/// looks the same as an expired one.
+async fn owned_by(
+    state: &AppState,
+    listen_key: &str,
+    user: &AuthUser,
+) -> Result<bool, Json<Value>> {
+    match state.redis_manager.listen_key_owner(listen_key).await {
+        Ok(owner) => Ok(owner.as_deref() == Some(user.user_id.as_str())),
+        Err(e) => Err(Json(json!({
+            "error": format!("Redis error: {}", e)
+        }))),
+    }
+}
+
+fn listen_key_not_found() -> Json<Value> {
+    Json(json!({
+        "error": "Listen key not found or expired"
+    }))
+}
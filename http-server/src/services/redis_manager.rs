@@ -1,43 +1,361 @@
-use redis::{Client, Commands, RedisResult};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures_util::StreamExt;
+use metrics::histogram;
+use redis::{
+    aio::{ConnectionManager, PubSub},
+    AsyncCommands, Client, RedisError, RedisResult,
+};
+use serde_json::json;
+use tokio::sync::oneshot;
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use crate::models::{MessageFromEngine, MessageToEngine};
 
+/// Listen keys expire unless kept alive within this window.
+pub const LISTEN_KEY_TTL_SECS: i64 = 60 * 60;
+
+/// How long a request waits for the engine, unless `ENGINE_TIMEOUT_MS` is set.
+pub const DEFAULT_ENGINE_TIMEOUT_MS: u64 = 5_000;
+
+/// Pause between attempts to win the reply subscription back after losing it.
+const REPLY_RESUBSCRIBE_DELAY_SECS: u64 = 1;
+
+fn listen_key_redis_key(listen_key: &str) -> String {
+    format!("listenKey:{}", listen_key)
+}
+
+fn signature_redis_key(signature: &str) -> String {
+    format!("apiSignature:{}", signature)
+}
+
+/// Requests waiting for the engine, by client id.
+type PendingReplies = Arc<Mutex<HashMap<String, oneshot::Sender<String>>>>;
+
+/// Why a request to the engine got no usable reply.
+#[derive(Debug)]
+pub enum EngineRequestError {
+    Redis(RedisError),
+    Timeout(Duration),
+    InvalidResponse(String),
+}
+
+impl fmt::Display for EngineRequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineRequestError::Redis(e) => write!(f, "Redis error: {}", e),
+            EngineRequestError::Timeout(timeout) => {
+                write!(f, "Engine did not reply within {}ms", timeout.as_millis())
+            }
+            EngineRequestError::InvalidResponse(e) => write!(f, "Invalid engine response: {}", e),
+        }
+    }
+}
+
+impl From<RedisError> for EngineRequestError {
+    fn from(e: RedisError) -> Self {
+        EngineRequestError::Redis(e)
+    }
+}
+
+impl IntoResponse for EngineRequestError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            EngineRequestError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            EngineRequestError::Redis(_) | EngineRequestError::InvalidResponse(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+        };
+        (status, Json(json!({ "error": self.to_string() }))).into_response()
+    }
+}
+
 pub struct RedisManager {
-    client: Client,
+    connection: ConnectionManager,
+    /// Every client id this instance sends starts with it, so a single
+    /// pattern subscription receives all the engine's replies to it
+    reply_prefix: String,
+    pending: PendingReplies,
+    timeout: Duration,
 }
 
 impl RedisManager {
-    pub fn new() -> Self {
-        let client = redis::Client::open("redis://127.0.0.1/").unwrap();
-        RedisManager { client }
+    pub async fn new() -> RedisResult<Self> {
+        Self::connect("redis://127.0.0.1/").await
     }
 
-    pub fn send_and_wait(&self, message: MessageToEngine) -> RedisResult<MessageFromEngine> {
-        let mut conn = self.client.get_connection()?;
-        let client_id = Uuid::new_v4().to_string();
+    pub async fn connect(url: &str) -> RedisResult<Self> {
+        let client = Client::open(url)?;
+        let connection = client.get_connection_manager().await?;
+        let reply_prefix = format!("http-{}:", Uuid::new_v4().simple());
+        let pending = PendingReplies::default();
 
+        // Subscribed before the first request is pushed, so no reply can be
+        // published ahead of its subscription
+        let pubsub = subscribe_replies(&client, &reply_prefix).await?;
+        tokio::spawn(listen_for_replies(
+            client,
+            reply_prefix.clone(),
+            pubsub,
+            pending.clone(),
+        ));
+
+        let timeout = std::env::var("ENGINE_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_ENGINE_TIMEOUT_MS);
+
+        Ok(RedisManager {
+            connection,
+            reply_prefix,
+            pending,
+            timeout: Duration::from_millis(timeout),
+        })
+    }
+
+    pub async fn send_and_wait(
+        &self,
+        message: MessageToEngine,
+    ) -> Result<MessageFromEngine, EngineRequestError> {
+        let client_id = format!("{}{}", self.reply_prefix, Uuid::new_v4());
         let message_with_id = serde_json::json!({
             "client_id": client_id,
             "message": message
         });
+        let kind = message_with_id["message"]["type"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+
+        let started = Instant::now();
+        let result = self.round_trip(&client_id, &message_with_id).await;
+        let outcome = match &result {
+            Ok(_) => "ok",
+            Err(EngineRequestError::Timeout(_)) => "timeout",
+            Err(_) => "error",
+        };
+        histogram!("http_engine_round_trip_seconds", "type" => kind, "outcome" => outcome)
+            .record(started.elapsed());
+
+        result
+    }
 
-        let _: () = conn.lpush("messages", serde_json::to_string(&message_with_id).unwrap())?;
+    async fn round_trip(
+        &self,
+        client_id: &str,
+        message_with_id: &serde_json::Value,
+    ) -> Result<MessageFromEngine, EngineRequestError> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(client_id.to_string(), reply_tx);
+        let _waiting = PendingReply {
+            pending: &self.pending,
+            client_id,
+        };
 
-        let mut pubsub = conn.as_pubsub();
-        pubsub.subscribe(&client_id)?;
+        let mut connection = self.connection.clone();
+        let _: () = connection
+            .lpush("messages", serde_json::to_string(message_with_id).unwrap())
+            .await?;
 
-        let msg = pubsub.get_message()?;
-        let response: String = msg.get_payload()?;
+        let response = match tokio::time::timeout(self.timeout, reply_rx).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) | Err(_) => return Err(EngineRequestError::Timeout(self.timeout)),
+        };
 
-        let parsed_response: MessageFromEngine = serde_json::from_str(&response).map_err(|e| {
-            redis::RedisError::from((
-                redis::ErrorKind::ParseError,
-                "Failed to parse engine response",
-                e.to_string(),
-            ))
-        })?;
+        serde_json::from_str(&response)
+            .map_err(|e| EngineRequestError::InvalidResponse(e.to_string()))
+    }
+
+    /// Issues a listen key that lets wss open `user_id`'s private stream.
+    pub async fn create_listen_key(&self, user_id: &str) -> RedisResult<String> {
+        let mut connection = self.connection.clone();
+        let listen_key = Uuid::new_v4().simple().to_string();
+
+        let _: () = connection
+            .set_ex(
+                listen_key_redis_key(&listen_key),
+                user_id,
+                LISTEN_KEY_TTL_SECS as u64,
+            )
+            .await?;
+        Ok(listen_key)
+    }
+
+    /// Pushes the expiry of `listen_key` back, false if it already expired.
+    pub async fn keepalive_listen_key(&self, listen_key: &str) -> RedisResult<bool> {
+        let mut connection = self.connection.clone();
+        connection
+            .expire(listen_key_redis_key(listen_key), LISTEN_KEY_TTL_SECS)
+            .await
+    }
+
+    /// User `listen_key` was issued to, if it hasn't expired.
+    pub async fn listen_key_owner(&self, listen_key: &str) -> RedisResult<Option<String>> {
+        let mut connection = self.connection.clone();
+        connection.get(listen_key_redis_key(listen_key)).await
+    }
+
+    /// Records a request signature for `ttl`, false if it was already recorded.
+    pub async fn claim_signature(&self, signature: &str, ttl: Duration) -> RedisResult<bool> {
+        let mut connection = self.connection.clone();
+        let claimed: Option<String> = redis::cmd("SET")
+            .arg(signature_redis_key(signature))
+            .arg(1)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl.as_millis() as u64)
+            .query_async(&mut connection)
+            .await?;
+        Ok(claimed.is_some())
+    }
+
+    pub async fn delete_listen_key(&self, listen_key: &str) -> RedisResult<bool> {
+        let mut connection = self.connection.clone();
+        connection.del(listen_key_redis_key(listen_key)).await
+    }
+}
+
+/// Forgets a waiting request however `round_trip` ends, including the
+/// handler being dropped when its client goes away.
+struct PendingReply<'a> {
+    pending: &'a PendingReplies,
+    client_id: &'a str,
+}
+
+impl Drop for PendingReply<'_> {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().remove(self.client_id);
+    }
+}
+
+async fn subscribe_replies(client: &Client, reply_prefix: &str) -> RedisResult<PubSub> {
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.psubscribe(format!("{}*", reply_prefix)).await?;
+    Ok(pubsub)
+}
+
+/// Hands each engine reply to the request waiting for it. Replies published
+/// while the subscription is being won back are lost and their requests time
+/// out.
+async fn listen_for_replies(
+    client: Client,
+    reply_prefix: String,
+    mut pubsub: PubSub,
+    pending: PendingReplies,
+) {
+    loop {
+        let mut messages = pubsub.into_on_message();
+        while let Some(msg) = messages.next().await {
+            let payload: String = match msg.get_payload() {
+                Ok(payload) => payload,
+                Err(e) => {
+                    warn!("Ignoring unreadable engine reply: {}", e);
+                    continue;
+                }
+            };
+            let waiting = pending.lock().unwrap().remove(msg.get_channel_name());
+            match waiting {
+                Some(reply) => {
+                    let _ = reply.send(payload);
+                }
+                None => warn!(
+                    client_id = msg.get_channel_name(),
+                    "Dropping engine reply nobody is waiting for"
+                ),
+            }
+        }
 
-        Ok(parsed_response)
+        error!("Lost the engine reply subscription, resubscribing");
+        pubsub = loop {
+            tokio::time::sleep(Duration::from_secs(REPLY_RESUBSCRIBE_DELAY_SECS)).await;
+            match subscribe_replies(&client, &reply_prefix).await {
+                Ok(pubsub) => break pubsub,
+                Err(e) => error!("Failed to resubscribe to engine replies: {}", e),
+            }
+        };
+        info!("Resubscribed to engine replies");
     }
 }
+
+/// Answers just enough of the Redis protocol for `RedisManager` to connect,
+/// so routes that never reach the engine can be tested without a server.
+/// Returns its URL.
+#[cfg(test)]
+pub async fn stub_redis() -> String {
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    async fn read_command(
+        reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>,
+    ) -> Option<Vec<String>> {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.ok()?;
+        let count: usize = line.trim().strip_prefix('*')?.parse().ok()?;
+
+        let mut args = Vec::with_capacity(count);
+        for _ in 0..count {
+            line.clear();
+            reader.read_line(&mut line).await.ok()?;
+            let len: usize = line.trim().strip_prefix('$')?.parse().ok()?;
+            let mut arg = vec![0; len + 2];
+            reader.read_exact(&mut arg).await.ok()?;
+            arg.truncate(len);
+            args.push(String::from_utf8(arg).ok()?);
+        }
+        Some(args)
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("redis://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (read, mut write) = socket.into_split();
+                let mut reader = BufReader::new(read);
+                while let Some(args) = read_command(&mut reader).await {
+                    let name = args[0].to_lowercase();
+                    let reply = match name.as_str() {
+                        "subscribe" | "psubscribe" => args[1..]
+                            .iter()
+                            .enumerate()
+                            .map(|(i, channel)| {
+                                format!(
+                                    "*3\r\n${}\r\n{}\r\n${}\r\n{}\r\n:{}\r\n",
+                                    name.len(),
+                                    name,
+                                    channel.len(),
+                                    channel,
+                                    i + 1
+                                )
+                            })
+                            .collect(),
+                        "ping" => String::from("+PONG\r\n"),
+                        "get" => String::from("$-1\r\n"),
+                        "lpush" | "rpush" | "publish" | "del" | "expire" => String::from(":1\r\n"),
+                        _ => String::from("+OK\r\n"),
+                    };
+                    if write.write_all(reply.as_bytes()).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    url
+}
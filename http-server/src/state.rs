@@ -1,22 +1,56 @@
 use std::sync::Arc;
 
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
 use sqlx::PgPool;
 
-use crate::services::RedisManager;
+use crate::{auth::AuthConfig, services::RedisManager};
+
+/// Histogram buckets for engine round trips, in seconds.
+const ROUND_TRIP_BUCKETS: [f64; 10] =
+    [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
 
 #[derive(Clone)]
 pub struct AppState {
     pub redis_manager: Arc<RedisManager>,
     pub db_pool: Arc<PgPool>,
+    pub metrics: PrometheusHandle,
+    pub auth: AuthConfig,
 }
 
 impl AppState {
     pub async fn new() -> Self {
         let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
         let pool = PgPool::connect(&database_url).await.unwrap();
+        let metrics = PrometheusBuilder::new()
+            .set_buckets(&ROUND_TRIP_BUCKETS)
+            .and_then(|builder| builder.install_recorder())
+            .expect("Failed to install metrics recorder");
         Self {
-            redis_manager: Arc::new(RedisManager::new()),
+            redis_manager: Arc::new(
+                RedisManager::new()
+                    .await
+                    .expect("Failed to connect to Redis"),
+            ),
             db_pool: Arc::new(pool),
+            metrics,
+            auth: AuthConfig::from_env(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl AppState {
+    /// State for route tests, with `auth` and the Redis at `redis_url`.
+    pub async fn for_tests(db_pool: PgPool, redis_url: &str, auth: AuthConfig) -> Self {
+        Self {
+            redis_manager: Arc::new(
+                RedisManager::connect(redis_url)
+                    .await
+                    .expect("Failed to connect to Redis"),
+            ),
+            db_pool: Arc::new(db_pool),
+            metrics: PrometheusBuilder::new().build_recorder().handle(),
+            auth,
         }
     }
 }
@@ -0,0 +1,211 @@
+//! Multi-market settlement throughput of the keyed `AccountStore` against the
+//! previous `Mutex<Vec<User>>` design, where every lookup was a linear scan
+//! under one global lock.
+//!
+//! Each market runs on its own thread (like an `OrderbookWorker`) and settles
+//! hold + trade cycles between its own pair of users.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Barrier, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use orderbook_manager::{
+    models::Balance,
+    trade::{AccountStore, Ledger, Transaction},
+};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+const USERS: usize = 10_000;
+const OPS_PER_MARKET: u64 = 2_000;
+const BASE: &str = "SOL";
+const QUOTE: &str = "USDC";
+
+fn user_id(i: usize) -> String {
+    format!("user-{}", i)
+}
+
+/// The account layout from before the store redesign.
+struct LegacyAccounts {
+    users: Mutex<Vec<LegacyUser>>,
+}
+
+struct LegacyUser {
+    id: String,
+    balances: Vec<Balance>,
+}
+
+impl LegacyAccounts {
+    fn new() -> Self {
+        let users = (0..USERS)
+            .map(|i| LegacyUser {
+                id: user_id(i),
+                balances: vec![funded(BASE), funded(QUOTE)],
+            })
+            .collect();
+        LegacyAccounts {
+            users: Mutex::new(users),
+        }
+    }
+
+    fn hold(&self, user_id: &str, asset: &str, amount: Decimal) {
+        let mut users = self.users.lock().unwrap();
+        let user = users.iter_mut().find(|u| u.id == user_id).unwrap();
+        let balance = user
+            .balances
+            .iter_mut()
+            .find(|b| b.ticker == asset)
+            .unwrap();
+        balance.locked_balance += amount;
+    }
+
+    fn settle(&self, buyer_id: &str, seller_id: &str, quantity: Decimal, value: Decimal) {
+        let mut users = self.users.lock().unwrap();
+
+        let seller = users.iter_mut().find(|u| u.id == seller_id).unwrap();
+        for balance in seller.balances.iter_mut() {
+            if balance.ticker == BASE {
+                balance.locked_balance -= quantity;
+                balance.balance -= quantity;
+            } else if balance.ticker == QUOTE {
+                balance.balance += value;
+            }
+        }
+
+        let buyer = users.iter_mut().find(|u| u.id == buyer_id).unwrap();
+        for balance in buyer.balances.iter_mut() {
+            if balance.ticker == BASE {
+                balance.balance += quantity;
+            } else if balance.ticker == QUOTE {
+                balance.locked_balance -= value;
+                balance.balance -= value;
+            }
+        }
+    }
+}
+
+fn funded(asset: &str) -> Balance {
+    Balance {
+        ticker: asset.to_string(),
+        balance: dec!(1_000_000_000),
+        locked_balance: Decimal::ZERO,
+    }
+}
+
+fn funded_store() -> AccountStore {
+    let store = AccountStore::new();
+    for i in 0..USERS {
+        let account = store.get_or_create(&user_id(i));
+        let mut user = account.lock().unwrap();
+        user.balances = HashMap::from([
+            (BASE.to_string(), funded(BASE)),
+            (QUOTE.to_string(), funded(QUOTE)),
+        ]);
+    }
+    store
+}
+
+/// Users near the end of the list are the worst case for the linear scan.
+fn market_users(market: usize) -> (String, String) {
+    (
+        user_id(USERS - 1 - 2 * market),
+        user_id(USERS - 2 - 2 * market),
+    )
+}
+
+fn run_markets<F>(markets: usize, iters: u64, cycle: Arc<F>) -> Duration
+where
+    F: Fn(&str, &str) + Send + Sync + 'static,
+{
+    let barrier = Arc::new(Barrier::new(markets + 1));
+    let handles: Vec<_> = (0..markets)
+        .map(|market| {
+            let barrier = barrier.clone();
+            let cycle = cycle.clone();
+            thread::spawn(move || {
+                let (buyer, seller) = market_users(market);
+                barrier.wait();
+                for _ in 0..iters * OPS_PER_MARKET {
+                    cycle(&buyer, &seller);
+                }
+            })
+        })
+        .collect();
+
+    barrier.wait();
+    let start = Instant::now();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    start.elapsed()
+}
+
+fn settlement_throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("multi_market_settlement");
+    group.sample_size(10);
+
+    for markets in [1usize, 4, 8] {
+        group.throughput(Throughput::Elements(markets as u64 * OPS_PER_MARKET));
+
+        let legacy = Arc::new(LegacyAccounts::new());
+        group.bench_with_input(
+            BenchmarkId::new("before_mutex_vec", markets),
+            &markets,
+            |b, &markets| {
+                let legacy = legacy.clone();
+                let cycle = Arc::new(move |buyer: &str, seller: &str| {
+                    legacy.hold(buyer, QUOTE, dec!(10));
+                    legacy.hold(seller, BASE, dec!(1));
+                    legacy.settle(buyer, seller, dec!(1), dec!(10));
+                });
+                b.iter_custom(|iters| run_markets(markets, iters, cycle.clone()));
+            },
+        );
+
+        let store = Arc::new(funded_store());
+        group.bench_with_input(
+            BenchmarkId::new("after_account_store", markets),
+            &markets,
+            |b, &markets| {
+                let store = store.clone();
+                let cycle = Arc::new(move |buyer: &str, seller: &str| {
+                    Ledger::apply(&store, Transaction::hold(buyer, QUOTE, dec!(10), "bench"))
+                        .unwrap();
+                    Ledger::apply(&store, Transaction::hold(seller, BASE, dec!(1), "bench"))
+                        .unwrap();
+                    Ledger::apply(
+                        &store,
+                        Transaction::trade(buyer, seller, BASE, QUOTE, dec!(1), dec!(10), "bench"),
+                    )
+                    .unwrap();
+                });
+                b.iter_custom(|iters| run_markets(markets, iters, cycle.clone()));
+            },
+        );
+    }
+
+    group.finish();
+}
+
+fn lookup(c: &mut Criterion) {
+    let legacy = LegacyAccounts::new();
+    let store = funded_store();
+    let last = user_id(USERS - 1);
+
+    c.bench_function("lookup/before_mutex_vec", |b| {
+        b.iter(|| {
+            let users = legacy.users.lock().unwrap();
+            users.iter().find(|u| u.id == last).is_some()
+        })
+    });
+    c.bench_function("lookup/after_account_store", |b| {
+        b.iter(|| store.get(&last).is_some())
+    });
+}
+
+criterion_group!(benches, settlement_throughput, lookup);
+criterion_main!(benches);
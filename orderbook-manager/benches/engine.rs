@@ -0,0 +1,124 @@
+//! Full `Engine::process` path with replies captured by an in-memory sink:
+//! validation, dispatch to the market worker, matching, settlement and the
+//! reply, everything but Redis itself.
+
+use std::{sync::Arc, time::Duration};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use orderbook_manager::{
+    models::{
+        CancelOrderPayload, CreateOrderPayload, DepositPayload, GetDepthPayload, MessageFromApi,
+        MessageToApi, OrderSide,
+    },
+    services::{MemorySink, RedisManager},
+    trade::Engine,
+};
+use rust_decimal_macros::dec;
+
+const MARKET: &str = "YES_USDC";
+const MAKER: &str = "maker";
+const TAKER: &str = "taker";
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Harness {
+    engine: Engine,
+    sink: Arc<MemorySink>,
+    requests: u64,
+}
+
+impl Harness {
+    fn new() -> Self {
+        let sink = Arc::new(MemorySink::new());
+        if RedisManager::instance().install_sink(sink.clone()).is_err() {
+            panic!("another sink is already installed");
+        }
+
+        let mut engine = Engine::new();
+        engine
+            .create_market(String::from("YES"), String::from("USDC"), None, None)
+            .unwrap();
+
+        let mut harness = Harness {
+            engine,
+            sink,
+            requests: 0,
+        };
+        for user_id in [MAKER, TAKER] {
+            for asset in ["YES", "USDC"] {
+                harness.request(MessageFromApi::Deposit {
+                    data: DepositPayload {
+                        user_id: user_id.to_string(),
+                        asset: asset.to_string(),
+                        amount: dec!(1_000_000_000),
+                        reference: format!("bench-{}-{}", user_id, asset),
+                    },
+                });
+            }
+        }
+        harness
+    }
+
+    fn request(&mut self, message: MessageFromApi) -> MessageToApi {
+        self.requests += 1;
+        let client_id = format!("bench-{}", self.requests);
+        self.engine.process(client_id.clone(), message);
+        self.sink
+            .wait_for_reply(&client_id, REPLY_TIMEOUT)
+            .expect("engine did not reply")
+    }
+
+    fn order(&mut self, user_id: &str, side: OrderSide) -> MessageToApi {
+        self.request(MessageFromApi::CreateOrder {
+            data: CreateOrderPayload {
+                user_id: user_id.to_string(),
+                market: MARKET.to_string(),
+                price: dec!(0.5),
+                quantity: dec!(1),
+                side,
+            },
+        })
+    }
+}
+
+fn process(c: &mut Criterion) {
+    let mut harness = Harness::new();
+    let mut group = c.benchmark_group("engine_process");
+
+    // Each iteration leaves the book as it found it
+    group.bench_function("rest_then_fill", |b| {
+        b.iter(|| {
+            harness.order(MAKER, OrderSide::Ask);
+            harness.order(TAKER, OrderSide::Bid)
+        });
+    });
+    group.bench_function("rest_then_cancel", |b| {
+        b.iter(|| {
+            let MessageToApi::OrderPlaced { payload } = harness.order(MAKER, OrderSide::Ask) else {
+                panic!("order was not placed");
+            };
+            harness.request(MessageFromApi::CancelOrder {
+                data: CancelOrderPayload {
+                    order_id: payload.order_id,
+                    user_id: MAKER.to_string(),
+                    market: MARKET.to_string(),
+                },
+            })
+        });
+    });
+    group.bench_function("get_depth", |b| {
+        b.iter(|| {
+            harness.request(MessageFromApi::GetDepth {
+                data: GetDepthPayload {
+                    market: MARKET.to_string(),
+                    limit: Some(20),
+                    bucket: None,
+                },
+            })
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, process);
+criterion_main!(benches);
@@ -0,0 +1,180 @@
+//! Cost of the hot `Orderbook` operations as the book grows, from a handful of
+//! resting orders to 100k.
+//!
+//! Books are built directly rather than through `insert_order` so setup stays
+//! cheap at the larger sizes. Each side spreads its orders over up to 500
+//! one-cent price levels around 50.00, several orders deep per level.
+
+use std::{collections::HashMap, sync::Arc};
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use orderbook_manager::{
+    models::{Balance, CreateOrderPayload, Order, OrderSide},
+    services::{MemorySink, RedisManager},
+    trade::{AccountStore, Orderbook, QuoteAmount},
+};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+const SIZES: [usize; 4] = [10, 1_000, 10_000, 100_000];
+const MAX_LEVELS: usize = 500;
+const BASE: &str = "YES";
+const QUOTE: &str = "USDC";
+const MAKER: &str = "maker";
+const TAKER: &str = "taker";
+
+fn install_sink() {
+    // Settlement publishes balance updates, keep them off the network
+    let _ = RedisManager::instance().install_sink(Arc::new(MemorySink::new()));
+}
+
+fn funded(asset: &str) -> Balance {
+    // Everything locked so both sides can settle fills without holds
+    Balance {
+        ticker: asset.to_string(),
+        balance: dec!(1_000_000_000_000),
+        locked_balance: dec!(1_000_000_000_000),
+    }
+}
+
+fn funded_accounts() -> AccountStore {
+    let accounts = AccountStore::new();
+    for user_id in [MAKER, TAKER] {
+        let account = accounts.get_or_create(user_id);
+        account.lock().unwrap().balances = HashMap::from([
+            (BASE.to_string(), funded(BASE)),
+            (QUOTE.to_string(), funded(QUOTE)),
+        ]);
+    }
+    accounts
+}
+
+/// A book with `size` resting orders, half on each side.
+fn book(size: usize) -> Orderbook {
+    let mut book = Orderbook::new(BASE.to_string(), QUOTE.to_string());
+    let per_side = (size / 2).max(1);
+    let levels = per_side.min(MAX_LEVELS);
+
+    for i in 0..per_side {
+        let level = Decimal::from((i * levels / per_side) as u64) * dec!(0.01);
+        for (side, price) in [
+            (OrderSide::Bid, dec!(49.99) - level),
+            (OrderSide::Ask, dec!(50.01) + level),
+        ] {
+            let order = Order {
+                id: format!("{:?}-{}", side, i),
+                user_id: MAKER.to_string(),
+                price,
+                quantity: dec!(10),
+                side: side.clone(),
+                timestamp: i as i64,
+            };
+            let (orders, levels) = match side {
+                OrderSide::Bid => (&mut book.bids, &mut book.bid_levels),
+                OrderSide::Ask => (&mut book.asks, &mut book.ask_levels),
+            };
+            *levels.entry(price).or_insert(Decimal::ZERO) += order.quantity;
+            orders.push(order);
+        }
+    }
+
+    book
+}
+
+fn taker(side: OrderSide, price: Decimal, quantity: Decimal) -> CreateOrderPayload {
+    CreateOrderPayload {
+        user_id: TAKER.to_string(),
+        market: format!("{}_{}", BASE, QUOTE),
+        price,
+        quantity,
+        side,
+    }
+}
+
+/// A taker bid sweeping five resting asks.
+fn fill_orders(c: &mut Criterion) {
+    install_sink();
+    let accounts = funded_accounts();
+    let order = taker(OrderSide::Bid, dec!(100), dec!(50));
+
+    let mut group = c.benchmark_group("fill_orders");
+    for size in SIZES {
+        let book = book(size);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &book, |b, book| {
+            b.iter_batched(
+                || book.clone(),
+                // Returned so dropping the clone isn't timed
+                |mut book| {
+                    let _ = book.fill_orders(&order, &accounts);
+                    book
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Rests an order in the middle of the bids and cancels it again.
+fn insert_cancel(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert_cancel");
+    for size in SIZES {
+        let mut book = book(size);
+        let price = book.bids[book.bids.len() / 2].price;
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter(|| {
+                book.insert_order(Order {
+                    id: String::from("bench"),
+                    user_id: TAKER.to_string(),
+                    price,
+                    quantity: dec!(1),
+                    side: OrderSide::Bid,
+                    timestamp: i64::MAX,
+                });
+                book.remove_order("bench", TAKER).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn get_depth(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_depth");
+    for size in SIZES {
+        let book = book(size);
+        group.bench_with_input(BenchmarkId::new("top_20", size), &book, |b, book| {
+            b.iter(|| book.get_depth(Some(20), None))
+        });
+        group.bench_with_input(BenchmarkId::new("full", size), &book, |b, book| {
+            b.iter(|| book.get_depth(None, None))
+        });
+        group.bench_with_input(BenchmarkId::new("bucket_0.1", size), &book, |b, book| {
+            b.iter(|| book.get_depth(Some(20), Some(dec!(0.1))))
+        });
+    }
+    group.finish();
+}
+
+/// Quotes a bid for 1000 base, deep enough to walk 100 orders on big books.
+fn get_quote_detail(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_quote_detail");
+    for size in SIZES {
+        let book = book(size);
+        group.bench_with_input(BenchmarkId::new("base", size), &book, |b, book| {
+            b.iter(|| book.get_quote_detail(OrderSide::Bid, QuoteAmount::Base(dec!(1000))))
+        });
+        group.bench_with_input(BenchmarkId::new("quote", size), &book, |b, book| {
+            b.iter(|| book.get_quote_detail(OrderSide::Bid, QuoteAmount::Quote(dec!(50000))))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    fill_orders,
+    insert_cancel,
+    get_depth,
+    get_quote_detail
+);
+criterion_main!(benches);
@@ -0,0 +1,176 @@
+//! End-to-end latency against a running engine and local Redis: each request
+//! goes through the same envelope, `messages` queue and reply channel that
+//! http-server's `send_and_wait` uses, and is timed until the reply arrives.
+//!
+//! Usage: `cargo run --release --bin latency -- [requests] [market_base]`
+//!
+//! Creates the market if needed and funds two users through the engine, so
+//! point it at a staging engine rather than production.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use orderbook_manager::{
+    constant::MESSAGE_FROM_API_CHANNEL,
+    models::{
+        CreateMarketPayload, CreateOrderPayload, DepositPayload, GetDepthPayload, IncomingMessage,
+        MessageFromApi, MessageToApi, OrderSide, Status,
+    },
+    services::RedisManager,
+};
+use redis::Commands;
+use rust_decimal_macros::dec;
+
+const QUOTE: &str = "USDC";
+const MAKER: &str = "latency-maker";
+const TAKER: &str = "latency-taker";
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Client {
+    commands: redis::Connection,
+    replies: redis::Connection,
+    prefix: String,
+    requests: u64,
+}
+
+impl Client {
+    fn connect() -> Result<Self> {
+        let redis_manager = RedisManager::new();
+        let commands = redis_manager.get_connection()?;
+        let mut replies = redis_manager.get_connection()?;
+        replies.set_read_timeout(Some(REPLY_TIMEOUT))?;
+
+        let prefix = format!("latency-{}", uuid::Uuid::new_v4());
+        // One pattern subscription up front, so no reply can beat its subscribe
+        replies.as_pubsub().psubscribe(format!("{}-*", prefix))?;
+
+        Ok(Client {
+            commands,
+            replies,
+            prefix,
+            requests: 0,
+        })
+    }
+
+    fn request(&mut self, message: MessageFromApi) -> Result<(MessageToApi, Duration)> {
+        self.requests += 1;
+        let client_id = format!("{}-{}", self.prefix, self.requests);
+        let envelope = serde_json::to_string(&IncomingMessage {
+            client_id: client_id.clone(),
+            message,
+        })?;
+
+        let start = Instant::now();
+        let _: () = self.commands.lpush(MESSAGE_FROM_API_CHANNEL, envelope)?;
+        loop {
+            let reply = self
+                .replies
+                .as_pubsub()
+                .get_message()
+                .context("no reply from the engine")?;
+            if reply.get_channel_name() != client_id {
+                continue;
+            }
+            let elapsed = start.elapsed();
+            let payload: String = reply.get_payload()?;
+            return Ok((serde_json::from_str(&payload)?, elapsed));
+        }
+    }
+}
+
+fn order(user_id: &str, market: &str, side: OrderSide) -> MessageFromApi {
+    MessageFromApi::CreateOrder {
+        data: CreateOrderPayload {
+            user_id: user_id.to_string(),
+            market: market.to_string(),
+            price: dec!(0.5),
+            quantity: dec!(1),
+            side,
+        },
+    }
+}
+
+fn report(name: &str, mut samples: Vec<Duration>) {
+    samples.sort();
+    let percentile = |p: usize| samples[(samples.len() * p / 100).min(samples.len() - 1)];
+    let mean = samples.iter().sum::<Duration>() / samples.len() as u32;
+    println!(
+        "{:<16} n={:<6} mean={:>9.3?} p50={:>9.3?} p90={:>9.3?} p99={:>9.3?} max={:>9.3?}",
+        name,
+        samples.len(),
+        mean,
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        samples[samples.len() - 1]
+    );
+}
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let requests: usize = match args.next() {
+        Some(n) => n.parse().context("requests must be a number")?,
+        None => 1_000,
+    };
+    let base = args.next().unwrap_or_else(|| String::from("LATENCY"));
+    let market = format!("{}_{}", base, QUOTE);
+
+    let mut client = Client::connect()?;
+
+    let now = Utc::now();
+    let (reply, _) = client.request(MessageFromApi::CreateMarket {
+        data: CreateMarketPayload {
+            name: market.clone(),
+            description: Some(String::from("Latency harness")),
+            base_asset: base.clone(),
+            quote_asset: QUOTE.to_string(),
+            start_time: now,
+            end_time: now + chrono::Duration::days(1),
+            status: Status::Ongoing,
+            amm_subsidy: None,
+        },
+    })?;
+    match reply {
+        MessageToApi::MarketCreated { .. } => println!("created market {}", market),
+        MessageToApi::Rejected { payload } if payload.code == "MARKET_EXISTS" => {}
+        other => bail!("could not create {}: {:?}", market, other),
+    }
+
+    for user_id in [MAKER, TAKER] {
+        for asset in [base.as_str(), QUOTE] {
+            client.request(MessageFromApi::Deposit {
+                data: DepositPayload {
+                    user_id: user_id.to_string(),
+                    asset: asset.to_string(),
+                    amount: dec!(1_000_000),
+                    reference: uuid::Uuid::new_v4().to_string(),
+                },
+            })?;
+        }
+    }
+
+    let mut depth = Vec::with_capacity(requests);
+    let mut rest = Vec::with_capacity(requests);
+    let mut fill = Vec::with_capacity(requests);
+    for _ in 0..requests {
+        let (_, elapsed) = client.request(MessageFromApi::GetDepth {
+            data: GetDepthPayload {
+                market: market.clone(),
+                limit: Some(20),
+                bucket: None,
+            },
+        })?;
+        depth.push(elapsed);
+
+        let (_, elapsed) = client.request(order(MAKER, &market, OrderSide::Ask))?;
+        rest.push(elapsed);
+        let (_, elapsed) = client.request(order(TAKER, &market, OrderSide::Bid))?;
+        fill.push(elapsed);
+    }
+
+    report("get_depth", depth);
+    report("order_rest", rest);
+    report("order_fill", fill);
+    Ok(())
+}
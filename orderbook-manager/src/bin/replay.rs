@@ -0,0 +1,270 @@
+//! Offline replay of a command journal recorded with `COMMAND_JOURNAL_PATH`.
+//!
+//! Usage:
+//!   `cargo run --bin replay -- run <journal> [--until <sequence>] [--out <dump.json>]`
+//!   `cargo run --bin replay -- diff <a.json> <b.json>`
+//!
+//! `run` feeds every recorded request into a fresh `Engine` at its recorded
+//! time, one at a time, and dumps the books, balances and emitted messages as
+//! they stand after `--until` (or the end of the journal). Order and
+//! withdrawal ids are generated anew on replay, so they are translated from
+//! the recorded ids on the way in and back on the way out, which keeps dumps
+//! from two engine builds comparable with `diff`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use orderbook_manager::{
+    models::{Balance, Order},
+    services::{read_journal, JournalEntry, MemorySink, RedisManager, SinkMessage},
+    trade::{feed, recorded_replies, Engine, IdMap},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ReplayDump {
+    /// Last journal entry applied
+    sequence: Option<u64>,
+    requests: usize,
+    divergences: Vec<Divergence>,
+    books: BTreeMap<String, Vec<Order>>,
+    balances: BTreeMap<String, Vec<Balance>>,
+    events: Vec<SinkMessage>,
+}
+
+/// A request whose replayed reply has a different type than the recorded one.
+#[derive(Debug, Serialize, Deserialize)]
+struct Divergence {
+    sequence: u64,
+    client_id: String,
+    recorded: Option<String>,
+    replayed: Option<String>,
+}
+
+fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(tracing::Level::WARN)
+        .init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("run") => {
+            let journal = args.get(1).context("missing journal path")?;
+            let until = flag(&args, "--until")?
+                .map(|s| s.parse::<u64>())
+                .transpose()
+                .context("--until must be a sequence number")?;
+            let dump = run(journal, until)?;
+            let json = serde_json::to_string_pretty(&dump)?;
+            match flag(&args, "--out")? {
+                Some(path) => std::fs::write(path, json)?,
+                None => println!("{}", json),
+            }
+            if !dump.divergences.is_empty() {
+                eprintln!("{} replies diverged from the journal", dump.divergences.len());
+            }
+            Ok(())
+        }
+        Some("diff") => {
+            let (Some(a), Some(b)) = (args.get(1), args.get(2)) else {
+                bail!("usage: replay diff <a.json> <b.json>");
+            };
+            if diff(&load_dump(a)?, &load_dump(b)?) {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        _ => bail!("usage: replay run <journal> [--until <sequence>] [--out <dump.json>] | replay diff <a.json> <b.json>"),
+    }
+}
+
+fn flag<'a>(args: &'a [String], name: &str) -> Result<Option<&'a str>> {
+    match args.iter().position(|a| a == name) {
+        Some(i) => match args.get(i + 1) {
+            Some(value) => Ok(Some(value)),
+            None => bail!("{} needs a value", name),
+        },
+        None => Ok(None),
+    }
+}
+
+fn run(path: &str, until: Option<u64>) -> Result<ReplayDump> {
+    let entries = read_journal(path)?;
+
+    let sink = Arc::new(MemorySink::recording());
+    if RedisManager::instance().install_sink(sink.clone()).is_err() {
+        bail!("another sink is already installed");
+    }
+
+    let mut recorded_replies = recorded_replies(&entries);
+
+    let mut engine = Engine::new();
+    let mut ids = IdMap::default();
+    let mut dump = ReplayDump {
+        sequence: None,
+        requests: 0,
+        divergences: Vec::new(),
+        books: BTreeMap::new(),
+        balances: BTreeMap::new(),
+        events: Vec::new(),
+    };
+
+    for entry in entries
+        .iter()
+        .take_while(|e| until.is_none_or(|until| e.sequence() <= until))
+    {
+        dump.sequence = Some(entry.sequence());
+        match entry {
+            JournalEntry::Markets { time, markets, .. } => {
+                engine.reconcile_markets(markets, *time);
+            }
+            JournalEntry::Request {
+                sequence,
+                time,
+                message,
+            } => {
+                dump.requests += 1;
+                let Some(client_id) = feed(&mut engine, &ids, message, *time) else {
+                    continue;
+                };
+
+                let replayed = sink
+                    .wait_for_reply(&client_id, REPLY_TIMEOUT)
+                    .map(|reply| serde_json::to_value(reply).unwrap());
+                let recorded = recorded_replies.get_mut(&client_id).and_then(Vec::pop);
+                if let (Some(recorded), Some(replayed)) = (&recorded, &replayed) {
+                    ids.learn(recorded, replayed);
+                }
+
+                let recorded_type = recorded.as_ref().and_then(reply_type);
+                let replayed_type = replayed.as_ref().and_then(reply_type);
+                if recorded_type != replayed_type {
+                    dump.divergences.push(Divergence {
+                        sequence: *sequence,
+                        client_id,
+                        recorded: recorded_type,
+                        replayed: replayed_type,
+                    });
+                }
+            }
+            JournalEntry::ChainReceipt { receipt, .. } => {
+                let mut receipt = receipt.clone();
+                receipt.withdrawal_id = ids.replayed_id(&receipt.withdrawal_id);
+                engine.settle_withdrawal(receipt);
+            }
+            JournalEntry::Reply { .. } => {}
+        }
+    }
+
+    let snapshot = engine.snapshot();
+    if !snapshot.unavailable_markets.is_empty() {
+        bail!("{:?} didn't answer the audit", snapshot.unavailable_markets);
+    }
+    dump.books = snapshot.books;
+    for order in dump.books.values_mut().flatten() {
+        order.id = ids.recorded_id(&order.id);
+    }
+    dump.balances = snapshot.balances;
+    dump.events = sink.take_recorded();
+
+    Ok(dump)
+}
+
+fn reply_type(reply: &Value) -> Option<String> {
+    let kind = reply.get("type")?.as_str()?;
+    match reply.pointer("/payload/code").and_then(Value::as_str) {
+        Some(code) => Some(format!("{}:{}", kind, code)),
+        None => Some(kind.to_string()),
+    }
+}
+
+fn load_dump(path: &str) -> Result<ReplayDump> {
+    let json = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+    serde_json::from_str(&json).with_context(|| format!("{} is not a replay dump", path))
+}
+
+/// Prints where two dumps disagree on books and balances and returns whether
+/// they did. Emitted messages carry fresh trade ids and timestamps on every
+/// run, so only their count is compared.
+fn diff(a: &ReplayDump, b: &ReplayDump) -> bool {
+    let mut differs = false;
+
+    if a.sequence != b.sequence {
+        println!("sequence: {:?} vs {:?}", a.sequence, b.sequence);
+        differs = true;
+    }
+
+    let markets = a
+        .books
+        .keys()
+        .chain(b.books.keys())
+        .collect::<BTreeSet<_>>();
+    for market in markets {
+        let orders = |dump: &ReplayDump| {
+            dump.books
+                .get(market)
+                .map(|orders| {
+                    orders
+                        .iter()
+                        .map(|o| {
+                            let side = format!("{:?}", o.side);
+                            (o.id.clone(), o.user_id.clone(), side, o.price, o.quantity)
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default()
+        };
+        let (a_orders, b_orders) = (orders(a), orders(b));
+        if a_orders != b_orders {
+            println!("book {}:", market);
+            for order in a_orders.iter().filter(|o| !b_orders.contains(o)) {
+                println!("  - {:?}", order);
+            }
+            for order in b_orders.iter().filter(|o| !a_orders.contains(o)) {
+                println!("  + {:?}", order);
+            }
+            differs = true;
+        }
+    }
+
+    let users = a
+        .balances
+        .keys()
+        .chain(b.balances.keys())
+        .collect::<BTreeSet<_>>();
+    for user in users {
+        let balances = |dump: &ReplayDump| {
+            dump.balances
+                .get(user)
+                .map(|balances| {
+                    balances
+                        .iter()
+                        .map(|b| (b.ticker.clone(), b.balance, b.locked_balance))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default()
+        };
+        let (a_balances, b_balances) = (balances(a), balances(b));
+        if a_balances != b_balances {
+            println!("balances {}:", user);
+            println!("  - {:?}", a_balances);
+            println!("  + {:?}", b_balances);
+            differs = true;
+        }
+    }
+
+    if a.events.len() != b.events.len() {
+        println!("events: {} vs {}", a.events.len(), b.events.len());
+        differs = true;
+    }
+
+    differs
+}
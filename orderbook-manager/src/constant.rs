@@ -1 +1,51 @@
 pub const MESSAGE_FROM_API_CHANNEL: &str = "messages";
+
+/// Upper bounds on client supplied prices and quantities, low enough that
+/// `price * quantity` and balance arithmetic can never overflow a `Decimal`.
+pub const MAX_PRICE: i64 = 1_000_000_000_000;
+pub const MAX_QUANTITY: i64 = 1_000_000_000_000;
+
+/// How often the engine re-reads the `markets` table while running.
+pub const MARKET_RECONCILE_INTERVAL_SECS: u64 = 30;
+
+/// Longest the engine blocks on the request queue, so shutdown and the
+/// periodic jobs are never kept waiting longer than this.
+pub const QUEUE_POLL_TIMEOUT_SECS: u64 = 1;
+
+/// How long the engine waits before retrying Redis after a failed poll.
+pub const QUEUE_RETRY_DELAY_SECS: u64 = 1;
+
+/// How many times shutdown audits the markets for a complete snapshot
+/// before giving up and keeping the previous one.
+pub const SNAPSHOT_ATTEMPTS: usize = 3;
+
+/// Where the engine writes its state on shutdown, unless `ENGINE_SNAPSHOT_PATH` is set.
+pub const DEFAULT_SNAPSHOT_PATH: &str = "engine-snapshot.json";
+
+/// Longest a graceful shutdown may take before the process exits anyway.
+pub const SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+
+/// Requests each market worker queues before new ones are rejected with
+/// `ENGINE_BUSY`, unless `WORKER_QUEUE_CAPACITY` is set. Cancels have a queue
+/// of the same size of their own.
+pub const DEFAULT_WORKER_QUEUE_CAPACITY: usize = 10_000;
+
+/// Assets the faucet hands out, comma separated, unless `FAUCET_ASSETS` is set.
+pub const DEFAULT_FAUCET_ASSETS: &str = "USDC";
+
+/// How often the engine checks its balance invariants while running.
+pub const INVARIANT_CHECK_INTERVAL_SECS: u64 = 300;
+
+/// How long the invariant checker waits for a market worker to pause.
+pub const AUDIT_REPLY_TIMEOUT_SECS: u64 = 5;
+
+/// How long recovery waits for the reply to each replayed journal request.
+pub const RECOVERY_REPLY_TIMEOUT_SECS: u64 = 5;
+
+/// Port Prometheus scrapes the engine on, unless `METRICS_PORT` is set.
+pub const DEFAULT_METRICS_PORT: u16 = 9100;
+
+/// Histogram buckets for matching latency, in seconds.
+pub const MATCH_DURATION_BUCKETS: [f64; 10] = [
+    0.00001, 0.000025, 0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01,
+];
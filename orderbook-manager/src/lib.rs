@@ -0,0 +1,4 @@
+pub mod constant;
+pub mod models;
+pub mod services;
+pub mod trade;
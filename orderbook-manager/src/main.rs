@@ -1,30 +1,222 @@
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
 use anyhow::Result;
-use constant::MESSAGE_FROM_API_CHANNEL;
-use models::IncomingMessage;
+use chrono::Utc;
+use dotenv::dotenv;
+use metrics_exporter_prometheus::PrometheusBuilder;
+use orderbook_manager::{
+    constant::{
+        DEFAULT_METRICS_PORT, DEFAULT_SNAPSHOT_PATH, INVARIANT_CHECK_INTERVAL_SECS,
+        MARKET_RECONCILE_INTERVAL_SECS, MATCH_DURATION_BUCKETS, MESSAGE_FROM_API_CHANNEL,
+        QUEUE_POLL_TIMEOUT_SECS, QUEUE_RETRY_DELAY_SECS, SHUTDOWN_TIMEOUT_SECS, SNAPSHOT_ATTEMPTS,
+    },
+    models::IncomingMessage,
+    services::{read_journal, CommandJournal, MarketStore, RedisManager},
+    trade::{reject, Engine, EngineError, EngineSnapshot},
+};
 use redis::Commands;
-use services::RedisManager;
-use trade::Engine;
-
-mod constant;
-mod models;
-mod services;
-mod trade;
+use serde_json::Value;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use tracing::{error, info, warn};
 
 fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
+    dotenv().ok();
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register(signal, Arc::clone(&shutdown))?;
+    }
+
+    let metrics_port = std::env::var("METRICS_PORT")
+        .ok()
+        .and_then(|port| port.parse().ok())
+        .unwrap_or(DEFAULT_METRICS_PORT);
+    PrometheusBuilder::new()
+        .with_http_listener(([0, 0, 0, 0], metrics_port))
+        .set_buckets(&MATCH_DURATION_BUCKETS)?
+        .install()?;
+    info!("Serving metrics on port {}", metrics_port);
+
+    let snapshot_path =
+        std::env::var("ENGINE_SNAPSHOT_PATH").unwrap_or_else(|_| DEFAULT_SNAPSHOT_PATH.to_string());
+    let journal_path = std::env::var("COMMAND_JOURNAL_PATH").ok();
+
+    // Pick up where the last run stopped, including requests it handled after
+    // its snapshot if it died before writing another
+    let snapshot = EngineSnapshot::read(&snapshot_path)?;
+    if snapshot.is_some() {
+        info!(path = snapshot_path, "Restoring engine snapshot");
+    }
+    let journal_entries = match &journal_path {
+        Some(path) if Path::new(path).exists() => read_journal(path)?,
+        _ => Vec::new(),
+    };
+    let mut engine = Engine::recover(snapshot, &journal_entries);
+    drop(journal_entries);
+
+    // Replayable with the `replay` binary
+    let journal = match journal_path {
+        Some(path) => {
+            let journal = Arc::new(CommandJournal::open(&path)?);
+            let _ = RedisManager::instance().install_tap(journal.clone());
+            info!(path, "Recording command journal");
+            Some(journal)
+        }
+        None => None,
+    };
 
-    let mut engine = Engine::new();
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let mut market_store = MarketStore::connect(&database_url)?;
+    let markets = market_store.load()?;
+    if let Some(journal) = &journal {
+        journal.record_markets(&markets);
+    }
+    engine.reconcile_markets(&markets, Utc::now());
+    info!("Loaded {} markets", engine.orderbook_workers.len());
 
     let redis_manager = RedisManager::new();
     let mut conn = redis_manager.get_connection()?;
 
-    loop {
-        let response: Option<(String, String)> = conn.brpop(MESSAGE_FROM_API_CHANNEL, 0.0)?;
+    let reconcile_interval = Duration::from_secs(MARKET_RECONCILE_INTERVAL_SECS);
+    let mut last_reconcile = Instant::now();
+    let invariant_check_interval = Duration::from_secs(INVARIANT_CHECK_INTERVAL_SECS);
+    let mut last_invariant_check = Instant::now();
+
+    while !shutdown.load(Ordering::Relaxed) {
+        engine.supervise();
+
+        for receipt in engine.chain.receipts() {
+            if let Some(journal) = &journal {
+                journal.record_chain_receipt(&receipt);
+            }
+            engine.settle_withdrawal(receipt);
+        }
+
+        if last_reconcile.elapsed() >= reconcile_interval {
+            match market_store.load() {
+                Ok(markets) => {
+                    if let Some(journal) = &journal {
+                        journal.record_markets(&markets);
+                    }
+                    engine.reconcile_markets(&markets, Utc::now())
+                }
+                Err(e) => {
+                    error!("Failed to reload markets: {}", e);
+                    if let Ok(store) = MarketStore::connect(&database_url) {
+                        market_store = store;
+                    }
+                }
+            }
+            last_reconcile = Instant::now();
+        }
+
+        if last_invariant_check.elapsed() >= invariant_check_interval {
+            engine.run_invariant_check();
+            last_invariant_check = Instant::now();
+        }
+
+        // Redis going away mustn't skip the shutdown snapshot, so keep retrying
+        let response: Option<(String, String)> =
+            match conn.brpop(MESSAGE_FROM_API_CHANNEL, QUEUE_POLL_TIMEOUT_SECS as f64) {
+                Ok(response) => response,
+                Err(e) => {
+                    error!("Failed to poll the request queue: {}", e);
+                    thread::sleep(Duration::from_secs(QUEUE_RETRY_DELAY_SECS));
+                    match redis_manager.get_connection() {
+                        Ok(new_conn) => conn = new_conn,
+                        Err(e) => error!("Failed to reconnect to Redis: {}", e),
+                    }
+                    continue;
+                }
+            };
 
         if let Some((_, message)) = response {
-            let parsed_message: IncomingMessage = serde_json::from_str(&message)?;
+            if let Some(journal) = &journal {
+                journal.record_request(&message);
+            }
+            match serde_json::from_str::<IncomingMessage>(&message) {
+                Ok(parsed_message) => {
+                    engine.process(parsed_message.client_id, parsed_message.message)
+                }
+                Err(e) => handle_malformed(&message, e),
+            }
+        }
+    }
+
+    shut_down(engine, &snapshot_path, journal.as_deref());
+    Ok(())
+}
 
-            engine.process(parsed_message.client_id, parsed_message.message);
+/// Lets the workers finish what they were handed, saves the engine state and
+/// stops the workers. The previous snapshot is kept if a market never answers
+/// the audit. Requests still queued in Redis stay there for the next start.
+/// Exits the process if any of it takes longer than the timeout.
+fn shut_down(mut engine: Engine, snapshot_path: &str, journal: Option<&CommandJournal>) {
+    info!("Shutting down, no longer taking requests");
+
+    thread::spawn(|| {
+        thread::sleep(Duration::from_secs(SHUTDOWN_TIMEOUT_SECS));
+        error!(
+            "Shutdown took longer than {}s, exiting",
+            SHUTDOWN_TIMEOUT_SECS
+        );
+        std::process::exit(1);
+    });
+
+    // Withdrawals already handed to the chain are settled before the snapshot
+    for receipt in engine.chain.finish() {
+        if let Some(journal) = journal {
+            journal.record_chain_receipt(&receipt);
         }
+        engine.settle_withdrawal(receipt);
+    }
+
+    // Audits queue behind the in-flight orders, so the snapshot sees them
+    // settled. A market that doesn't answer gets another chance, and if it
+    // never does the previous snapshot is kept rather than one without it.
+    for attempt in 1..=SNAPSHOT_ATTEMPTS {
+        let mut snapshot = engine.snapshot();
+        snapshot.journal_sequence = journal.and_then(CommandJournal::last_sequence);
+        if !snapshot.unavailable_markets.is_empty() && attempt < SNAPSHOT_ATTEMPTS {
+            warn!(
+                markets = ?snapshot.unavailable_markets,
+                attempt,
+                "Markets didn't answer the snapshot audit, retrying"
+            );
+            continue;
+        }
+
+        match snapshot.write(snapshot_path) {
+            Ok(()) => info!(path = snapshot_path, "Wrote engine snapshot"),
+            Err(e) => error!(
+                path = snapshot_path,
+                "Failed to write engine snapshot, keeping the previous one: {}", e
+            ),
+        }
+        break;
+    }
+
+    drop(engine);
+    info!("Shutdown complete");
+}
+
+/// Rejects a message that doesn't parse, as long as it names a client to reply to.
+fn handle_malformed(message: &str, e: serde_json::Error) {
+    error!("Failed to parse incoming message: {}", e);
+
+    let client_id = serde_json::from_str::<Value>(message)
+        .ok()
+        .and_then(|value| value.get("client_id")?.as_str().map(String::from));
+    if let Some(client_id) = client_id {
+        reject(&client_id, EngineError::InvalidRequest(e.to_string()));
     }
 }
@@ -17,10 +17,65 @@ pub enum MessageFromApi {
     GetOpenOrders { data: GetOpenOrdersPayload },
     #[serde(rename = "GET_USER_BALANCES")]
     GetUserBalances { data: GetUserBalancesPayload },
-    #[serde(rename = "ON_RAMP_USER")]
-    OnRampUser { data: OnRampPayload },
+    #[serde(rename = "GET_USER_PORTFOLIO")]
+    GetUserPortfolio { data: GetUserPortfolioPayload },
+    #[serde(rename = "DEPOSIT")]
+    Deposit { data: DepositPayload },
+    #[serde(rename = "WITHDRAW")]
+    Withdraw { data: WithdrawPayload },
+    #[serde(rename = "APPROVE_WITHDRAWAL")]
+    ApproveWithdrawal { data: WithdrawalActionPayload },
+    #[serde(rename = "REJECT_WITHDRAWAL")]
+    RejectWithdrawal { data: WithdrawalActionPayload },
+    #[serde(rename = "GET_WITHDRAWALS")]
+    GetWithdrawals { data: GetWithdrawalsPayload },
+    #[serde(rename = "FAUCET")]
+    Faucet { data: FaucetPayload },
     #[serde(rename = "CREATE_MARKET")]
     CreateMarket { data: CreateMarketPayload },
+    #[serde(rename = "CLOSE_MARKET")]
+    CloseMarket { data: CloseMarketPayload },
+    #[serde(rename = "GET_AMM_STATE")]
+    GetAmmState { data: GetAmmStatePayload },
+    #[serde(rename = "GET_BOOK_TICKER")]
+    GetBookTicker { data: GetBookTickerPayload },
+    #[serde(rename = "GET_TICKERS")]
+    GetTickers { data: GetTickersPayload },
+    #[serde(rename = "CHECK_INVARIANTS")]
+    CheckInvariants,
+    #[serde(rename = "GET_HEALTH")]
+    GetHealth,
+    #[serde(rename = "RESTORE_MARKET")]
+    RestoreMarket { data: RestoreMarketPayload },
+}
+
+impl MessageFromApi {
+    /// The `type` tag, used to label metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MessageFromApi::CreateOrder { .. } => "CREATE_ORDER",
+            MessageFromApi::CancelOrder { .. } => "CANCEL_ORDER",
+            MessageFromApi::GetDepth { .. } => "GET_DEPTH",
+            MessageFromApi::GetQuote { .. } => "GET_QUOTE",
+            MessageFromApi::GetOpenOrders { .. } => "GET_OPEN_ORDERS",
+            MessageFromApi::GetUserBalances { .. } => "GET_USER_BALANCES",
+            MessageFromApi::GetUserPortfolio { .. } => "GET_USER_PORTFOLIO",
+            MessageFromApi::Deposit { .. } => "DEPOSIT",
+            MessageFromApi::Withdraw { .. } => "WITHDRAW",
+            MessageFromApi::ApproveWithdrawal { .. } => "APPROVE_WITHDRAWAL",
+            MessageFromApi::RejectWithdrawal { .. } => "REJECT_WITHDRAWAL",
+            MessageFromApi::GetWithdrawals { .. } => "GET_WITHDRAWALS",
+            MessageFromApi::Faucet { .. } => "FAUCET",
+            MessageFromApi::CreateMarket { .. } => "CREATE_MARKET",
+            MessageFromApi::CloseMarket { .. } => "CLOSE_MARKET",
+            MessageFromApi::GetAmmState { .. } => "GET_AMM_STATE",
+            MessageFromApi::GetBookTicker { .. } => "GET_BOOK_TICKER",
+            MessageFromApi::GetTickers { .. } => "GET_TICKERS",
+            MessageFromApi::CheckInvariants => "CHECK_INVARIANTS",
+            MessageFromApi::GetHealth => "GET_HEALTH",
+            MessageFromApi::RestoreMarket { .. } => "RESTORE_MARKET",
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -45,13 +100,21 @@ pub struct CancelOrderPayload {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GetDepthPayload {
     pub market: String,
+    /// Maximum levels per side
+    pub limit: Option<usize>,
+    /// Price bucket to aggregate levels into, e.g. 0.01 or 0.05
+    pub bucket: Option<Decimal>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GetQuotePayload {
     pub market: String,
     pub side: OrderSide,
-    pub quantity: Decimal,
+    /// Base units to buy or sell
+    pub quantity: Option<Decimal>,
+    /// Quote to spend (bid) or receive (ask), instead of `quantity`
+    #[serde(rename = "quoteAmount")]
+    pub quote_amount: Option<Decimal>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -68,9 +131,49 @@ pub struct GetUserBalancesPayload {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct OnRampPayload {
+pub struct GetUserPortfolioPayload {
+    #[serde(rename = "userId")]
+    pub user_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DepositPayload {
+    #[serde(rename = "userId")]
+    pub user_id: String,
+    pub asset: String,
+    pub amount: Decimal,
+    /// Chain transaction the deposit was observed in, used as the ledger
+    /// reference. Each one is credited once
+    pub reference: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WithdrawPayload {
+    #[serde(rename = "userId")]
+    pub user_id: String,
+    pub asset: String,
+    pub amount: Decimal,
+    pub destination: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WithdrawalActionPayload {
+    #[serde(rename = "withdrawalId")]
+    pub withdrawal_id: String,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetWithdrawalsPayload {
+    #[serde(rename = "userId")]
+    pub user_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FaucetPayload {
     #[serde(rename = "userId")]
     pub user_id: String,
+    pub asset: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -88,10 +191,40 @@ pub struct CreateMarketPayload {
     pub start_time: DateTime<Utc>,
     pub end_time: DateTime<Utc>,
     pub status: Status,
+    /// Seeds the market with an LMSR AMM funded with this much quote
+    pub amm_subsidy: Option<Decimal>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Status {
     Incoming,
     Ongoing,
+    Closed,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CloseMarketPayload {
+    pub market: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetAmmStatePayload {
+    pub market: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetBookTickerPayload {
+    /// All markets when unset
+    pub market: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetTickersPayload {
+    /// All markets when unset
+    pub market: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RestoreMarketPayload {
+    pub market: String,
 }
@@ -1,6 +1,9 @@
+use chrono::{DateTime, Utc};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
+use super::{BookTicker, OrderSide};
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum MessageToApi {
@@ -14,10 +17,32 @@ pub enum MessageToApi {
     Depth { payload: DepthPayload },
     #[serde(rename = "USER_BALANCES")]
     UserBalances { payload: UserBalancesPayload },
+    #[serde(rename = "USER_PORTFOLIO")]
+    UserPortfolio { payload: UserPortfolioPayload },
     #[serde(rename = "QUOTE")]
     Quote { payload: QuotePayload },
     #[serde(rename = "MARKET_CREATED")]
     MarketCreated { payload: MarketCreated },
+    #[serde(rename = "MARKET_CLOSED")]
+    MarketClosed { payload: MarketClosedPayload },
+    #[serde(rename = "WITHDRAWAL")]
+    Withdrawal { payload: Withdrawal },
+    #[serde(rename = "WITHDRAWALS")]
+    Withdrawals { payload: WithdrawalsPayload },
+    #[serde(rename = "BOOK_TICKER")]
+    BookTicker { payload: BookTickerPayload },
+    #[serde(rename = "TICKERS")]
+    Tickers { payload: TickersPayload },
+    #[serde(rename = "AMM_STATE")]
+    AmmState { payload: AmmStatePayload },
+    #[serde(rename = "RECONCILIATION")]
+    Reconciliation { payload: ReconciliationReport },
+    #[serde(rename = "HEALTH")]
+    Health { payload: HealthPayload },
+    #[serde(rename = "MARKET_RESTORED")]
+    MarketRestored { payload: MarketRestoredPayload },
+    #[serde(rename = "REJECTED")]
+    Rejected { payload: RejectedPayload },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -32,11 +57,24 @@ pub struct OrderCancelledPayload {
     pub message: Option<String>,
 }
 
+/// Sent instead of the normal reply when a request cannot be processed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RejectedPayload {
+    pub code: String,
+    pub message: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MarketCreated {
     pub message: Option<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MarketClosedPayload {
+    pub market: String,
+    pub end_time: DateTime<Utc>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct OpenOrders {
     #[serde(rename = "userId")]
@@ -46,8 +84,14 @@ pub struct OpenOrders {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DepthPayload {
+    /// `[price, quantity]`, best first
     pub bids: Vec<[String; 2]>,
     pub asks: Vec<[String; 2]>,
+    pub best_bid: Option<Decimal>,
+    pub best_ask: Option<Decimal>,
+    pub spread: Option<Decimal>,
+    /// Book sequence number the snapshot was taken at
+    pub sequence: u64,
 }
 
 // User Balances
@@ -63,10 +107,206 @@ pub struct Balance {
     pub locked_balance: Decimal,
 }
 
+// User Portfolio
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Position {
+    pub market: String,
+    pub net_quantity: Decimal,
+    pub avg_entry_price: Decimal,
+    pub realised_pnl: Decimal,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PositionSummary {
+    pub market: String,
+    pub net_quantity: Decimal,
+    pub avg_entry_price: Decimal,
+    pub mark_price: Option<Decimal>,
+    pub realised_pnl: Decimal,
+    pub unrealised_pnl: Decimal,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserPortfolioPayload {
+    pub positions: Vec<PositionSummary>,
+    pub total_realised_pnl: Decimal,
+    pub total_unrealised_pnl: Decimal,
+}
+
 // Quote
 #[derive(Debug, Serialize, Deserialize)]
 pub struct QuotePayload {
+    pub side: OrderSide,
     pub avg_price: Decimal,
+    /// Base units the book can fill
     pub quantity: Decimal,
+    /// Quote paid (bid) or received (ask) for `quantity`
     pub total_cost: Decimal,
+    /// False when the book is too thin for the full request
+    pub fully_filled: bool,
+    pub worst_price: Option<Decimal>,
+    pub mid_price: Option<Decimal>,
+    /// Relative distance of `avg_price` from `mid_price`
+    pub price_impact: Option<Decimal>,
+    pub levels_consumed: usize,
+    /// Zero until markets have a fee schedule
+    pub estimated_fee: Decimal,
+    /// Asset the fee is taken from, the one the taker receives
+    pub fee_asset: String,
+}
+
+// Withdrawals
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum WithdrawalStatus {
+    Pending,
+    /// Handed to the chain adapter, waiting for its receipt
+    Approved,
+    Completed,
+    Rejected,
+    /// Paid out on chain, but the ledger couldn't settle it. The hold stays
+    /// locked until an operator reconciles it
+    Unsettled,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Withdrawal {
+    pub id: String,
+    pub user_id: String,
+    pub asset: String,
+    pub amount: Decimal,
+    pub destination: String,
+    pub status: WithdrawalStatus,
+    pub tx_signature: Option<String>,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WithdrawalsPayload {
+    pub withdrawals: Vec<Withdrawal>,
+}
+
+// AMM
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AmmStatePayload {
+    pub market: String,
+    pub user_id: String,
+    pub subsidy: Decimal,
+    /// LMSR liquidity parameter `b`
+    pub liquidity: Decimal,
+    /// Current LMSR price of the outcome share
+    pub price: Decimal,
+    /// Outcome shares sold to takers net of buybacks
+    pub net_sold: Decimal,
+    pub quote_balance: Decimal,
+    /// Loss at resolution if the market settles against the AMM
+    pub worst_case_loss: Decimal,
+    pub subsidy_remaining: Decimal,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BookTickerPayload {
+    pub tickers: Vec<BookTicker>,
+}
+
+/// Rolling 24 hour statistics for one market. Prices are unset until the
+/// market has traded inside the window.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Ticker24h {
+    pub market: String,
+    pub open: Option<Decimal>,
+    pub high: Option<Decimal>,
+    pub low: Option<Decimal>,
+    pub last: Option<Decimal>,
+    pub volume: Decimal,
+    pub quote_volume: Decimal,
+    pub trade_count: u64,
+    pub price_change: Option<Decimal>,
+    pub price_change_percent: Option<Decimal>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TickersPayload {
+    pub tickers: Vec<Ticker24h>,
+}
+
+// Reconciliation
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReconciliationReport {
+    pub time: DateTime<Utc>,
+    pub markets_checked: usize,
+    pub users_checked: usize,
+    pub assets: Vec<AssetReconciliation>,
+    pub violations: Vec<InvariantViolation>,
+}
+
+/// Supply of one asset held by users against what came in from outside.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AssetReconciliation {
+    pub asset: String,
+    pub total_balance: Decimal,
+    pub total_locked: Decimal,
+    /// Deposits minus withdrawals
+    pub net_external: Decimal,
+    /// `total_balance - net_external`, zero when the books balance
+    pub difference: Decimal,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ViolationKind {
+    /// Balances of an asset don't add up to its net deposits
+    SupplyMismatch,
+    /// Locked balance differs from the holds of the user's live orders and
+    /// pending withdrawals
+    LockedMismatch,
+    NegativeBalance,
+    LockedExceedsBalance,
+    /// A market worker didn't answer the audit in time
+    MarketUnavailable,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InvariantViolation {
+    pub kind: ViolationKind,
+    pub user_id: Option<String>,
+    /// Asset, or market for `MARKET_UNAVAILABLE`
+    pub asset: String,
+    pub expected: Option<Decimal>,
+    pub actual: Option<Decimal>,
+}
+
+// Health
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum WorkerStatus {
+    Running,
+    /// The worker panicked or died, the market rejects every command
+    Halted,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MarketHealth {
+    pub market: String,
+    pub status: WorkerStatus,
+    pub reason: Option<String>,
+    pub halted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HealthPayload {
+    /// False while any market is halted or any withdrawal is unsettled
+    pub healthy: bool,
+    pub markets: Vec<MarketHealth>,
+    /// Withdrawals paid out on chain that the ledger couldn't settle
+    pub unsettled_withdrawals: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MarketRestoredPayload {
+    pub market: String,
+    /// Resting orders carried over from the halted worker
+    pub orders: usize,
 }
@@ -15,3 +15,51 @@ pub struct TradeData {
     pub price: Decimal,
     pub quantity: Decimal,
 }
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename = "LEDGER_ENTRIES")]
+pub struct LedgerEntriesPayload {
+    pub data: Vec<LedgerEntry>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LedgerEntry {
+    pub id: String,
+    pub transaction_id: String,
+    pub user_id: String,
+    pub asset: String,
+    pub account: LedgerAccount,
+    pub direction: EntryDirection,
+    pub amount: Decimal,
+    pub reason: EntryReason,
+    pub reference_id: String,
+    pub time: DateTime<Utc>,
+}
+
+/// `Available` and `Locked` are the two halves of a user's `Balance`, `External`
+/// is the contra account for funds entering or leaving the exchange.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum LedgerAccount {
+    Available,
+    Locked,
+    External,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum EntryDirection {
+    Debit,
+    Credit,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum EntryReason {
+    Trade,
+    Fee,
+    Deposit,
+    Withdrawal,
+    Hold,
+    Release,
+}
@@ -1,6 +1,10 @@
+use std::collections::HashMap;
+
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
+use crate::trade::RollingStats;
+
 pub mod message_from_api;
 pub use message_from_api::*;
 
@@ -13,6 +17,9 @@ pub use message_to_db::*;
 pub mod orderbook_worker_message;
 pub use orderbook_worker_message::*;
 
+pub mod user_event;
+pub use user_event::*;
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct IncomingMessage {
     pub client_id: String,
@@ -29,8 +36,51 @@ pub struct Order {
     pub timestamp: i64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct User {
     pub id: String,
-    pub balances: Vec<Balance>,
+    /// Keyed by asset ticker
+    pub balances: HashMap<String, Balance>,
+    /// Keyed by market
+    pub positions: HashMap<String, Position>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MarketSummary {
+    pub last_price: Option<Decimal>,
+    pub mid_price: Option<Decimal>,
+    pub book_ticker: BookTicker,
+    pub stats: RollingStats,
+}
+
+/// One match of a taker against a resting order, at the maker's price.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Fill {
+    pub trade_id: String,
+    pub maker_order_id: String,
+    pub maker_user_id: String,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    /// What is left of the maker order after this fill
+    pub maker_remaining: Decimal,
+}
+
+/// Top of book for one market.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct BookTicker {
+    pub market: String,
+    pub bid_price: Option<Decimal>,
+    pub bid_quantity: Option<Decimal>,
+    pub ask_price: Option<Decimal>,
+    pub ask_quantity: Option<Decimal>,
+}
+
+/// Incremental `depth@{market}` update covering book sequence numbers
+/// `first_update_id..=last_update_id`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DepthDiff {
+    pub first_update_id: u64,
+    pub last_update_id: u64,
+    pub bids: Vec<[String; 2]>,
+    pub asks: Vec<[String; 2]>,
 }
@@ -1,4 +1,7 @@
-use super::{CancelOrderPayload, CreateOrderPayload, GetOpenOrdersPayload, OrderSide};
+use std::sync::mpsc;
+
+use super::{CancelOrderPayload, CreateOrderPayload, GetOpenOrdersPayload, Order, OrderSide};
+use crate::trade::QuoteAmount;
 use rust_decimal::Decimal;
 
 pub enum OrderbookMessage {
@@ -13,6 +16,8 @@ pub enum OrderbookMessage {
     GetDepth {
         client_id: String,
         market: String,
+        limit: Option<usize>,
+        bucket: Option<Decimal>,
     },
     GetOpenOrders {
         client_id: String,
@@ -21,8 +26,39 @@ pub enum OrderbookMessage {
     GetQuote {
         client_id: String,
         market: String,
-        quantity: Decimal,
+        amount: QuoteAmount,
         side: OrderSide,
     },
+    GetAmmState {
+        client_id: String,
+    },
+    /// Replies with the resting orders, then blocks the worker until `resume`
+    /// is signalled or dropped so the invariant checker sees a still book.
+    Audit {
+        reply: mpsc::Sender<BookAudit>,
+        resume: mpsc::Receiver<()>,
+    },
     ShutDown,
 }
+
+impl OrderbookMessage {
+    /// Client waiting for a reply, if any.
+    pub fn client_id(&self) -> Option<&str> {
+        match self {
+            OrderbookMessage::CreateOrder { client_id, .. }
+            | OrderbookMessage::CancelOrder { client_id, .. }
+            | OrderbookMessage::GetDepth { client_id, .. }
+            | OrderbookMessage::GetOpenOrders { client_id, .. }
+            | OrderbookMessage::GetQuote { client_id, .. }
+            | OrderbookMessage::GetAmmState { client_id } => Some(client_id),
+            OrderbookMessage::Audit { .. } | OrderbookMessage::ShutDown => None,
+        }
+    }
+}
+
+pub struct BookAudit {
+    pub market: String,
+    pub base_asset: String,
+    pub quote_asset: String,
+    pub orders: Vec<Order>,
+}
@@ -0,0 +1,61 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use super::{EntryReason, OrderSide};
+
+/// Events on a user's private `user@{user_id}` stream.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "e")]
+pub enum UserEvent {
+    #[serde(rename = "orderUpdate")]
+    OrderUpdate(OrderUpdate),
+    #[serde(rename = "balanceUpdate")]
+    BalanceUpdate(BalanceUpdate),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum OrderStatus {
+    New,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+}
+
+impl OrderStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::New => "NEW",
+            OrderStatus::PartiallyFilled => "PARTIALLY_FILLED",
+            OrderStatus::Filled => "FILLED",
+            OrderStatus::Cancelled => "CANCELLED",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OrderUpdate {
+    pub order_id: String,
+    pub market: String,
+    pub side: OrderSide,
+    pub price: Decimal,
+    pub status: OrderStatus,
+    /// Quantity still open after this event
+    pub remaining_quantity: Decimal,
+    /// Set on fills only
+    pub trade_id: Option<String>,
+    pub last_fill_price: Option<Decimal>,
+    pub last_fill_quantity: Option<Decimal>,
+    pub time: i64,
+}
+
+/// A user's balance in one asset right after a ledger transaction touched it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BalanceUpdate {
+    pub asset: String,
+    pub balance: Decimal,
+    pub locked_balance: Decimal,
+    pub reason: EntryReason,
+    pub reference_id: String,
+    pub time: i64,
+}
@@ -0,0 +1,135 @@
+use std::{thread, time::Duration};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use crossbeam_channel::{Receiver, Sender};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::models::Withdrawal;
+
+/// Sends approved withdrawals on-chain. The engine only talks to this trait so
+/// the Solana integration can be swapped for `MockChainAdapter` in tests and
+/// local setups.
+pub trait ChainAdapter: Send {
+    fn name(&self) -> &str;
+
+    /// Submits the withdrawal and returns the transaction signature.
+    fn submit_withdrawal(&mut self, withdrawal: &Withdrawal) -> Result<String>;
+}
+
+/// Accepts every withdrawal with a fake signature, or fails all of them with
+/// `fail_with` when set.
+#[derive(Debug, Default)]
+pub struct MockChainAdapter {
+    pub submitted: Vec<Withdrawal>,
+    pub fail_with: Option<String>,
+}
+
+impl MockChainAdapter {
+    pub fn new() -> Self {
+        MockChainAdapter::default()
+    }
+}
+
+impl ChainAdapter for MockChainAdapter {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    fn submit_withdrawal(&mut self, withdrawal: &Withdrawal) -> Result<String> {
+        if let Some(reason) = &self.fail_with {
+            return Err(anyhow!(reason.clone()));
+        }
+
+        self.submitted.push(withdrawal.clone());
+        Ok(format!("mock-{}", Uuid::new_v4()))
+    }
+}
+
+/// What the chain adapter made of one approved withdrawal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainReceipt {
+    pub withdrawal_id: String,
+    pub time: DateTime<Utc>,
+    /// Transaction signature, or why the adapter failed the withdrawal
+    pub outcome: Result<String, String>,
+}
+
+/// Runs the chain adapter on a thread of its own, so a slow chain never holds
+/// up the engine loop. Each submitted withdrawal comes back as one receipt.
+pub struct ChainSubmitter {
+    name: String,
+    withdrawals: Option<Sender<Withdrawal>>,
+    receipts: Receiver<ChainReceipt>,
+    thread_handle: Option<thread::JoinHandle<()>>,
+}
+
+impl ChainSubmitter {
+    pub fn spawn(mut adapter: Box<dyn ChainAdapter>) -> Self {
+        let name = adapter.name().to_string();
+        let (withdrawal_tx, withdrawal_rx) = crossbeam_channel::unbounded::<Withdrawal>();
+        let (receipt_tx, receipt_rx) = crossbeam_channel::unbounded();
+
+        let thread_handle = thread::spawn(move || {
+            info!(adapter = adapter.name(), "Started chain adapter thread");
+            for withdrawal in withdrawal_rx {
+                let outcome = adapter
+                    .submit_withdrawal(&withdrawal)
+                    .map_err(|e| e.to_string());
+                let receipt = ChainReceipt {
+                    withdrawal_id: withdrawal.id,
+                    time: Utc::now(),
+                    outcome,
+                };
+                if receipt_tx.send(receipt).is_err() {
+                    break;
+                }
+            }
+        });
+
+        ChainSubmitter {
+            name,
+            withdrawals: Some(withdrawal_tx),
+            receipts: receipt_rx,
+            thread_handle: Some(thread_handle),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn submit(&self, withdrawal: Withdrawal) {
+        if let Some(withdrawals) = &self.withdrawals {
+            let _ = withdrawals.send(withdrawal);
+        }
+    }
+
+    /// Receipts that came back since the last call, without waiting.
+    pub fn receipts(&self) -> Vec<ChainReceipt> {
+        self.receipts.try_iter().collect()
+    }
+
+    /// Blocks until a receipt comes back or `timeout` passes.
+    pub fn wait_for_receipt(&self, timeout: Duration) -> Option<ChainReceipt> {
+        self.receipts.recv_timeout(timeout).ok()
+    }
+
+    /// Stops taking withdrawals and waits for the ones already submitted,
+    /// returning their receipts.
+    pub fn finish(&mut self) -> Vec<ChainReceipt> {
+        self.withdrawals = None;
+        if let Some(handle) = self.thread_handle.take() {
+            let _ = handle.join();
+        }
+        self.receipts()
+    }
+}
+
+impl Drop for ChainSubmitter {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
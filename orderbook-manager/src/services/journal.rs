@@ -0,0 +1,188 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use super::{ChainReceipt, MarketDefinition, MessageSink, SinkTarget};
+
+/// One line of the command journal. Requests are kept as the raw text popped
+/// from the queue so malformed ones replay exactly as they arrived.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum JournalEntry {
+    Request {
+        sequence: u64,
+        time: DateTime<Utc>,
+        message: String,
+    },
+    Reply {
+        sequence: u64,
+        time: DateTime<Utc>,
+        client_id: String,
+        message: String,
+    },
+    /// Market definitions as loaded from Postgres, recorded when they change
+    Markets {
+        sequence: u64,
+        time: DateTime<Utc>,
+        markets: Vec<MarketDefinition>,
+    },
+    /// The chain adapter's answer to an approved withdrawal, as settled
+    ChainReceipt {
+        sequence: u64,
+        time: DateTime<Utc>,
+        receipt: ChainReceipt,
+    },
+}
+
+impl JournalEntry {
+    pub fn sequence(&self) -> u64 {
+        match self {
+            JournalEntry::Request { sequence, .. }
+            | JournalEntry::Reply { sequence, .. }
+            | JournalEntry::Markets { sequence, .. }
+            | JournalEntry::ChainReceipt { sequence, .. } => *sequence,
+        }
+    }
+
+    pub fn time(&self) -> DateTime<Utc> {
+        match self {
+            JournalEntry::Request { time, .. }
+            | JournalEntry::Reply { time, .. }
+            | JournalEntry::Markets { time, .. }
+            | JournalEntry::ChainReceipt { time, .. } => *time,
+        }
+    }
+}
+
+struct JournalWriter {
+    file: File,
+    next_sequence: u64,
+    last_markets: Option<Vec<MarketDefinition>>,
+}
+
+/// Append-only JSON lines log of everything the engine was asked and answered,
+/// enough for the `replay` binary to rebuild its state offline. Replies are
+/// captured by installing the journal as the `RedisManager` tap.
+pub struct CommandJournal {
+    writer: Mutex<JournalWriter>,
+}
+
+impl CommandJournal {
+    /// Opens `path` for appending, continuing the sequence of an existing file.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let next_sequence = if path.exists() {
+            read_journal(path)?
+                .last()
+                .map(|entry| entry.sequence() + 1)
+                .unwrap_or(0)
+        } else {
+            0
+        };
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open command journal {}", path.display()))?;
+
+        Ok(CommandJournal {
+            writer: Mutex::new(JournalWriter {
+                file,
+                next_sequence,
+                last_markets: None,
+            }),
+        })
+    }
+
+    /// Sequence of the last entry written, `None` while the journal is empty.
+    pub fn last_sequence(&self) -> Option<u64> {
+        self.writer.lock().unwrap().next_sequence.checked_sub(1)
+    }
+
+    pub fn record_request(&self, message: &str) {
+        self.append(|sequence, time| JournalEntry::Request {
+            sequence,
+            time,
+            message: message.to_string(),
+        });
+    }
+
+    /// Records `markets` unless they are the same as the last recorded set.
+    pub fn record_markets(&self, markets: &[MarketDefinition]) {
+        {
+            let mut writer = self.writer.lock().unwrap();
+            if writer.last_markets.as_deref() == Some(markets) {
+                return;
+            }
+            writer.last_markets = Some(markets.to_vec());
+        }
+        self.append(|sequence, time| JournalEntry::Markets {
+            sequence,
+            time,
+            markets: markets.to_vec(),
+        });
+    }
+
+    pub fn record_chain_receipt(&self, receipt: &ChainReceipt) {
+        self.append(|sequence, time| JournalEntry::ChainReceipt {
+            sequence,
+            time,
+            receipt: receipt.clone(),
+        });
+    }
+
+    fn append(&self, entry: impl FnOnce(u64, DateTime<Utc>) -> JournalEntry) {
+        let mut writer = self.writer.lock().unwrap();
+        let entry = entry(writer.next_sequence, Utc::now());
+        writer.next_sequence += 1;
+
+        let mut line = serde_json::to_string(&entry).unwrap();
+        line.push('\n');
+        // Flushed per entry, the journal matters most when the engine dies
+        if let Err(e) = writer
+            .file
+            .write_all(line.as_bytes())
+            .and_then(|_| writer.file.flush())
+        {
+            error!("Failed to write command journal: {}", e);
+        }
+    }
+}
+
+impl MessageSink for CommandJournal {
+    fn deliver(&self, target: SinkTarget<'_>, payload: String) {
+        if let SinkTarget::Client(client_id) = target {
+            self.append(|sequence, time| JournalEntry::Reply {
+                sequence,
+                time,
+                client_id: client_id.to_string(),
+                message: payload,
+            });
+        }
+    }
+}
+
+pub fn read_journal(path: impl AsRef<Path>) -> Result<Vec<JournalEntry>> {
+    let path = path.as_ref();
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open command journal {}", path.display()))?;
+
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.as_ref().is_ok_and(|l| l.trim().is_empty()))
+        .map(|(number, line)| {
+            serde_json::from_str(&line?)
+                .with_context(|| format!("Bad journal entry on line {}", number + 1))
+        })
+        .collect()
+}
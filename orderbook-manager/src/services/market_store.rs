@@ -0,0 +1,56 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use postgres::{Client, NoTls};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+/// A row of the http-server `markets` table, as far as the engine cares.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MarketDefinition {
+    pub base_asset: String,
+    pub quote_asset: String,
+    pub end_time: DateTime<Utc>,
+    pub status: Option<String>,
+    pub amm_subsidy: Option<Decimal>,
+}
+
+impl MarketDefinition {
+    pub fn market(&self) -> String {
+        format!("{}_{}", self.base_asset, self.quote_asset)
+    }
+
+    pub fn is_closed(&self, now: DateTime<Utc>) -> bool {
+        self.status.as_deref() == Some("closed") || now >= self.end_time
+    }
+}
+
+/// Read-only access to the market definitions owned by http-server.
+pub struct MarketStore {
+    client: Client,
+}
+
+impl MarketStore {
+    pub fn connect(database_url: &str) -> Result<Self> {
+        let client = Client::connect(database_url, NoTls)?;
+        Ok(MarketStore { client })
+    }
+
+    pub fn load(&mut self) -> Result<Vec<MarketDefinition>> {
+        let rows = self.client.query(
+            "SELECT base_asset, quote_asset, end_time, status, amm_subsidy
+             FROM markets ORDER BY created_at",
+            &[],
+        )?;
+
+        Ok(rows
+            .iter()
+            .map(|row| MarketDefinition {
+                base_asset: row.get("base_asset"),
+                quote_asset: row.get("quote_asset"),
+                end_time: row.get("end_time"),
+                status: row.get("status"),
+                amm_subsidy: row.get("amm_subsidy"),
+            })
+            .collect())
+    }
+}
@@ -1,2 +1,14 @@
 pub mod redis_manager;
 pub use redis_manager::*;
+
+pub mod chain_adapter;
+pub use chain_adapter::*;
+
+pub mod market_store;
+pub use market_store::*;
+
+pub mod sink;
+pub use sink::*;
+
+pub mod journal;
+pub use journal::*;
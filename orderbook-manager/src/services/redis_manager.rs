@@ -1,8 +1,13 @@
+use std::sync::{Arc, OnceLock, RwLock};
+
 use lazy_static::lazy_static;
 use redis::{Client, Commands, Connection, RedisResult};
+use serde::Serialize;
 use serde_json::Value;
 
-use crate::models::{AddTradePayload, MessageToApi};
+use crate::models::MessageToApi;
+
+use super::{MessageSink, SinkTarget};
 
 lazy_static! {
     static ref REDIS_MANAGER: RedisManager = RedisManager::new();
@@ -10,35 +15,86 @@ lazy_static! {
 
 pub struct RedisManager {
     client: Client,
+    sink: OnceLock<Arc<dyn MessageSink>>,
+    tap: OnceLock<Arc<dyn MessageSink>>,
+    diversion: RwLock<Option<Arc<dyn MessageSink>>>,
+}
+
+impl Default for RedisManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RedisManager {
     pub fn new() -> Self {
         let client = redis::Client::open("redis://127.0.0.1/").unwrap();
-        RedisManager { client }
+        RedisManager {
+            client,
+            sink: OnceLock::new(),
+            tap: OnceLock::new(),
+            diversion: RwLock::new(None),
+        }
     }
 
     pub fn instance() -> &'static RedisManager {
         &REDIS_MANAGER
     }
 
+    /// Sends every later message to `sink` instead of Redis. Can only be done
+    /// once per process, the sink is handed back if one is already installed.
+    pub fn install_sink(&self, sink: Arc<dyn MessageSink>) -> Result<(), Arc<dyn MessageSink>> {
+        self.sink.set(sink)
+    }
+
+    /// Sends a copy of every later message to `tap` as well, e.g. the command
+    /// journal. Can only be done once per process, like `install_sink`.
+    pub fn install_tap(&self, tap: Arc<dyn MessageSink>) -> Result<(), Arc<dyn MessageSink>> {
+        self.tap.set(tap)
+    }
+
+    /// Sends every later message only to `sink`, past Redis, the installed
+    /// sink and the tap, until called with `None`. Recovery replays requests
+    /// whose messages already went out this way.
+    pub fn divert(&self, sink: Option<Arc<dyn MessageSink>>) {
+        *self.diversion.write().unwrap() = sink;
+    }
+
     pub fn get_connection(&self) -> RedisResult<Connection> {
         self.client.get_connection()
     }
 
-    pub fn send_to_api(&self, client_id: &str, message: &MessageToApi) -> RedisResult<()> {
+    fn deliver(&self, target: SinkTarget<'_>, payload: String) -> RedisResult<()> {
+        if let Some(diversion) = self.diversion.read().unwrap().as_ref() {
+            diversion.deliver(target, payload);
+            return Ok(());
+        }
+        if let Some(tap) = self.tap.get() {
+            tap.deliver(target, payload.clone());
+        }
+        if let Some(sink) = self.sink.get() {
+            sink.deliver(target, payload);
+            return Ok(());
+        }
+
         let mut conn = self.get_connection()?;
+        match target {
+            SinkTarget::Client(client_id) => conn.publish(client_id, payload),
+            SinkTarget::Channel(channel) => conn.publish(channel, payload),
+            SinkTarget::Db => conn.lpush("db_processor", payload),
+        }
+    }
+
+    pub fn send_to_api(&self, client_id: &str, message: &MessageToApi) -> RedisResult<()> {
         let message_json = serde_json::to_string(message).unwrap();
-        conn.publish(client_id, message_json)
+        self.deliver(SinkTarget::Client(client_id), message_json)
     }
 
     pub fn publish_message(&self, channel: &str, message: &Value) -> RedisResult<()> {
-        let mut conn = self.get_connection()?;
-        conn.publish(channel, message.to_string())
+        self.deliver(SinkTarget::Channel(channel), message.to_string())
     }
 
-    pub fn push_message_to_db(&self, message: &AddTradePayload) -> RedisResult<()> {
-        let mut conn = self.get_connection()?;
-        conn.lpush("db_processor", serde_json::to_string(message).unwrap())
+    pub fn push_message_to_db<T: Serialize>(&self, message: &T) -> RedisResult<()> {
+        self.deliver(SinkTarget::Db, serde_json::to_string(message).unwrap())
     }
 }
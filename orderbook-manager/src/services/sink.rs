@@ -0,0 +1,137 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::MessageToApi;
+
+/// Where an outgoing engine message is headed.
+#[derive(Debug, Clone, Copy)]
+pub enum SinkTarget<'a> {
+    /// Reply to the request waiting on `client_id`
+    Client(&'a str),
+    /// Pub/sub stream forwarded by wss
+    Channel(&'a str),
+    /// db-processor queue
+    Db,
+}
+
+impl SinkTarget<'_> {
+    pub fn name(&self) -> &str {
+        match self {
+            SinkTarget::Client(client_id) => client_id,
+            SinkTarget::Channel(channel) => channel,
+            SinkTarget::Db => "db_processor",
+        }
+    }
+}
+
+/// Receives everything the engine sends out. `RedisManager` delivers to Redis
+/// unless a sink is installed, which lets tests and benches run the engine
+/// without a Redis server.
+pub trait MessageSink: Send + Sync {
+    fn deliver(&self, target: SinkTarget<'_>, payload: String);
+}
+
+/// One message as delivered, for sinks that keep them.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SinkMessage {
+    /// Client id, stream name, or `db_processor`
+    pub target: String,
+    pub payload: String,
+}
+
+/// Keeps client replies until they are read and only counts stream and db
+/// messages, so long runs stay bounded in memory. A recording sink also keeps
+/// every message in delivery order.
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    replies: Mutex<HashMap<String, VecDeque<String>>>,
+    reply_ready: Condvar,
+    published: AtomicU64,
+    persisted: AtomicU64,
+    recorded: Option<Mutex<Vec<SinkMessage>>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        MemorySink::default()
+    }
+
+    pub fn recording() -> Self {
+        MemorySink {
+            recorded: Some(Mutex::new(Vec::new())),
+            ..Default::default()
+        }
+    }
+
+    /// Everything recorded since the last call, empty unless recording.
+    pub fn take_recorded(&self) -> Vec<SinkMessage> {
+        self.recorded
+            .as_ref()
+            .map(|recorded| std::mem::take(&mut *recorded.lock().unwrap()))
+            .unwrap_or_default()
+    }
+
+    /// Blocks until a reply for `client_id` arrives or `timeout` passes.
+    pub fn wait_for_reply(&self, client_id: &str, timeout: Duration) -> Option<MessageToApi> {
+        let deadline = Instant::now() + timeout;
+        let mut replies = self.replies.lock().unwrap();
+        loop {
+            if let Some(payload) = replies.get_mut(client_id).and_then(|q| q.pop_front()) {
+                if replies.get(client_id).is_some_and(|q| q.is_empty()) {
+                    replies.remove(client_id);
+                }
+                return serde_json::from_str(&payload).ok();
+            }
+
+            let remaining = deadline.checked_duration_since(Instant::now())?;
+            replies = self.reply_ready.wait_timeout(replies, remaining).unwrap().0;
+        }
+    }
+
+    /// Messages published on streams so far.
+    pub fn published(&self) -> u64 {
+        self.published.load(Ordering::Relaxed)
+    }
+
+    /// Messages queued for db-processor so far.
+    pub fn persisted(&self) -> u64 {
+        self.persisted.load(Ordering::Relaxed)
+    }
+}
+
+impl MessageSink for MemorySink {
+    fn deliver(&self, target: SinkTarget<'_>, payload: String) {
+        if let Some(recorded) = &self.recorded {
+            recorded.lock().unwrap().push(SinkMessage {
+                target: target.name().to_string(),
+                payload: payload.clone(),
+            });
+        }
+
+        match target {
+            SinkTarget::Client(client_id) => {
+                self.replies
+                    .lock()
+                    .unwrap()
+                    .entry(client_id.to_string())
+                    .or_default()
+                    .push_back(payload);
+                self.reply_ready.notify_all();
+            }
+            SinkTarget::Channel(_) => {
+                self.published.fetch_add(1, Ordering::Relaxed);
+            }
+            SinkTarget::Db => {
+                self.persisted.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use rust_decimal::Decimal;
+
+use crate::models::{Balance, User};
+
+use super::LockExt;
+
+impl User {
+    pub fn new(id: &str) -> Self {
+        User {
+            id: id.to_string(),
+            balances: HashMap::new(),
+            positions: HashMap::new(),
+        }
+    }
+
+    pub fn balance_list(&self) -> Vec<Balance> {
+        let mut balances: Vec<Balance> = self.balances.values().cloned().collect();
+        balances.sort_by(|a, b| a.ticker.cmp(&b.ticker));
+        balances
+    }
+}
+
+/// Users keyed by id, each behind its own lock. The map lock is only held long
+/// enough to clone an account handle, so workers for different markets only
+/// contend when they touch the same user.
+#[derive(Debug, Default)]
+pub struct AccountStore {
+    accounts: RwLock<HashMap<String, Arc<Mutex<User>>>>,
+    /// Net amount of each asset that entered through the external account,
+    /// which all balances together must add up to
+    external_flows: Mutex<HashMap<String, Decimal>>,
+}
+
+impl AccountStore {
+    pub fn new() -> Self {
+        AccountStore::default()
+    }
+
+    pub fn get(&self, user_id: &str) -> Option<Arc<Mutex<User>>> {
+        self.accounts.read().unwrap().get(user_id).cloned()
+    }
+
+    pub fn get_or_create(&self, user_id: &str) -> Arc<Mutex<User>> {
+        if let Some(account) = self.get(user_id) {
+            return account;
+        }
+
+        let mut accounts = self.accounts.write().unwrap();
+        accounts
+            .entry(user_id.to_string())
+            .or_insert_with(|| Arc::new(Mutex::new(User::new(user_id))))
+            .clone()
+    }
+
+    pub fn contains(&self, user_id: &str) -> bool {
+        self.accounts.read().unwrap().contains_key(user_id)
+    }
+
+    /// Returns the handles for `user_ids` sorted by id. Callers that need
+    /// several accounts at once must lock them in this order to avoid
+    /// deadlocking against another worker.
+    pub fn get_many(&self, user_ids: &[&str]) -> Option<Vec<(String, Arc<Mutex<User>>)>> {
+        let mut ids: Vec<&str> = user_ids.to_vec();
+        ids.sort_unstable();
+        ids.dedup();
+
+        let accounts = self.accounts.read().unwrap();
+        ids.into_iter()
+            .map(|id| accounts.get(id).map(|a| (id.to_string(), a.clone())))
+            .collect()
+    }
+
+    pub fn user_ids(&self) -> Vec<String> {
+        self.accounts.read().unwrap().keys().cloned().collect()
+    }
+
+    pub fn record_external_flow(&self, asset: &str, amount: Decimal) {
+        *self
+            .external_flows
+            .lock_or_recover()
+            .entry(asset.to_string())
+            .or_insert(Decimal::ZERO) += amount;
+    }
+
+    pub fn external_flows(&self) -> HashMap<String, Decimal> {
+        self.external_flows.lock_or_recover().clone()
+    }
+}
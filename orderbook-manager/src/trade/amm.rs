@@ -0,0 +1,452 @@
+use chrono::Utc;
+use rust_decimal::{
+    prelude::{FromPrimitive, ToPrimitive},
+    Decimal,
+};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::models::{AmmStatePayload, Order, OrderSide};
+
+use super::{AccountStore, EngineError, Ledger, LockExt, Orderbook, Transaction};
+
+/// Ladder shape shared by every AMM, read from `AMM_LEVELS`, `AMM_LEVEL_SIZE`
+/// and `AMM_TICK`.
+#[derive(Debug, Clone)]
+pub struct AmmConfig {
+    pub levels: usize,
+    pub level_size: Decimal,
+    pub tick: Decimal,
+}
+
+impl AmmConfig {
+    pub fn from_env() -> Self {
+        let levels = std::env::var("AMM_LEVELS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5);
+        let level_size = std::env::var("AMM_LEVEL_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(Decimal::new(10, 0));
+        let tick = std::env::var("AMM_TICK")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(Decimal::new(1, 2));
+
+        AmmConfig {
+            levels,
+            level_size,
+            tick,
+        }
+    }
+}
+
+/// Logarithmic market scoring rule market maker for a binary outcome market,
+/// where the base asset is the outcome share and pays out 1 quote if it wins.
+///
+/// The AMM trades through its own account and rests a ladder of real orders
+/// priced off the LMSR cost function, so takers match it like any other maker.
+/// With liquidity `b = subsidy / ln 2` the subsidy covers every bid it can
+/// post and bounds its worst case loss at resolution.
+pub struct LmsrAmm {
+    pub user_id: String,
+    pub subsidy: Decimal,
+    pub liquidity: f64,
+    /// Outcome shares minted into the AMM account to back its asks
+    pub minted: Decimal,
+    config: AmmConfig,
+    quoted_inventory: Option<Decimal>,
+}
+
+impl LmsrAmm {
+    /// Creates the AMM account for `orderbook` and funds it with the subsidy
+    /// plus enough shares to quote asks up to the top tick. An account restored
+    /// from a snapshot was funded before and is taken over as it is.
+    pub fn new(
+        orderbook: &Orderbook,
+        subsidy: Decimal,
+        config: AmmConfig,
+        accounts: &AccountStore,
+    ) -> Result<Self, EngineError> {
+        let subsidy_f64 = subsidy.to_f64().filter(|s| *s > 0.0).ok_or_else(|| {
+            EngineError::InvalidRequest(String::from("AMM subsidy must be positive"))
+        })?;
+        let tick = config.tick.to_f64().unwrap_or(0.01);
+        if !(tick > 0.0 && tick < 0.5) {
+            return Err(EngineError::InvalidRequest(String::from(
+                "AMM tick must be between 0 and 0.5",
+            )));
+        }
+
+        let liquidity = subsidy_f64 / std::f64::consts::LN_2;
+        let max_shares = liquidity * ((1.0 - tick) / tick).ln();
+        let minted = Decimal::from_f64(max_shares.ceil())
+            .ok_or_else(|| EngineError::Internal(String::from("AMM inventory overflow")))?;
+
+        let user_id = format!("amm:{}", orderbook.market());
+        if accounts.contains(&user_id) {
+            info!(user_id, %subsidy, liquidity, %minted, "AMM account restored");
+        } else {
+            accounts.get_or_create(&user_id);
+            let reference = format!("amm-subsidy-{}", orderbook.market());
+            Ledger::post(
+                accounts,
+                Transaction::deposit(&user_id, &orderbook.quote_asset, subsidy, &reference),
+            )?;
+            Ledger::post(
+                accounts,
+                Transaction::deposit(&user_id, &orderbook.base_asset, minted, &reference),
+            )?;
+            info!(user_id, %subsidy, liquidity, %minted, "AMM funded");
+        }
+
+        Ok(LmsrAmm {
+            user_id,
+            subsidy,
+            liquidity,
+            minted,
+            config,
+            quoted_inventory: None,
+        })
+    }
+
+    /// `b * ln(1 + e^(q/b))`, written to stay finite for large `|q|`.
+    fn cost(&self, q: f64) -> f64 {
+        let x = q / self.liquidity;
+        self.liquidity * (x.max(0.0) + (-x.abs()).exp().ln_1p())
+    }
+
+    fn price(&self, q: f64) -> f64 {
+        1.0 / (1.0 + (-q / self.liquidity).exp())
+    }
+
+    /// Shares the AMM has sold net of the ones it bought back.
+    fn net_sold(&self, orderbook: &Orderbook, accounts: &AccountStore) -> Decimal {
+        self.minted - self.holdings(&orderbook.base_asset, accounts)
+    }
+
+    fn holdings(&self, asset: &str, accounts: &AccountStore) -> Decimal {
+        accounts
+            .get(&self.user_id)
+            .and_then(|user| {
+                user.lock_or_recover()
+                    .balances
+                    .get(asset)
+                    .map(|b| b.balance)
+            })
+            .unwrap_or(Decimal::ZERO)
+    }
+
+    /// Replaces the AMM's resting orders with a fresh ladder around its current
+    /// inventory. Does nothing if the inventory hasn't moved since the last
+    /// quote, so the ledger only sees hold/release churn after AMM fills.
+    pub fn requote(&mut self, orderbook: &mut Orderbook, accounts: &AccountStore) {
+        let inventory = self.holdings(&orderbook.base_asset, accounts);
+        if self.quoted_inventory == Some(inventory) {
+            return;
+        }
+
+        self.cancel_all(orderbook, accounts);
+
+        let q = (self.minted - inventory).to_f64().unwrap_or(0.0);
+        let size = self.config.level_size;
+        let size_f64 = size.to_f64().unwrap_or(0.0);
+        if size_f64 <= 0.0 {
+            return;
+        }
+
+        let best_bid = orderbook.best_bid();
+        for k in 0..self.config.levels {
+            let lo = q + k as f64 * size_f64;
+            let avg = (self.cost(lo + size_f64) - self.cost(lo)) / size_f64;
+            let Some(price) = self.round_to_tick(avg, true) else {
+                break;
+            };
+            if price >= Decimal::ONE {
+                break;
+            }
+            // Only rest passively, a crossing level would trade against a user bid
+            if best_bid.is_some_and(|bid| bid >= price) {
+                continue;
+            }
+            if !self.place(orderbook, accounts, OrderSide::Ask, price, size) {
+                break;
+            }
+        }
+
+        let best_ask = orderbook
+            .asks
+            .iter()
+            .find(|o| o.user_id != self.user_id)
+            .map(|o| o.price);
+        for k in 0..self.config.levels {
+            let hi = q - k as f64 * size_f64;
+            let avg = (self.cost(hi) - self.cost(hi - size_f64)) / size_f64;
+            let Some(price) = self.round_to_tick(avg, false) else {
+                break;
+            };
+            if price <= Decimal::ZERO {
+                break;
+            }
+            if best_ask.is_some_and(|ask| ask <= price) {
+                continue;
+            }
+            if !self.place(orderbook, accounts, OrderSide::Bid, price, size) {
+                break;
+            }
+        }
+
+        self.quoted_inventory = Some(inventory);
+    }
+
+    fn round_to_tick(&self, price: f64, up: bool) -> Option<Decimal> {
+        let ticks = Decimal::from_f64(price)? / self.config.tick;
+        let ticks = if up { ticks.ceil() } else { ticks.floor() };
+        Some(ticks * self.config.tick)
+    }
+
+    fn place(
+        &self,
+        orderbook: &mut Orderbook,
+        accounts: &AccountStore,
+        side: OrderSide,
+        price: Decimal,
+        quantity: Decimal,
+    ) -> bool {
+        let order_id = Uuid::new_v4().to_string();
+        let (hold_asset, hold_amount) = match side {
+            OrderSide::Bid => (&orderbook.quote_asset, price * quantity),
+            OrderSide::Ask => (&orderbook.base_asset, quantity),
+        };
+
+        let transaction = Transaction::hold(&self.user_id, hold_asset, hold_amount, &order_id);
+        if let Err(e) = Ledger::post(accounts, transaction) {
+            error!(
+                user_id = self.user_id,
+                "Failed to hold funds for AMM order: {}", e
+            );
+            return false;
+        }
+
+        orderbook.insert_order(Order {
+            id: order_id,
+            user_id: self.user_id.clone(),
+            price,
+            quantity,
+            side,
+            timestamp: Utc::now().timestamp(),
+        });
+        true
+    }
+
+    fn cancel_all(&self, orderbook: &mut Orderbook, accounts: &AccountStore) {
+        for order in orderbook.remove_user_orders(&self.user_id) {
+            let (asset, amount) = match order.side {
+                OrderSide::Bid => (&orderbook.quote_asset, order.price * order.quantity),
+                OrderSide::Ask => (&orderbook.base_asset, order.quantity),
+            };
+            let transaction = Transaction::release(&self.user_id, asset, amount, &order.id);
+            if let Err(e) = Ledger::post(accounts, transaction) {
+                error!(order_id = order.id, "Failed to release AMM order: {}", e);
+            }
+        }
+    }
+
+    pub fn state(&self, orderbook: &Orderbook, accounts: &AccountStore) -> AmmStatePayload {
+        let net_sold = self.net_sold(orderbook, accounts);
+        let quote_balance = self.holdings(&orderbook.quote_asset, accounts);
+
+        // Quote taken in from takers, net of what the AMM paid for buybacks
+        let cash_flow = quote_balance - self.subsidy;
+        let loss_if_yes = net_sold - cash_flow;
+        let loss_if_no = -cash_flow;
+        let worst_case_loss = loss_if_yes.max(loss_if_no).max(Decimal::ZERO);
+
+        AmmStatePayload {
+            market: orderbook.market(),
+            user_id: self.user_id.clone(),
+            subsidy: self.subsidy,
+            liquidity: Decimal::from_f64(self.liquidity)
+                .unwrap_or_default()
+                .round_dp(8),
+            price: Decimal::from_f64(self.price(net_sold.to_f64().unwrap_or(0.0)))
+                .unwrap_or_default()
+                .round_dp(8),
+            net_sold,
+            quote_balance,
+            worst_case_loss,
+            subsidy_remaining: self.subsidy - worst_case_loss,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rust_decimal_macros::dec;
+
+    use crate::{
+        models::{Balance, CreateOrderPayload},
+        services::{MemorySink, RedisManager},
+    };
+
+    use super::*;
+
+    const TAKER: &str = "taker";
+
+    fn config() -> AmmConfig {
+        AmmConfig {
+            levels: 5,
+            level_size: dec!(10),
+            tick: dec!(0.01),
+        }
+    }
+
+    fn setup(subsidy: Decimal) -> (Orderbook, AccountStore, LmsrAmm) {
+        let _ = RedisManager::instance().install_sink(Arc::new(MemorySink::new()));
+        let accounts = AccountStore::new();
+        let orderbook = Orderbook::new(String::from("YES"), String::from("USDC"));
+        let amm = LmsrAmm::new(&orderbook, subsidy, config(), &accounts).unwrap();
+        (orderbook, accounts, amm)
+    }
+
+    /// Sends a taker order through the book the way the worker does, holding
+    /// the taker's funds first and giving back whatever wasn't used.
+    fn take(
+        orderbook: &mut Orderbook,
+        accounts: &AccountStore,
+        side: OrderSide,
+        price: Decimal,
+        quantity: Decimal,
+    ) {
+        let (asset, amount) = match side {
+            OrderSide::Bid => (orderbook.quote_asset.clone(), price * quantity),
+            OrderSide::Ask => (orderbook.base_asset.clone(), quantity),
+        };
+        accounts
+            .get_or_create(TAKER)
+            .lock()
+            .unwrap()
+            .balances
+            .insert(
+                asset.clone(),
+                Balance {
+                    ticker: asset.clone(),
+                    balance: amount,
+                    locked_balance: amount,
+                },
+            );
+        let order = CreateOrderPayload {
+            user_id: TAKER.to_string(),
+            market: orderbook.market(),
+            price,
+            quantity,
+            side,
+        };
+        let matched = orderbook.fill_orders(&order, accounts);
+        assert!(matched.unsettled.is_none() && matched.dropped.is_empty());
+    }
+
+    #[test]
+    fn liquidity_is_set_by_the_subsidy() {
+        let (_, _, amm) = setup(dec!(100));
+
+        assert!((amm.liquidity - 100.0 / std::f64::consts::LN_2).abs() < 1e-9);
+        // Starting from no shares sold, the subsidy is exactly C(0) = b ln 2
+        assert!((amm.cost(0.0) - 100.0).abs() < 1e-9);
+        assert!((amm.price(0.0) - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn price_is_the_marginal_cost() {
+        let (_, _, amm) = setup(dec!(100));
+
+        for q in [-500.0, -50.0, 0.0, 25.0, 400.0] {
+            let h = 1e-4;
+            let marginal = (amm.cost(q + h) - amm.cost(q - h)) / (2.0 * h);
+            assert!((marginal - amm.price(q)).abs() < 1e-6, "q = {}", q);
+        }
+        assert!(amm.price(-50.0) < amm.price(0.0) && amm.price(0.0) < amm.price(50.0));
+        assert!(amm.cost(1e6).is_finite() && amm.cost(-1e6).is_finite());
+    }
+
+    #[test]
+    fn minted_shares_cover_asks_up_to_the_top_tick() {
+        let (orderbook, accounts, amm) = setup(dec!(100));
+        let minted = amm.minted.to_f64().unwrap();
+
+        assert!(amm.price(minted) >= 0.99);
+        assert_eq!(amm.holdings(&orderbook.base_asset, &accounts), amm.minted);
+        assert_eq!(amm.holdings(&orderbook.quote_asset, &accounts), dec!(100));
+    }
+
+    #[test]
+    fn rejects_bad_subsidy_and_tick() {
+        let _ = RedisManager::instance().install_sink(Arc::new(MemorySink::new()));
+        let accounts = AccountStore::new();
+        let orderbook = Orderbook::new(String::from("YES"), String::from("USDC"));
+
+        assert!(LmsrAmm::new(&orderbook, dec!(0), config(), &accounts).is_err());
+        let wide = AmmConfig {
+            tick: dec!(0.5),
+            ..config()
+        };
+        assert!(LmsrAmm::new(&orderbook, dec!(100), wide, &accounts).is_err());
+    }
+
+    #[test]
+    fn ladder_brackets_the_lmsr_price() {
+        let (mut orderbook, accounts, mut amm) = setup(dec!(100));
+        amm.requote(&mut orderbook, &accounts);
+
+        assert_eq!(orderbook.asks.len(), 5);
+        assert_eq!(orderbook.bids.len(), 5);
+        let best_bid = orderbook.best_bid().unwrap();
+        let best_ask = orderbook.best_ask().unwrap();
+        assert!(best_bid < dec!(0.5) && dec!(0.5) < best_ask);
+        assert!(orderbook.asks.iter().all(|o| o.price < Decimal::ONE));
+        assert!(orderbook.bids.iter().all(|o| o.price > Decimal::ZERO));
+    }
+
+    #[test]
+    fn losses_stay_within_the_subsidy() {
+        let (mut orderbook, accounts, mut amm) = setup(dec!(100));
+
+        // Buy out every ask the AMM will quote, then sell it all back
+        for _ in 0..100 {
+            amm.requote(&mut orderbook, &accounts);
+            if orderbook.asks.is_empty() {
+                break;
+            }
+            take(
+                &mut orderbook,
+                &accounts,
+                OrderSide::Bid,
+                Decimal::ONE,
+                dec!(50),
+            );
+            let state = amm.state(&orderbook, &accounts);
+            assert!(state.worst_case_loss <= amm.subsidy, "{:?}", state);
+        }
+        assert!(amm.state(&orderbook, &accounts).net_sold > Decimal::ZERO);
+
+        for _ in 0..100 {
+            amm.requote(&mut orderbook, &accounts);
+            if orderbook.bids.is_empty() {
+                break;
+            }
+            take(
+                &mut orderbook,
+                &accounts,
+                OrderSide::Ask,
+                dec!(0.01),
+                dec!(50),
+            );
+            let state = amm.state(&orderbook, &accounts);
+            assert!(state.worst_case_loss <= amm.subsidy, "{:?}", state);
+            assert!(state.quote_balance >= Decimal::ZERO);
+        }
+    }
+}
@@ -1,52 +1,99 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
     sync::{Arc, Mutex},
 };
 
 use crate::{
+    constant::{MAX_PRICE, MAX_QUANTITY},
     models::{
-        Balance, MarketCreated, MessageFromApi, MessageToApi, OrderCancelledPayload,
-        OrderbookMessage, User, UserBalancesPayload,
+        BookTicker, BookTickerPayload, MarketClosedPayload, MarketCreated, MarketSummary,
+        MessageFromApi, MessageToApi, Order, OrderbookMessage, Ticker24h, TickersPayload,
+        UserBalancesPayload, UserPortfolioPayload, Withdrawal,
     },
-    services::RedisManager,
+    services::{ChainAdapter, ChainSubmitter, MarketDefinition, MockChainAdapter, RedisManager},
 };
-use anyhow::{Ok, Result};
+use chrono::{DateTime, Utc};
+use crossbeam_channel::TrySendError;
+use metrics::counter;
 use rust_decimal::Decimal;
 use tracing::{error, info};
 
-use super::OrderbookWorker;
+use super::{
+    reject, AccountStore, EngineError, Faucet, LockExt, Orderbook, OrderbookWorker, QuoteAmount,
+};
 
 pub struct Engine {
     pub orderbook_workers: HashMap<String, OrderbookWorker>,
-    pub users: Arc<Mutex<Vec<User>>>,
+    pub accounts: Arc<AccountStore>,
+    pub market_summaries: Arc<Mutex<HashMap<String, MarketSummary>>>,
+    pub withdrawals: HashMap<String, Withdrawal>,
+    /// Hands approved withdrawals to the chain adapter off the engine loop
+    pub chain: ChainSubmitter,
+    pub faucet: Option<Faucet>,
+    /// References of every deposit credited, so none is credited twice
+    pub(super) deposit_references: BTreeSet<String>,
+    /// Resting orders from a snapshot, per market, waiting for the market to
+    /// be loaded
+    pub(super) restored_books: HashMap<String, Vec<Order>>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Engine {
     pub fn new() -> Self {
-        let initial_users = Vec::new();
-
-        let users = Arc::new(Mutex::new(initial_users));
+        Self::with_chain_adapter(Box::new(MockChainAdapter::new()))
+    }
 
-        let engine = Engine {
+    pub fn with_chain_adapter(chain_adapter: Box<dyn ChainAdapter>) -> Self {
+        Engine {
             orderbook_workers: HashMap::new(),
-            users,
-        };
-
-        engine
+            accounts: Arc::new(AccountStore::new()),
+            market_summaries: Arc::new(Mutex::new(HashMap::new())),
+            withdrawals: HashMap::new(),
+            chain: ChainSubmitter::spawn(chain_adapter),
+            faucet: Faucet::from_env(),
+            deposit_references: BTreeSet::new(),
+            restored_books: HashMap::new(),
+        }
     }
 
-    pub fn create_market(&mut self, base_asset: String, quote_asset: String) -> Result<()> {
+    pub fn create_market(
+        &mut self,
+        base_asset: String,
+        quote_asset: String,
+        end_time: Option<DateTime<Utc>>,
+        amm_subsidy: Option<Decimal>,
+    ) -> Result<(), EngineError> {
+        for asset in [&base_asset, &quote_asset] {
+            if asset.is_empty() || asset.contains('_') {
+                return Err(EngineError::InvalidRequest(format!(
+                    "Invalid asset name: {:?}",
+                    asset
+                )));
+            }
+        }
+
         let market = format!("{}_{}", base_asset, quote_asset);
 
         if self.orderbook_workers.contains_key(&market) {
-            return Err(anyhow::anyhow!("Market already exists"));
+            return Err(EngineError::MarketExists(market));
         }
 
+        let mut orderbook = Orderbook::new(base_asset, quote_asset);
+        for order in self.restored_books.remove(&market).unwrap_or_default() {
+            orderbook.insert_order(order);
+        }
         let worker = OrderbookWorker::new(
             market.clone(),
-            base_asset,
-            quote_asset,
-            Arc::clone(&self.users),
+            orderbook,
+            end_time,
+            amm_subsidy,
+            Arc::clone(&self.accounts),
+            Arc::clone(&self.market_summaries),
         );
 
         self.orderbook_workers.insert(market, worker);
@@ -54,154 +101,384 @@ impl Engine {
         Ok(())
     }
 
+    /// Stops a market from taking new orders. The worker keeps running so
+    /// resting orders can still be cancelled and their funds released.
+    pub fn close_market(
+        &mut self,
+        market: &str,
+        now: DateTime<Utc>,
+    ) -> Result<DateTime<Utc>, EngineError> {
+        let worker = self
+            .orderbook_workers
+            .get_mut(market)
+            .ok_or_else(|| EngineError::UnknownMarket(market.to_string()))?;
+
+        let end_time = worker.end_time.map_or(now, |end_time| end_time.min(now));
+        worker.end_time = Some(end_time);
+        info!(market, %end_time, "Market closed");
+
+        Ok(end_time)
+    }
+
+    /// Brings the running workers in line with the `markets` table: spawns a
+    /// worker for every open market that doesn't have one and closes the ones
+    /// that were closed in the table. Closed markets with a restored book get a
+    /// worker too, so their orders can still be cancelled. Markets missing from
+    /// the table are left alone since http-server only inserts them after the
+    /// engine accepts them.
+    pub fn reconcile_markets(&mut self, markets: &[MarketDefinition], now: DateTime<Utc>) {
+        for definition in markets {
+            let market = definition.market();
+            let is_closed = definition.is_closed(now);
+
+            match self.orderbook_workers.get_mut(&market) {
+                Some(worker) if is_closed => {
+                    if !worker.is_closed(now) {
+                        let _ = self.close_market(&market, now);
+                    }
+                }
+                Some(worker) => worker.end_time = Some(definition.end_time),
+                None if is_closed && !self.restored_books.contains_key(&market) => {}
+                None => {
+                    match self.create_market(
+                        definition.base_asset.clone(),
+                        definition.quote_asset.clone(),
+                        Some(definition.end_time),
+                        definition.amm_subsidy,
+                    ) {
+                        Ok(()) => info!(market, "Loaded market"),
+                        Err(e) => error!(market, "Failed to load market: {}", e),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Handles one request from http-server. Every request gets exactly one
+    /// reply, either the normal response or a `REJECTED` message.
     pub fn process(&mut self, client_id: String, message: MessageFromApi) {
+        self.process_at(client_id, message, Utc::now());
+    }
+
+    /// Same as `process` with `now` as the time market closing and faucet
+    /// cooldowns are judged at, which replays take from the journal.
+    pub fn process_at(&mut self, client_id: String, message: MessageFromApi, now: DateTime<Utc>) {
+        let kind = message.kind();
+        counter!("engine_requests_total", "type" => kind).increment(1);
+
+        if let Err(e) = self.handle_message(&client_id, message, now) {
+            counter!("engine_rejections_total", "type" => kind, "code" => e.code()).increment(1);
+            reject(&client_id, e);
+        }
+    }
+
+    fn handle_message(
+        &mut self,
+        client_id: &str,
+        message: MessageFromApi,
+        now: DateTime<Utc>,
+    ) -> Result<(), EngineError> {
         match message {
             MessageFromApi::CreateMarket { data } => {
-                match self.create_market(data.base_asset.clone(), data.quote_asset.clone()) {
-                    Result::Ok(()) => {
-                        let response = MessageToApi::MarketCreated {
-                            payload: MarketCreated {
-                                message: Some("Market successfully created".to_string()),
-                            },
-                        };
-                        let _ = RedisManager::instance().send_to_api(&client_id, &response);
-                    }
-                    Err(err) => {
-                        let response = MessageToApi::OrderCancelled {
-                            payload: OrderCancelledPayload {
-                                message: Some(format!("Failed to create market: {}", err)),
-                            },
-                        };
-                        let _ = RedisManager::instance().send_to_api(&client_id, &response);
-                    }
+                if let Some(subsidy) = data.amm_subsidy {
+                    validate_quantity(subsidy)?;
                 }
+                self.create_market(
+                    data.base_asset,
+                    data.quote_asset,
+                    Some(data.end_time),
+                    data.amm_subsidy,
+                )?;
+
+                let response = MessageToApi::MarketCreated {
+                    payload: MarketCreated {
+                        message: Some("Market successfully created".to_string()),
+                    },
+                };
+                let _ = RedisManager::instance().send_to_api(client_id, &response);
+                Ok(())
             }
-            MessageFromApi::CreateOrder { data } => {
-                if let Some(worker) = self.orderbook_workers.get(&data.market) {
-                    if let Err(e) = worker.sender.send(OrderbookMessage::CreateOrder {
-                        client_id,
-                        payload: data,
-                    }) {
-                        error!("Failed to send create order message to worker: {}", e);
+            MessageFromApi::CloseMarket { data } => {
+                let end_time = self.close_market(&data.market, now)?;
+
+                let response = MessageToApi::MarketClosed {
+                    payload: MarketClosedPayload {
+                        market: data.market,
+                        end_time,
+                    },
+                };
+                let _ = RedisManager::instance().send_to_api(client_id, &response);
+                Ok(())
+            }
+            MessageFromApi::GetBookTicker { data } => {
+                let markets: Vec<String> = match data.market {
+                    Some(market) => vec![self.worker(&market)?.market.clone()],
+                    None => {
+                        let mut markets: Vec<String> =
+                            self.orderbook_workers.keys().cloned().collect();
+                        markets.sort();
+                        markets
                     }
-                } else {
-                    error!("Market not found: {}", data.market);
-                    let redis_manager = RedisManager::instance();
-                    let message = MessageToApi::OrderCancelled {
-                        payload: OrderCancelledPayload {
-                            message: Some(String::from("Market not found")),
+                };
+
+                let summaries = self.market_summaries.lock_or_recover();
+                let tickers = markets
+                    .into_iter()
+                    .map(|market| match summaries.get(&market) {
+                        Some(summary) => summary.book_ticker.clone(),
+                        // Nothing has rested on the book yet
+                        None => BookTicker {
+                            market,
+                            ..Default::default()
                         },
-                    };
-                    let _ = redis_manager.send_to_api(&client_id, &message);
-                }
+                    })
+                    .collect();
+
+                let message = MessageToApi::BookTicker {
+                    payload: BookTickerPayload { tickers },
+                };
+                let _ = RedisManager::instance().send_to_api(client_id, &message);
+                Ok(())
             }
-            MessageFromApi::CancelOrder { data } => {
-                if let Some(worker) = self.orderbook_workers.get(&data.market) {
-                    if let Err(e) = worker.sender.send(OrderbookMessage::CancelOrder {
-                        client_id,
-                        payload: data,
-                    }) {
-                        error!("Failed to send cancel order message to worker: {}", e);
+            MessageFromApi::CheckInvariants => self.handle_check_invariants(client_id),
+            MessageFromApi::GetHealth => self.handle_get_health(client_id),
+            MessageFromApi::RestoreMarket { data } => {
+                self.handle_restore_market(client_id, &data.market)
+            }
+            MessageFromApi::GetTickers { data } => {
+                let markets: Vec<String> = match data.market {
+                    Some(market) => vec![self.worker(&market)?.market.clone()],
+                    None => {
+                        let mut markets: Vec<String> =
+                            self.orderbook_workers.keys().cloned().collect();
+                        markets.sort();
+                        markets
                     }
-                } else {
-                    error!("Market not found: {}", data.market);
+                };
+
+                let summaries = self.market_summaries.lock_or_recover();
+                let tickers = markets
+                    .iter()
+                    .map(|market| match summaries.get(market) {
+                        Some(summary) => summary.stats.snapshot(market, now),
+                        None => Ticker24h {
+                            market: market.clone(),
+                            ..Default::default()
+                        },
+                    })
+                    .collect();
+
+                let message = MessageToApi::Tickers {
+                    payload: TickersPayload { tickers },
+                };
+                let _ = RedisManager::instance().send_to_api(client_id, &message);
+                Ok(())
+            }
+            MessageFromApi::GetAmmState { data } => Self::dispatch(
+                self.running_worker(&data.market)?,
+                OrderbookMessage::GetAmmState {
+                    client_id: client_id.to_string(),
+                },
+            ),
+            MessageFromApi::CreateOrder { data } => {
+                validate_price(data.price)?;
+                validate_quantity(data.quantity)?;
+
+                let worker = self.running_worker(&data.market)?;
+                if worker.is_closed(now) {
+                    return Err(EngineError::MarketClosed(data.market));
                 }
+
+                Self::dispatch(
+                    worker,
+                    OrderbookMessage::CreateOrder {
+                        client_id: client_id.to_string(),
+                        payload: data,
+                    },
+                )
             }
+            MessageFromApi::CancelOrder { data } => Self::dispatch(
+                self.running_worker(&data.market)?,
+                OrderbookMessage::CancelOrder {
+                    client_id: client_id.to_string(),
+                    payload: data,
+                },
+            ),
             MessageFromApi::GetDepth { data } => {
-                if let Some(worker) = self.orderbook_workers.get(&data.market) {
-                    if let Err(e) = worker.sender.send(OrderbookMessage::GetDepth {
-                        client_id,
-                        market: data.market,
-                    }) {
-                        error!("Failed to send get depth message to worker: {}", e);
-                    }
-                } else {
-                    error!("Market not found: {}", data.market);
+                if data.limit == Some(0) {
+                    return Err(EngineError::InvalidRequest(String::from(
+                        "Depth limit must be positive",
+                    )));
                 }
-            }
-            MessageFromApi::GetOpenOrders { data } => {
-                if let Some(worker) = self.orderbook_workers.get(&data.market) {
-                    if let Err(e) = worker.sender.send(OrderbookMessage::GetOpenOrders {
-                        client_id,
-                        payload: data,
-                    }) {
-                        error!("Failed to send get open orders message to worker: {}", e);
-                    }
-                } else {
-                    error!("Market not found: {}", data.market);
+                if let Some(bucket) = data.bucket {
+                    validate_price(bucket)?;
                 }
+
+                Self::dispatch(
+                    self.running_worker(&data.market)?,
+                    OrderbookMessage::GetDepth {
+                        client_id: client_id.to_string(),
+                        market: data.market,
+                        limit: data.limit,
+                        bucket: data.bucket,
+                    },
+                )
             }
+            MessageFromApi::GetOpenOrders { data } => Self::dispatch(
+                self.running_worker(&data.market)?,
+                OrderbookMessage::GetOpenOrders {
+                    client_id: client_id.to_string(),
+                    payload: data,
+                },
+            ),
             MessageFromApi::GetQuote { data } => {
-                if let Some(worker) = self.orderbook_workers.get(&data.market) {
-                    if let Err(e) = worker.sender.send(OrderbookMessage::GetQuote {
-                        client_id,
-                        market: data.market,
-                        quantity: data.quantity,
-                        side: data.side,
-                    }) {
-                        error!("Failed to send get quote message to worker: {}", e);
+                let amount = match (data.quantity, data.quote_amount) {
+                    (Some(quantity), None) => QuoteAmount::Base(quantity),
+                    (None, Some(quote_amount)) => QuoteAmount::Quote(quote_amount),
+                    _ => {
+                        return Err(EngineError::InvalidRequest(String::from(
+                            "Exactly one of quantity and quoteAmount is required",
+                        )))
                     }
-                } else {
-                    error!("Market not found: {}", data.market);
+                };
+                match amount {
+                    QuoteAmount::Base(quantity) => validate_quantity(quantity)?,
+                    QuoteAmount::Quote(quote_amount) => validate_notional(quote_amount)?,
                 }
+
+                Self::dispatch(
+                    self.running_worker(&data.market)?,
+                    OrderbookMessage::GetQuote {
+                        client_id: client_id.to_string(),
+                        market: data.market,
+                        amount,
+                        side: data.side,
+                    },
+                )
             }
             MessageFromApi::GetUserBalances { data } => {
-                let mut users = self.users.lock().unwrap();
-                let user = users
-                    .iter_mut()
-                    .find(|u| u.id == data.user_id)
-                    .expect("User not found");
+                let user = self
+                    .accounts
+                    .get(&data.user_id)
+                    .ok_or(EngineError::UnknownUser(data.user_id))?;
 
                 let redis_manager = RedisManager::instance();
                 let message = MessageToApi::UserBalances {
                     payload: UserBalancesPayload {
-                        balances: user.balances.clone(),
+                        balances: user.lock_or_recover().balance_list(),
                     },
                 };
 
-                let _ = redis_manager.send_to_api(&client_id, &message);
+                let _ = redis_manager.send_to_api(client_id, &message);
+                Ok(())
             }
-            MessageFromApi::OnRampUser { data } => {
-                let mut users = self.users.lock().unwrap();
+            MessageFromApi::GetUserPortfolio { data } => {
+                let summaries = self.market_summaries.lock_or_recover();
 
-                if let Some(user) = users.iter_mut().find(|u| u.id == data.user_id) {
-                    for bal in &mut user.balances {
-                        bal.balance = Decimal::new(10_000, 0);
-                    }
-                } else {
-                    users.push(User {
-                        id: data.user_id.clone(),
-                        balances: vec![Balance {
-                            ticker: "USD".to_string(),       
-                            balance: Decimal::new(10_000, 0),
-                            locked_balance: Decimal::new(0, 0),
-                        }],
-                    });
-                }
-
-                let updated_user = users
-                    .iter()
-                    .find(|u| u.id == data.user_id)
-                    .expect("just inserted or found above");
+                let positions: Vec<_> = self
+                    .accounts
+                    .get(&data.user_id)
+                    .map(|user| {
+                        user.lock_or_recover()
+                            .positions
+                            .values()
+                            .map(|position| {
+                                let mark_price = summaries
+                                    .get(&position.market)
+                                    .and_then(|s| s.mid_price.or(s.last_price));
+                                position.summary(mark_price)
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
 
                 let redis_manager = RedisManager::instance();
-                let message = MessageToApi::UserBalances {
-                    payload: UserBalancesPayload {
-                        balances: updated_user.balances.clone(),
+                let message = MessageToApi::UserPortfolio {
+                    payload: UserPortfolioPayload {
+                        total_realised_pnl: positions.iter().map(|p| p.realised_pnl).sum(),
+                        total_unrealised_pnl: positions.iter().map(|p| p.unrealised_pnl).sum(),
+                        positions,
                     },
                 };
 
-                let _ = redis_manager.send_to_api(&client_id, &message);
+                let _ = redis_manager.send_to_api(client_id, &message);
+                Ok(())
+            }
+            MessageFromApi::Deposit { data } => self.handle_deposit(client_id, data),
+            MessageFromApi::Withdraw { data } => self.handle_withdraw(client_id, data),
+            MessageFromApi::ApproveWithdrawal { data } => {
+                self.handle_approve_withdrawal(client_id, data)
+            }
+            MessageFromApi::RejectWithdrawal { data } => {
+                self.handle_reject_withdrawal(client_id, data)
             }
+            MessageFromApi::GetWithdrawals { data } => {
+                self.handle_get_withdrawals(client_id, data.user_id)
+            }
+            MessageFromApi::Faucet { data } => self.handle_faucet(client_id, data, now),
+        }
+    }
+
+    pub(super) fn worker(&self, market: &str) -> Result<&OrderbookWorker, EngineError> {
+        self.orderbook_workers
+            .get(market)
+            .ok_or_else(|| EngineError::UnknownMarket(market.to_string()))
+    }
+
+    /// Worker of `market` if it is still trading. Commands for a halted
+    /// market are rejected here rather than queued for a worker that will
+    /// never answer them.
+    fn running_worker(&self, market: &str) -> Result<&OrderbookWorker, EngineError> {
+        let worker = self.worker(market)?;
+        if worker.halt().is_some() || worker.is_dead() {
+            return Err(EngineError::MarketHalted(worker.market.clone()));
         }
+        Ok(worker)
+    }
+
+    pub(super) fn dispatch(
+        worker: &OrderbookWorker,
+        message: OrderbookMessage,
+    ) -> Result<(), EngineError> {
+        worker.send(message).map_err(|e| match e {
+            TrySendError::Full(_) => EngineError::EngineBusy(worker.market.clone()),
+            TrySendError::Disconnected(_) => {
+                error!(
+                    market = worker.market,
+                    "Failed to send message to worker: {}", e
+                );
+                EngineError::MarketHalted(worker.market.clone())
+            }
+        })
+    }
+}
+
+pub fn validate_price(price: Decimal) -> Result<(), EngineError> {
+    if price <= Decimal::ZERO || price > Decimal::from(MAX_PRICE) {
+        return Err(EngineError::InvalidPrice);
+    }
+    Ok(())
+}
+
+pub fn validate_quantity(quantity: Decimal) -> Result<(), EngineError> {
+    if quantity <= Decimal::ZERO || quantity > Decimal::from(MAX_QUANTITY) {
+        return Err(EngineError::InvalidQuantity);
+    }
+    Ok(())
+}
+
+pub fn validate_notional(amount: Decimal) -> Result<(), EngineError> {
+    if amount <= Decimal::ZERO || amount > Decimal::from(MAX_PRICE) * Decimal::from(MAX_QUANTITY) {
+        return Err(EngineError::InvalidQuantity);
     }
+    Ok(())
 }
 
 impl Drop for Engine {
     fn drop(&mut self) {
         for (market, worker) in self.orderbook_workers.iter_mut() {
             info!("Shutting down orderbook worker for market: {}", market);
-            if let Err(e) = worker.sender.send(OrderbookMessage::ShutDown) {
+            if let Err(e) = worker.send(OrderbookMessage::ShutDown) {
                 error!("Failed to send shutdown message to worker: {}", e);
             }
 
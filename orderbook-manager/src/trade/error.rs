@@ -0,0 +1,103 @@
+use std::fmt;
+
+use tracing::warn;
+
+use crate::{
+    models::{MessageToApi, RejectedPayload},
+    services::RedisManager,
+};
+
+/// Everything a client request can be rejected with. Engine and worker paths
+/// return these instead of panicking and reply with a `REJECTED` message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EngineError {
+    UnknownUser(String),
+    UnknownMarket(String),
+    UnknownOrder(String),
+    UnknownWithdrawal(String),
+    MarketExists(String),
+    /// A deposit with this reference was already credited
+    DuplicateDeposit(String),
+    MarketClosed(String),
+    /// The market's worker panicked or died and it no longer trades
+    MarketHalted(String),
+    /// The market's queue is full and the request was shed
+    EngineBusy(String),
+    InsufficientBalance {
+        asset: String,
+    },
+    InvalidQuantity,
+    InvalidPrice,
+    InvalidRequest(String),
+    Internal(String),
+}
+
+impl EngineError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            EngineError::UnknownUser(_) => "UNKNOWN_USER",
+            EngineError::UnknownMarket(_) => "UNKNOWN_MARKET",
+            EngineError::UnknownOrder(_) => "UNKNOWN_ORDER",
+            EngineError::UnknownWithdrawal(_) => "UNKNOWN_WITHDRAWAL",
+            EngineError::MarketExists(_) => "MARKET_EXISTS",
+            EngineError::DuplicateDeposit(_) => "DUPLICATE_DEPOSIT",
+            EngineError::MarketClosed(_) => "MARKET_CLOSED",
+            EngineError::MarketHalted(_) => "MARKET_HALTED",
+            EngineError::EngineBusy(_) => "ENGINE_BUSY",
+            EngineError::InsufficientBalance { .. } => "INSUFFICIENT_BALANCE",
+            EngineError::InvalidQuantity => "INVALID_QUANTITY",
+            EngineError::InvalidPrice => "INVALID_PRICE",
+            EngineError::InvalidRequest(_) => "INVALID_REQUEST",
+            EngineError::Internal(_) => "INTERNAL_ERROR",
+        }
+    }
+
+    pub fn to_message(&self) -> MessageToApi {
+        MessageToApi::Rejected {
+            payload: RejectedPayload {
+                code: self.code().to_string(),
+                message: self.to_string(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::UnknownUser(user_id) => write!(f, "User not found: {}", user_id),
+            EngineError::UnknownMarket(market) => write!(f, "Market not found: {}", market),
+            EngineError::UnknownOrder(order_id) => write!(f, "Order not found: {}", order_id),
+            EngineError::UnknownWithdrawal(id) => write!(f, "Withdrawal not found: {}", id),
+            EngineError::MarketExists(market) => write!(f, "Market already exists: {}", market),
+            EngineError::DuplicateDeposit(reference) => {
+                write!(f, "Deposit already credited: {}", reference)
+            }
+            EngineError::MarketClosed(market) => write!(f, "Market is closed: {}", market),
+            EngineError::MarketHalted(market) => write!(f, "Market is halted: {}", market),
+            EngineError::EngineBusy(market) => {
+                write!(f, "Engine busy: {} has too many queued requests", market)
+            }
+            EngineError::InsufficientBalance { asset } => {
+                write!(f, "Insufficient {} balance", asset)
+            }
+            EngineError::InvalidQuantity => write!(f, "Quantity must be positive and in range"),
+            EngineError::InvalidPrice => write!(f, "Price must be positive and in range"),
+            EngineError::InvalidRequest(reason) => write!(f, "Invalid request: {}", reason),
+            EngineError::Internal(reason) => write!(f, "Internal error: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+/// Replies to `client_id` with a `REJECTED` message for `error`.
+pub fn reject(client_id: &str, error: EngineError) {
+    warn!(
+        client_id,
+        code = error.code(),
+        "Rejected request: {}",
+        error
+    );
+    let _ = RedisManager::instance().send_to_api(client_id, &error.to_message());
+}
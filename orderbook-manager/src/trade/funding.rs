@@ -0,0 +1,348 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Duration, Utc};
+use metrics::counter;
+use rust_decimal::Decimal;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    constant::DEFAULT_FAUCET_ASSETS,
+    models::{
+        DepositPayload, FaucetPayload, MessageToApi, UserBalancesPayload, WithdrawPayload,
+        Withdrawal, WithdrawalActionPayload, WithdrawalStatus, WithdrawalsPayload,
+    },
+    services::{ChainReceipt, RedisManager},
+};
+
+use super::{validate_quantity, Engine, EngineError, Ledger, LockExt, Transaction};
+
+/// Test-faucet credits for staging, enabled with `FAUCET_ENABLED=true`. Each
+/// user can claim at most once per cooldown window, and only the assets
+/// listed in `FAUCET_ASSETS`.
+pub struct Faucet {
+    pub amount: Decimal,
+    pub cooldown: Duration,
+    pub assets: BTreeSet<String>,
+    last_claims: BTreeMap<String, DateTime<Utc>>,
+}
+
+impl Faucet {
+    pub fn from_env() -> Option<Self> {
+        let enabled = std::env::var("FAUCET_ENABLED")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false);
+        if !enabled {
+            return None;
+        }
+
+        let amount = std::env::var("FAUCET_AMOUNT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(Decimal::new(10_000, 0));
+        let cooldown_secs = std::env::var("FAUCET_COOLDOWN_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(24 * 60 * 60);
+        let assets = std::env::var("FAUCET_ASSETS")
+            .unwrap_or_else(|_| DEFAULT_FAUCET_ASSETS.to_string())
+            .split(',')
+            .map(|asset| asset.trim().to_string())
+            .filter(|asset| !asset.is_empty())
+            .collect();
+
+        Some(Faucet {
+            amount,
+            cooldown: Duration::seconds(cooldown_secs),
+            assets,
+            last_claims: BTreeMap::new(),
+        })
+    }
+
+    pub fn claim(
+        &mut self,
+        user_id: &str,
+        asset: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Decimal, EngineError> {
+        if !self.assets.contains(asset) {
+            return Err(EngineError::InvalidRequest(format!(
+                "Faucet doesn't hand out {}",
+                asset
+            )));
+        }
+        if let Some(last_claim) = self.last_claims.get(user_id) {
+            let next_claim = *last_claim + self.cooldown;
+            if now < next_claim {
+                return Err(EngineError::InvalidRequest(format!(
+                    "Faucet already claimed, try again after {}",
+                    next_claim
+                )));
+            }
+        }
+
+        self.last_claims.insert(user_id.to_string(), now);
+        Ok(self.amount)
+    }
+
+    /// When each user last claimed, kept in snapshots so a restart doesn't
+    /// reset the cooldowns.
+    pub fn last_claims(&self) -> &BTreeMap<String, DateTime<Utc>> {
+        &self.last_claims
+    }
+
+    pub fn restore_claims(&mut self, last_claims: BTreeMap<String, DateTime<Utc>>) {
+        self.last_claims = last_claims;
+    }
+}
+
+impl Engine {
+    pub(super) fn handle_deposit(
+        &mut self,
+        client_id: &str,
+        data: DepositPayload,
+    ) -> Result<(), EngineError> {
+        validate_quantity(data.amount)?;
+        if data.reference.trim().is_empty() {
+            return Err(EngineError::InvalidRequest(String::from(
+                "Deposit reference is required",
+            )));
+        }
+        if self.deposit_references.contains(&data.reference) {
+            return Err(EngineError::DuplicateDeposit(data.reference));
+        }
+
+        let transaction =
+            Transaction::deposit(&data.user_id, &data.asset, data.amount, &data.reference);
+        self.post_and_reply_balances(client_id, &data.user_id, transaction)?;
+        self.deposit_references.insert(data.reference);
+        Ok(())
+    }
+
+    pub(super) fn handle_faucet(
+        &mut self,
+        client_id: &str,
+        data: FaucetPayload,
+        now: DateTime<Utc>,
+    ) -> Result<(), EngineError> {
+        let faucet = self
+            .faucet
+            .as_mut()
+            .ok_or_else(|| EngineError::InvalidRequest(String::from("Faucet is disabled")))?;
+
+        let amount = faucet.claim(&data.user_id, &data.asset, now)?;
+        let reference = format!("faucet-{}", Uuid::new_v4());
+        let transaction = Transaction::deposit(&data.user_id, &data.asset, amount, &reference);
+        self.post_and_reply_balances(client_id, &data.user_id, transaction)
+    }
+
+    pub(super) fn handle_withdraw(
+        &mut self,
+        client_id: &str,
+        data: WithdrawPayload,
+    ) -> Result<(), EngineError> {
+        validate_quantity(data.amount)?;
+
+        let now = Utc::now();
+        let withdrawal = Withdrawal {
+            id: Uuid::new_v4().to_string(),
+            user_id: data.user_id,
+            asset: data.asset,
+            amount: data.amount,
+            destination: data.destination,
+            status: WithdrawalStatus::Pending,
+            tx_signature: None,
+            reason: None,
+            created_at: now,
+            updated_at: now,
+        };
+
+        let transaction = Transaction::hold(
+            &withdrawal.user_id,
+            &withdrawal.asset,
+            withdrawal.amount,
+            &withdrawal.id,
+        );
+        Ledger::post(&self.accounts, transaction)?;
+
+        info!(withdrawal_id = withdrawal.id, "Withdrawal requested");
+        self.withdrawals
+            .insert(withdrawal.id.clone(), withdrawal.clone());
+        Self::reply_withdrawal(client_id, withdrawal);
+        Ok(())
+    }
+
+    /// Approves a pending withdrawal and hands it to the chain adapter. The
+    /// funds stay held until `settle_withdrawal` gets the adapter's receipt.
+    pub(super) fn handle_approve_withdrawal(
+        &mut self,
+        client_id: &str,
+        data: WithdrawalActionPayload,
+    ) -> Result<(), EngineError> {
+        let mut withdrawal = self.pending_withdrawal(&data.withdrawal_id)?;
+
+        withdrawal.status = WithdrawalStatus::Approved;
+        withdrawal.updated_at = Utc::now();
+        self.chain.submit(withdrawal.clone());
+
+        self.withdrawals
+            .insert(withdrawal.id.clone(), withdrawal.clone());
+        Self::reply_withdrawal(client_id, withdrawal);
+        Ok(())
+    }
+
+    /// Settles an approved withdrawal with the chain adapter's receipt. The
+    /// held funds are paid out when the adapter sent them and released back
+    /// to the user when it failed. A payout the ledger can't post leaves the
+    /// withdrawal `UNSETTLED` with its funds still held, for an operator to
+    /// reconcile against the chain.
+    pub fn settle_withdrawal(&mut self, receipt: ChainReceipt) {
+        let mut withdrawal = match self.withdrawals.get(&receipt.withdrawal_id) {
+            Some(w) if w.status == WithdrawalStatus::Approved => w.clone(),
+            _ => {
+                warn!(
+                    withdrawal_id = receipt.withdrawal_id,
+                    "Chain receipt for a withdrawal that isn't approved"
+                );
+                return;
+            }
+        };
+
+        match receipt.outcome {
+            Ok(signature) => {
+                let transaction = Transaction::withdrawal(
+                    &withdrawal.user_id,
+                    &withdrawal.asset,
+                    withdrawal.amount,
+                    &withdrawal.id,
+                );
+                match Ledger::post(&self.accounts, transaction) {
+                    Ok(_) => {
+                        info!(
+                            withdrawal_id = withdrawal.id,
+                            adapter = self.chain.name(),
+                            signature,
+                            "Withdrawal completed"
+                        );
+                        withdrawal.status = WithdrawalStatus::Completed;
+                    }
+                    Err(e) => {
+                        error!(
+                            withdrawal_id = withdrawal.id,
+                            signature,
+                            "Withdrawal paid out on chain but not settled, reconcile it: {}",
+                            e
+                        );
+                        counter!("engine_unsettled_withdrawals_total").increment(1);
+                        withdrawal.status = WithdrawalStatus::Unsettled;
+                        withdrawal.reason = Some(e.to_string());
+                    }
+                }
+                withdrawal.tx_signature = Some(signature);
+            }
+            Err(reason) => {
+                error!(
+                    withdrawal_id = withdrawal.id,
+                    "Chain adapter rejected withdrawal: {}", reason
+                );
+                self.release_withdrawal(&mut withdrawal, reason);
+            }
+        }
+
+        withdrawal.updated_at = receipt.time;
+        self.withdrawals.insert(withdrawal.id.clone(), withdrawal);
+    }
+
+    pub(super) fn handle_reject_withdrawal(
+        &mut self,
+        client_id: &str,
+        data: WithdrawalActionPayload,
+    ) -> Result<(), EngineError> {
+        let mut withdrawal = self.pending_withdrawal(&data.withdrawal_id)?;
+
+        let reason = data
+            .reason
+            .unwrap_or_else(|| String::from("Rejected by operator"));
+        self.release_withdrawal(&mut withdrawal, reason);
+
+        withdrawal.updated_at = Utc::now();
+        self.withdrawals
+            .insert(withdrawal.id.clone(), withdrawal.clone());
+        Self::reply_withdrawal(client_id, withdrawal);
+        Ok(())
+    }
+
+    pub(super) fn handle_get_withdrawals(
+        &self,
+        client_id: &str,
+        user_id: String,
+    ) -> Result<(), EngineError> {
+        let mut withdrawals: Vec<Withdrawal> = self
+            .withdrawals
+            .values()
+            .filter(|w| w.user_id == user_id)
+            .cloned()
+            .collect();
+        withdrawals.sort_by_key(|w| std::cmp::Reverse(w.created_at));
+
+        let message = MessageToApi::Withdrawals {
+            payload: WithdrawalsPayload { withdrawals },
+        };
+        let _ = RedisManager::instance().send_to_api(client_id, &message);
+        Ok(())
+    }
+
+    fn pending_withdrawal(&self, withdrawal_id: &str) -> Result<Withdrawal, EngineError> {
+        match self.withdrawals.get(withdrawal_id) {
+            Some(w) if w.status == WithdrawalStatus::Pending => Ok(w.clone()),
+            Some(_) => Err(EngineError::InvalidRequest(format!(
+                "Withdrawal {} is not pending",
+                withdrawal_id
+            ))),
+            None => Err(EngineError::UnknownWithdrawal(withdrawal_id.to_string())),
+        }
+    }
+
+    fn release_withdrawal(&mut self, withdrawal: &mut Withdrawal, reason: String) {
+        let transaction = Transaction::release(
+            &withdrawal.user_id,
+            &withdrawal.asset,
+            withdrawal.amount,
+            &withdrawal.id,
+        );
+        if let Err(e) = Ledger::post(&self.accounts, transaction) {
+            error!(
+                withdrawal_id = withdrawal.id,
+                "Failed to release withdrawal hold: {}", e
+            );
+        }
+
+        withdrawal.status = WithdrawalStatus::Rejected;
+        withdrawal.reason = Some(reason);
+    }
+
+    fn post_and_reply_balances(
+        &mut self,
+        client_id: &str,
+        user_id: &str,
+        transaction: Transaction,
+    ) -> Result<(), EngineError> {
+        let user = self.accounts.get_or_create(user_id);
+        Ledger::post(&self.accounts, transaction)?;
+
+        let balances = user.lock_or_recover().balance_list();
+
+        let message = MessageToApi::UserBalances {
+            payload: UserBalancesPayload { balances },
+        };
+        let _ = RedisManager::instance().send_to_api(client_id, &message);
+        Ok(())
+    }
+
+    fn reply_withdrawal(client_id: &str, withdrawal: Withdrawal) {
+        let message = MessageToApi::Withdrawal {
+            payload: withdrawal,
+        };
+        let _ = RedisManager::instance().send_to_api(client_id, &message);
+    }
+}
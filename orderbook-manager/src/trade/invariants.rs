@@ -0,0 +1,203 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use chrono::Utc;
+use metrics::gauge;
+use rust_decimal::Decimal;
+use tracing::{error, info};
+
+use crate::{
+    models::{
+        AssetReconciliation, InvariantViolation, MessageToApi, OrderSide, ReconciliationReport,
+        ViolationKind, WithdrawalStatus,
+    },
+    services::RedisManager,
+};
+
+use super::{Engine, EngineError, LockExt};
+
+impl Engine {
+    /// Checks that balances add up. Every market worker is paused for the
+    /// duration so orders and balances are read at the same instant:
+    ///
+    /// - each asset's balances sum to its net deposits
+    /// - each locked balance equals the holds of the user's live orders and
+    ///   pending withdrawals
+    /// - no balance is negative and nothing locks more than the balance
+    pub fn check_invariants(&self) -> ReconciliationReport {
+        let mut violations = Vec::new();
+
+        // Dropped once balances are read, which resumes the workers
+        let paused = self.pause_books();
+        let markets_checked = paused.audits.len();
+        for market in &paused.unavailable {
+            violations.push(InvariantViolation {
+                kind: ViolationKind::MarketUnavailable,
+                user_id: None,
+                asset: market.clone(),
+                expected: None,
+                actual: None,
+            });
+        }
+
+        // (user, asset) -> amount the user should have locked
+        let mut expected_locked: HashMap<(String, String), Decimal> = HashMap::new();
+        for audit in &paused.audits {
+            for order in &audit.orders {
+                let (asset, amount) = match order.side {
+                    OrderSide::Bid => (&audit.quote_asset, order.price * order.quantity),
+                    OrderSide::Ask => (&audit.base_asset, order.quantity),
+                };
+                *expected_locked
+                    .entry((order.user_id.clone(), asset.clone()))
+                    .or_insert(Decimal::ZERO) += amount;
+            }
+        }
+        for withdrawal in self.withdrawals.values() {
+            // Held from the request until the chain adapter's receipt settles it
+            if matches!(
+                withdrawal.status,
+                WithdrawalStatus::Pending
+                    | WithdrawalStatus::Approved
+                    | WithdrawalStatus::Unsettled
+            ) {
+                *expected_locked
+                    .entry((withdrawal.user_id.clone(), withdrawal.asset.clone()))
+                    .or_insert(Decimal::ZERO) += withdrawal.amount;
+            }
+        }
+
+        let mut user_ids = self.accounts.user_ids();
+        user_ids.sort();
+        // asset -> (total balance, total locked)
+        let mut totals: BTreeMap<String, (Decimal, Decimal)> = BTreeMap::new();
+        for user_id in &user_ids {
+            let Some(account) = self.accounts.get(user_id) else {
+                continue;
+            };
+            let user = account.lock_or_recover();
+
+            for balance in user.balance_list() {
+                let total = totals
+                    .entry(balance.ticker.clone())
+                    .or_insert((Decimal::ZERO, Decimal::ZERO));
+                total.0 += balance.balance;
+                total.1 += balance.locked_balance;
+
+                let violation = |kind, expected, actual| InvariantViolation {
+                    kind,
+                    user_id: Some(user_id.clone()),
+                    asset: balance.ticker.clone(),
+                    expected,
+                    actual: Some(actual),
+                };
+                if balance.balance < Decimal::ZERO || balance.locked_balance < Decimal::ZERO {
+                    violations.push(violation(
+                        ViolationKind::NegativeBalance,
+                        None,
+                        balance.balance.min(balance.locked_balance),
+                    ));
+                }
+                if balance.locked_balance > balance.balance {
+                    violations.push(violation(
+                        ViolationKind::LockedExceedsBalance,
+                        Some(balance.balance),
+                        balance.locked_balance,
+                    ));
+                }
+
+                let expected = expected_locked
+                    .remove(&(user_id.clone(), balance.ticker.clone()))
+                    .unwrap_or(Decimal::ZERO);
+                if balance.locked_balance != expected {
+                    violations.push(violation(
+                        ViolationKind::LockedMismatch,
+                        Some(expected),
+                        balance.locked_balance,
+                    ));
+                }
+            }
+        }
+        // Holds for balances that don't exist at all
+        for ((user_id, asset), expected) in expected_locked {
+            violations.push(InvariantViolation {
+                kind: ViolationKind::LockedMismatch,
+                user_id: Some(user_id),
+                asset,
+                expected: Some(expected),
+                actual: Some(Decimal::ZERO),
+            });
+        }
+
+        let external_flows = self.accounts.external_flows();
+        drop(paused);
+
+        let asset_names: BTreeSet<&String> = totals.keys().chain(external_flows.keys()).collect();
+        let assets: Vec<AssetReconciliation> = asset_names
+            .into_iter()
+            .map(|asset| {
+                let (total_balance, total_locked) = totals
+                    .get(asset)
+                    .copied()
+                    .unwrap_or((Decimal::ZERO, Decimal::ZERO));
+                let net_external = external_flows.get(asset).copied().unwrap_or(Decimal::ZERO);
+                AssetReconciliation {
+                    asset: asset.clone(),
+                    total_balance,
+                    total_locked,
+                    net_external,
+                    difference: total_balance - net_external,
+                }
+            })
+            .collect();
+        for asset in assets.iter().filter(|a| !a.difference.is_zero()) {
+            violations.push(InvariantViolation {
+                kind: ViolationKind::SupplyMismatch,
+                user_id: None,
+                asset: asset.asset.clone(),
+                expected: Some(asset.net_external),
+                actual: Some(asset.total_balance),
+            });
+        }
+
+        ReconciliationReport {
+            time: Utc::now(),
+            markets_checked,
+            users_checked: user_ids.len(),
+            assets,
+            violations,
+        }
+    }
+
+    /// Scheduled check: logs the outcome and sets `engine_invariant_violations`
+    /// so operators can alert on it. The report itself only goes to admins.
+    pub fn run_invariant_check(&self) -> ReconciliationReport {
+        let report = self.check_invariants();
+        gauge!("engine_invariant_violations").set(report.violations.len() as f64);
+
+        if report.violations.is_empty() {
+            info!(
+                markets = report.markets_checked,
+                users = report.users_checked,
+                "Invariant check passed"
+            );
+        } else {
+            for violation in &report.violations {
+                error!(?violation, "Invariant violated");
+            }
+            error!(
+                invariant_violations = report.violations.len(),
+                "Invariant check failed"
+            );
+        }
+
+        report
+    }
+
+    pub(super) fn handle_check_invariants(&self, client_id: &str) -> Result<(), EngineError> {
+        let message = MessageToApi::Reconciliation {
+            payload: self.run_invariant_check(),
+        };
+        let _ = RedisManager::instance().send_to_api(client_id, &message);
+        Ok(())
+    }
+}
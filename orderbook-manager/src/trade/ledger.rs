@@ -0,0 +1,426 @@
+use std::{collections::HashMap, sync::MutexGuard};
+
+use chrono::Utc;
+use rust_decimal::Decimal;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        Balance, EntryDirection, EntryReason, LedgerAccount, LedgerEntriesPayload, LedgerEntry,
+        User,
+    },
+    services::RedisManager,
+};
+
+use super::{publish_balance_updates, AccountStore, EngineError, LockExt};
+
+#[derive(Debug, Clone)]
+pub struct Posting {
+    pub user_id: String,
+    pub asset: String,
+    pub account: LedgerAccount,
+    pub direction: EntryDirection,
+    pub amount: Decimal,
+}
+
+/// A set of postings that must balance per asset before any of them is applied.
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    pub reason: EntryReason,
+    pub reference_id: String,
+    pub postings: Vec<Posting>,
+}
+
+impl Transaction {
+    pub fn new(reason: EntryReason, reference_id: &str) -> Self {
+        Transaction {
+            reason,
+            reference_id: reference_id.to_string(),
+            postings: Vec::new(),
+        }
+    }
+
+    pub fn debit(
+        self,
+        user_id: &str,
+        asset: &str,
+        account: LedgerAccount,
+        amount: Decimal,
+    ) -> Self {
+        self.posting(user_id, asset, account, EntryDirection::Debit, amount)
+    }
+
+    pub fn credit(
+        self,
+        user_id: &str,
+        asset: &str,
+        account: LedgerAccount,
+        amount: Decimal,
+    ) -> Self {
+        self.posting(user_id, asset, account, EntryDirection::Credit, amount)
+    }
+
+    fn posting(
+        mut self,
+        user_id: &str,
+        asset: &str,
+        account: LedgerAccount,
+        direction: EntryDirection,
+        amount: Decimal,
+    ) -> Self {
+        self.postings.push(Posting {
+            user_id: user_id.to_string(),
+            asset: asset.to_string(),
+            account,
+            direction,
+            amount,
+        });
+        self
+    }
+
+    /// Moves funds from a user's available balance into their locked balance.
+    pub fn hold(user_id: &str, asset: &str, amount: Decimal, reference_id: &str) -> Self {
+        Transaction::new(EntryReason::Hold, reference_id)
+            .debit(user_id, asset, LedgerAccount::Available, amount)
+            .credit(user_id, asset, LedgerAccount::Locked, amount)
+    }
+
+    /// Returns previously held funds to the user's available balance.
+    pub fn release(user_id: &str, asset: &str, amount: Decimal, reference_id: &str) -> Self {
+        Transaction::new(EntryReason::Release, reference_id)
+            .debit(user_id, asset, LedgerAccount::Locked, amount)
+            .credit(user_id, asset, LedgerAccount::Available, amount)
+    }
+
+    pub fn deposit(user_id: &str, asset: &str, amount: Decimal, reference_id: &str) -> Self {
+        Transaction::new(EntryReason::Deposit, reference_id)
+            .debit(user_id, asset, LedgerAccount::External, amount)
+            .credit(user_id, asset, LedgerAccount::Available, amount)
+    }
+
+    /// Pays out funds that were held when the withdrawal was requested.
+    pub fn withdrawal(user_id: &str, asset: &str, amount: Decimal, reference_id: &str) -> Self {
+        Transaction::new(EntryReason::Withdrawal, reference_id)
+            .debit(user_id, asset, LedgerAccount::Locked, amount)
+            .credit(user_id, asset, LedgerAccount::External, amount)
+    }
+
+    /// Settles a fill: the buyer's held quote pays the seller and the seller's
+    /// held base is delivered to the buyer.
+    pub fn trade(
+        buyer_id: &str,
+        seller_id: &str,
+        base_asset: &str,
+        quote_asset: &str,
+        quantity: Decimal,
+        trade_value: Decimal,
+        reference_id: &str,
+    ) -> Self {
+        Transaction::new(EntryReason::Trade, reference_id)
+            .debit(buyer_id, quote_asset, LedgerAccount::Locked, trade_value)
+            .credit(
+                seller_id,
+                quote_asset,
+                LedgerAccount::Available,
+                trade_value,
+            )
+            .debit(seller_id, base_asset, LedgerAccount::Locked, quantity)
+            .credit(buyer_id, base_asset, LedgerAccount::Available, quantity)
+    }
+}
+
+pub struct Ledger;
+
+impl Ledger {
+    /// Validates and applies a transaction to the in-memory balances, then
+    /// hands the resulting entries to db-processor. Nothing is applied if the
+    /// transaction is unbalanced or would leave any balance negative.
+    pub fn post(
+        accounts: &AccountStore,
+        transaction: Transaction,
+    ) -> Result<Vec<LedgerEntry>, EngineError> {
+        let entries = Self::apply(accounts, transaction)?;
+
+        let message = LedgerEntriesPayload {
+            data: entries.clone(),
+        };
+        if let Err(e) = RedisManager::instance().push_message_to_db(&message) {
+            error!("Failed to push ledger entries to db processor: {}", e);
+        }
+        publish_balance_updates(accounts, &entries);
+
+        Ok(entries)
+    }
+
+    /// Same as `post` without persisting the entries.
+    pub fn apply(
+        accounts: &AccountStore,
+        transaction: Transaction,
+    ) -> Result<Vec<LedgerEntry>, EngineError> {
+        let mut net_by_asset: HashMap<&str, Decimal> = HashMap::new();
+        // (user, asset) -> (balance delta, locked delta)
+        let mut deltas: HashMap<(&str, &str), (Decimal, Decimal)> = HashMap::new();
+
+        for posting in &transaction.postings {
+            if posting.amount < Decimal::ZERO {
+                return Err(EngineError::Internal(String::from(
+                    "Ledger posting amount must not be negative",
+                )));
+            }
+
+            let signed = match posting.direction {
+                EntryDirection::Debit => -posting.amount,
+                EntryDirection::Credit => posting.amount,
+            };
+            *net_by_asset.entry(&posting.asset).or_insert(Decimal::ZERO) += signed;
+
+            if posting.account == LedgerAccount::External {
+                continue;
+            }
+
+            let delta = deltas
+                .entry((&posting.user_id, &posting.asset))
+                .or_insert((Decimal::ZERO, Decimal::ZERO));
+            delta.0 += signed;
+            if posting.account == LedgerAccount::Locked {
+                delta.1 += signed;
+            }
+        }
+
+        if let Some((asset, _)) = net_by_asset.iter().find(|(_, net)| !net.is_zero()) {
+            return Err(EngineError::Internal(format!(
+                "Unbalanced ledger transaction for asset {}",
+                asset
+            )));
+        }
+
+        let user_ids: Vec<&str> = deltas.keys().map(|(user_id, _)| *user_id).collect();
+        let handles = accounts.get_many(&user_ids).ok_or_else(|| {
+            let missing = user_ids.iter().find(|id| !accounts.contains(id));
+            EngineError::UnknownUser(missing.unwrap_or(&"").to_string())
+        })?;
+        // `get_many` returns handles in id order, lock them in that order
+        let mut users: HashMap<&str, MutexGuard<User>> = handles
+            .iter()
+            .map(|(id, account)| (id.as_str(), account.lock_or_recover()))
+            .collect();
+
+        for ((user_id, asset), (balance_delta, locked_delta)) in &deltas {
+            let (balance, locked) = users[user_id]
+                .balances
+                .get(*asset)
+                .map(|b| (b.balance, b.locked_balance))
+                .unwrap_or((Decimal::ZERO, Decimal::ZERO));
+
+            let (Some(next_balance), Some(next_locked)) = (
+                balance.checked_add(*balance_delta),
+                locked.checked_add(*locked_delta),
+            ) else {
+                return Err(EngineError::InvalidQuantity);
+            };
+            if next_locked < Decimal::ZERO || next_balance < next_locked {
+                return Err(EngineError::InsufficientBalance {
+                    asset: asset.to_string(),
+                });
+            }
+        }
+
+        for ((user_id, asset), (balance_delta, locked_delta)) in deltas {
+            if let Some(user) = users.get_mut(user_id) {
+                let balance = user
+                    .balances
+                    .entry(asset.to_string())
+                    .or_insert_with(|| Balance {
+                        ticker: asset.to_string(),
+                        balance: Decimal::ZERO,
+                        locked_balance: Decimal::ZERO,
+                    });
+                balance.balance += balance_delta;
+                balance.locked_balance += locked_delta;
+            }
+        }
+        // Debiting the external account brings funds in, crediting pays them out
+        for posting in &transaction.postings {
+            if posting.account == LedgerAccount::External {
+                let inflow = match posting.direction {
+                    EntryDirection::Debit => posting.amount,
+                    EntryDirection::Credit => -posting.amount,
+                };
+                accounts.record_external_flow(&posting.asset, inflow);
+            }
+        }
+        drop(users);
+
+        let transaction_id = Uuid::new_v4().to_string();
+        let time = Utc::now();
+        let entries = transaction
+            .postings
+            .into_iter()
+            .map(|posting| LedgerEntry {
+                id: Uuid::new_v4().to_string(),
+                transaction_id: transaction_id.clone(),
+                user_id: posting.user_id,
+                asset: posting.asset,
+                account: posting.account,
+                direction: posting.direction,
+                amount: posting.amount,
+                reason: transaction.reason,
+                reference_id: transaction.reference_id.clone(),
+                time,
+            })
+            .collect();
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn funded(
+        accounts: &AccountStore,
+        user_id: &str,
+        asset: &str,
+        balance: Decimal,
+        locked: Decimal,
+    ) {
+        accounts
+            .get_or_create(user_id)
+            .lock()
+            .unwrap()
+            .balances
+            .insert(
+                asset.to_string(),
+                Balance {
+                    ticker: asset.to_string(),
+                    balance,
+                    locked_balance: locked,
+                },
+            );
+    }
+
+    fn balance_of(accounts: &AccountStore, user_id: &str, asset: &str) -> (Decimal, Decimal) {
+        let user = accounts.get(user_id).unwrap();
+        let user = user.lock().unwrap();
+        user.balances
+            .get(asset)
+            .map(|b| (b.balance, b.locked_balance))
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn trade_moves_held_funds_between_buyer_and_seller() {
+        let accounts = AccountStore::new();
+        funded(&accounts, "buyer", "USDC", dec!(100), dec!(30));
+        funded(&accounts, "seller", "YES", dec!(10), dec!(5));
+
+        let transaction =
+            Transaction::trade("buyer", "seller", "YES", "USDC", dec!(5), dec!(30), "t1");
+        let entries = Ledger::apply(&accounts, transaction).unwrap();
+
+        assert_eq!(entries.len(), 4);
+        assert!(entries
+            .iter()
+            .all(|e| e.transaction_id == entries[0].transaction_id));
+        assert_eq!(balance_of(&accounts, "buyer", "USDC"), (dec!(70), dec!(0)));
+        assert_eq!(balance_of(&accounts, "buyer", "YES"), (dec!(5), dec!(0)));
+        assert_eq!(balance_of(&accounts, "seller", "USDC"), (dec!(30), dec!(0)));
+        assert_eq!(balance_of(&accounts, "seller", "YES"), (dec!(5), dec!(0)));
+    }
+
+    #[test]
+    fn deposit_and_withdrawal_record_external_flows() {
+        let accounts = AccountStore::new();
+        accounts.get_or_create("user");
+
+        Ledger::apply(
+            &accounts,
+            Transaction::deposit("user", "USDC", dec!(50), "d1"),
+        )
+        .unwrap();
+        Ledger::apply(&accounts, Transaction::hold("user", "USDC", dec!(20), "w1")).unwrap();
+        Ledger::apply(
+            &accounts,
+            Transaction::withdrawal("user", "USDC", dec!(20), "w1"),
+        )
+        .unwrap();
+
+        assert_eq!(balance_of(&accounts, "user", "USDC"), (dec!(30), dec!(0)));
+        assert_eq!(accounts.external_flows()["USDC"], dec!(30));
+    }
+
+    #[test]
+    fn unbalanced_transaction_is_rejected() {
+        let accounts = AccountStore::new();
+        funded(&accounts, "user", "USDC", dec!(100), dec!(0));
+
+        let transaction = Transaction::new(EntryReason::Trade, "t1")
+            .debit("user", "USDC", LedgerAccount::Available, dec!(10))
+            .credit("user", "USDC", LedgerAccount::Locked, dec!(9));
+
+        assert!(matches!(
+            Ledger::apply(&accounts, transaction),
+            Err(EngineError::Internal(_))
+        ));
+        assert_eq!(balance_of(&accounts, "user", "USDC"), (dec!(100), dec!(0)));
+    }
+
+    #[test]
+    fn balancing_is_checked_per_asset() {
+        let accounts = AccountStore::new();
+        funded(&accounts, "user", "USDC", dec!(100), dec!(0));
+
+        // Nets to zero overall but not per asset
+        let transaction = Transaction::new(EntryReason::Trade, "t1")
+            .debit("user", "USDC", LedgerAccount::Available, dec!(10))
+            .credit("user", "YES", LedgerAccount::Available, dec!(10));
+
+        assert!(Ledger::apply(&accounts, transaction).is_err());
+        assert_eq!(balance_of(&accounts, "user", "YES"), (dec!(0), dec!(0)));
+    }
+
+    #[test]
+    fn overdraw_leaves_every_balance_untouched() {
+        let accounts = AccountStore::new();
+        funded(&accounts, "buyer", "USDC", dec!(100), dec!(30));
+        // The seller holds less than the trade delivers
+        funded(&accounts, "seller", "YES", dec!(10), dec!(2));
+
+        let transaction =
+            Transaction::trade("buyer", "seller", "YES", "USDC", dec!(5), dec!(30), "t1");
+
+        assert!(matches!(
+            Ledger::apply(&accounts, transaction),
+            Err(EngineError::InsufficientBalance { asset }) if asset == "YES"
+        ));
+        assert_eq!(
+            balance_of(&accounts, "buyer", "USDC"),
+            (dec!(100), dec!(30))
+        );
+        assert_eq!(balance_of(&accounts, "seller", "YES"), (dec!(10), dec!(2)));
+        assert_eq!(balance_of(&accounts, "seller", "USDC"), (dec!(0), dec!(0)));
+    }
+
+    #[test]
+    fn negative_amounts_and_unknown_users_are_rejected() {
+        let accounts = AccountStore::new();
+        funded(&accounts, "user", "USDC", dec!(100), dec!(0));
+
+        let negative = Transaction::hold("user", "USDC", dec!(-1), "h1");
+        assert!(matches!(
+            Ledger::apply(&accounts, negative),
+            Err(EngineError::Internal(_))
+        ));
+
+        let unknown = Transaction::deposit("nobody", "USDC", dec!(1), "d1");
+        assert!(matches!(
+            Ledger::apply(&accounts, unknown),
+            Err(EngineError::UnknownUser(id)) if id == "nobody"
+        ));
+        assert!(accounts.external_flows().is_empty());
+    }
+}
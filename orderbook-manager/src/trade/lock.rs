@@ -0,0 +1,15 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Locking that survives a panic elsewhere. A market worker that panics while
+/// holding a user or summary lock poisons it; that worker halts its market and
+/// the engine re-audits balances once it notices, so every other thread goes
+/// on with what the lock holds instead of panicking in turn.
+pub trait LockExt<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T> LockExt<T> for Mutex<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
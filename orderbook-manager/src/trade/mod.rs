@@ -6,3 +6,39 @@ pub use orderbook::*;
 
 pub mod orderbook_worker;
 pub use orderbook_worker::*;
+
+pub mod position;
+
+pub mod ledger;
+pub use ledger::*;
+
+pub mod funding;
+pub use funding::*;
+
+pub mod account_store;
+pub use account_store::*;
+
+pub mod lock;
+pub use lock::*;
+
+pub mod error;
+pub use error::*;
+
+pub mod amm;
+pub use amm::*;
+
+pub mod ticker_stats;
+pub use ticker_stats::*;
+
+pub mod user_events;
+pub use user_events::*;
+
+pub mod invariants;
+
+pub mod supervision;
+
+pub mod snapshot;
+pub use snapshot::*;
+
+pub mod recovery;
+pub use recovery::*;
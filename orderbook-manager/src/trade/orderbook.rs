@@ -1,12 +1,15 @@
-use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
-};
+use std::collections::{BTreeMap, BTreeSet};
 
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
+use uuid::Uuid;
+
+use crate::models::{
+    BookTicker, CreateOrderPayload, DepthDiff, DepthPayload, Fill, MarketSummary, Order, OrderSide,
+    Position, QuotePayload, User,
+};
 
-use crate::models::{CreateOrderPayload, DepthPayload, Order, OrderSide, QuotePayload, User};
+use super::{AccountStore, EngineError, Ledger, LockExt, Transaction};
 
 #[derive(Debug, Clone)]
 #[allow(unused)]
@@ -15,6 +18,47 @@ pub struct Orderbook {
     pub asks: Vec<Order>,
     pub base_asset: String,
     pub quote_asset: String,
+    pub last_price: Option<Decimal>,
+    /// Resting quantity per price, kept in step with `bids` and `asks`
+    pub bid_levels: BTreeMap<Decimal, Decimal>,
+    pub ask_levels: BTreeMap<Decimal, Decimal>,
+    /// Bumped on every change to the book
+    pub sequence: u64,
+    /// Levels touched since the last diff was taken
+    changed_bids: BTreeSet<Decimal>,
+    changed_asks: BTreeSet<Decimal>,
+    published_sequence: u64,
+}
+
+/// What matching a taker did to the book.
+#[derive(Debug, Default)]
+pub struct Matched {
+    pub fills: Vec<Fill>,
+    /// Makers taken off the book because their side of a fill couldn't be
+    /// settled, with the reason
+    pub dropped: Vec<(Order, EngineError)>,
+    /// Why matching stopped early, when the taker's side couldn't be settled
+    pub unsettled: Option<EngineError>,
+}
+
+/// How much a quote is for: base units, or quote to spend (bid) / receive (ask).
+#[derive(Debug, Clone, Copy)]
+pub enum QuoteAmount {
+    Base(Decimal),
+    Quote(Decimal),
+}
+
+impl Fill {
+    fn new(maker: &Order, quantity: Decimal) -> Self {
+        Fill {
+            trade_id: Uuid::new_v4().to_string(),
+            maker_order_id: maker.id.clone(),
+            maker_user_id: maker.user_id.clone(),
+            price: maker.price,
+            quantity,
+            maker_remaining: maker.quantity - quantity,
+        }
+    }
 }
 
 impl Orderbook {
@@ -24,220 +68,535 @@ impl Orderbook {
             asks: Vec::new(),
             base_asset,
             quote_asset,
+            last_price: None,
+            bid_levels: BTreeMap::new(),
+            ask_levels: BTreeMap::new(),
+            sequence: 0,
+            changed_bids: BTreeSet::new(),
+            changed_asks: BTreeSet::new(),
+            published_sequence: 0,
         }
     }
 
-    pub fn fill_orders(
-        &mut self,
-        order: &CreateOrderPayload,
-        users: &Arc<Mutex<Vec<User>>>,
-        base_asset: &str,
-        quote_asset: &str,
-    ) -> Decimal {
+    pub fn market(&self) -> String {
+        format!("{}_{}", self.base_asset, self.quote_asset)
+    }
+
+    pub fn best_bid(&self) -> Option<Decimal> {
+        self.bid_levels.keys().next_back().copied()
+    }
+
+    pub fn best_ask(&self) -> Option<Decimal> {
+        self.ask_levels.keys().next().copied()
+    }
+
+    pub fn mid_price(&self) -> Option<Decimal> {
+        match (self.best_bid(), self.best_ask()) {
+            (Some(bid), Some(ask)) => Some((bid + ask) / dec!(2)),
+            _ => None,
+        }
+    }
+
+    fn adjust_level(&mut self, side: &OrderSide, price: Decimal, delta: Decimal) {
+        let (levels, changed) = match side {
+            OrderSide::Bid => (&mut self.bid_levels, &mut self.changed_bids),
+            OrderSide::Ask => (&mut self.ask_levels, &mut self.changed_asks),
+        };
+        changed.insert(price);
+
+        let quantity = levels.entry(price).or_insert(Decimal::ZERO);
+        *quantity += delta;
+        if *quantity <= Decimal::ZERO {
+            levels.remove(&price);
+        }
+        self.sequence += 1;
+    }
+
+    /// Refreshes the book derived fields of `summary`, leaving its trade
+    /// statistics alone.
+    pub fn update_summary(&self, summary: &mut MarketSummary) {
+        summary.last_price = self.last_price;
+        summary.mid_price = self.mid_price();
+        summary.book_ticker = self.book_ticker();
+    }
+
+    pub fn book_ticker(&self) -> BookTicker {
+        let best_bid = self.bid_levels.iter().next_back();
+        let best_ask = self.ask_levels.iter().next();
+
+        BookTicker {
+            market: self.market(),
+            bid_price: best_bid.map(|(price, _)| *price),
+            bid_quantity: best_bid.map(|(_, quantity)| *quantity),
+            ask_price: best_ask.map(|(price, _)| *price),
+            ask_quantity: best_ask.map(|(_, quantity)| *quantity),
+        }
+    }
+
+    /// Rests an order on its side of the book, keeping price-time priority.
+    pub fn insert_order(&mut self, order: Order) {
+        self.adjust_level(&order.side, order.price, order.quantity);
+
+        match order.side {
+            OrderSide::Bid => {
+                self.bids.push(order);
+                self.bids.sort_by(|a, b| {
+                    b.price
+                        .cmp(&a.price)
+                        .then_with(|| a.timestamp.cmp(&b.timestamp))
+                });
+            }
+            OrderSide::Ask => {
+                self.asks.push(order);
+                self.asks.sort_by(|a, b| {
+                    a.price
+                        .cmp(&b.price)
+                        .then_with(|| a.timestamp.cmp(&b.timestamp))
+                });
+            }
+        }
+    }
+
+    /// Matches `order` against the opposite side and settles each fill. The
+    /// caller rests whatever quantity the returned fills don't cover, unless
+    /// the taker's side of a fill couldn't be settled: matching then stops
+    /// and the error is returned alongside the fills. A maker whose own side
+    /// can't be settled is taken off the book instead and matching carries on
+    /// behind it, so one broken order can't block its side of the market.
+    pub fn fill_orders(&mut self, order: &CreateOrderPayload, accounts: &AccountStore) -> Matched {
         let mut remaining_qty = order.quantity;
+        let mut matched = Matched::default();
 
+        // Always match against the front of the book; filled makers are removed
+        // so the next best order moves to index 0.
         match order.side {
             OrderSide::Bid => {
-                for i in 0..self.asks.len() {
-                    if self.asks[i].price > order.price {
+                while remaining_qty > Decimal::ZERO {
+                    let Some(best_ask) = self.asks.first() else {
                         break;
-                    }
-
-                    let match_qty = if self.asks[i].quantity > remaining_qty {
-                        remaining_qty
-                    } else {
-                        self.asks[i].quantity
                     };
+                    if best_ask.price > order.price {
+                        break;
+                    }
 
-                    self.flip_balance(
-                        &order.user_id,
-                        &self.asks[i].user_id,
-                        self.asks[i].price,
-                        match_qty,
-                        users,
-                        base_asset,
-                        quote_asset,
-                    );
+                    let match_qty = best_ask.quantity.min(remaining_qty);
+                    let fill = Fill::new(best_ask, match_qty);
+
+                    if let Err(e) =
+                        self.flip_balance(&order.user_id, &fill.maker_user_id, &fill, accounts)
+                    {
+                        if !self.is_makers_fault(&e, &order.side, &fill.maker_user_id) {
+                            matched.unsettled = Some(e);
+                            break;
+                        }
+                        let maker = self.asks.remove(0);
+                        self.adjust_level(&OrderSide::Ask, maker.price, -maker.quantity);
+                        matched.dropped.push((maker, e));
+                        continue;
+                    }
+                    self.last_price = Some(fill.price);
+                    let price = fill.price;
+                    matched.fills.push(fill);
 
                     remaining_qty -= match_qty;
-                    if self.asks[i].quantity == match_qty {
-                        self.asks.remove(i);
+                    self.adjust_level(&OrderSide::Ask, price, -match_qty);
+                    if self.asks[0].quantity == match_qty {
+                        self.asks.remove(0);
                     } else {
-                        self.asks[i].quantity -= match_qty;
-                    }
-
-                    if remaining_qty == dec!(0) {
-                        break;
+                        self.asks[0].quantity -= match_qty;
                     }
                 }
             }
             OrderSide::Ask => {
-                for i in 0..self.bids.len() {
-                    if self.bids[i].price < order.price {
+                while remaining_qty > Decimal::ZERO {
+                    let Some(best_bid) = self.bids.first() else {
                         break;
-                    }
-
-                    let match_qty = if self.bids[i].quantity > remaining_qty {
-                        remaining_qty
-                    } else {
-                        self.bids[i].quantity
                     };
+                    if best_bid.price < order.price {
+                        break;
+                    }
 
-                    self.flip_balance(
-                        &self.bids[i].user_id,
-                        &order.user_id,
-                        self.bids[i].price,
-                        match_qty,
-                        users,
-                        base_asset,
-                        quote_asset,
-                    );
+                    let match_qty = best_bid.quantity.min(remaining_qty);
+                    let fill = Fill::new(best_bid, match_qty);
+
+                    if let Err(e) =
+                        self.flip_balance(&fill.maker_user_id, &order.user_id, &fill, accounts)
+                    {
+                        if !self.is_makers_fault(&e, &order.side, &fill.maker_user_id) {
+                            matched.unsettled = Some(e);
+                            break;
+                        }
+                        let maker = self.bids.remove(0);
+                        self.adjust_level(&OrderSide::Bid, maker.price, -maker.quantity);
+                        matched.dropped.push((maker, e));
+                        continue;
+                    }
+                    self.last_price = Some(fill.price);
+                    let price = fill.price;
+                    matched.fills.push(fill);
 
                     remaining_qty -= match_qty;
-                    if self.bids[i].quantity == match_qty {
-                        self.bids.remove(i);
+                    self.adjust_level(&OrderSide::Bid, price, -match_qty);
+                    if self.bids[0].quantity == match_qty {
+                        self.bids.remove(0);
                     } else {
-                        self.bids[i].quantity -= match_qty;
-                    }
-
-                    if remaining_qty == dec!(0) {
-                        break;
+                        self.bids[0].quantity -= match_qty;
                     }
                 }
             }
         }
 
-        remaining_qty
+        matched
     }
 
-    pub fn get_depth(&self) -> DepthPayload {
-        let mut bids: Vec<[String; 2]> = Vec::new();
-        let mut asks: Vec<[String; 2]> = Vec::new();
-
-        let mut bid_map: HashMap<Decimal, Decimal> = HashMap::new();
-        for bid in &self.bids {
-            *bid_map.entry(bid.price).or_insert(Decimal::ZERO) += bid.quantity;
+    /// Whether a fill against `maker_id` failed on the maker's side, i.e. the
+    /// maker no longer holds what its resting order promised.
+    fn is_makers_fault(&self, error: &EngineError, taker_side: &OrderSide, maker_id: &str) -> bool {
+        let maker_asset = match taker_side {
+            OrderSide::Bid => &self.base_asset,
+            OrderSide::Ask => &self.quote_asset,
+        };
+        match error {
+            EngineError::InsufficientBalance { asset } => asset == maker_asset,
+            EngineError::UnknownUser(user_id) => user_id == maker_id,
+            _ => false,
         }
+    }
 
-        for (price, quantity) in bid_map {
-            bids.push([price.to_string(), quantity.to_string()]);
-        }
+    /// Removes `user_id`'s order `order_id` from whichever side it rests on.
+    pub fn remove_order(&mut self, order_id: &str, user_id: &str) -> Option<Order> {
+        let is_target = |order: &Order| order.id == order_id && order.user_id == user_id;
 
-        let mut ask_map: HashMap<Decimal, Decimal> = HashMap::new();
-        for ask in &self.asks {
-            *ask_map.entry(ask.price).or_insert(Decimal::ZERO) += ask.quantity;
-        }
+        let order = if let Some(index) = self.bids.iter().position(is_target) {
+            self.bids.remove(index)
+        } else if let Some(index) = self.asks.iter().position(is_target) {
+            self.asks.remove(index)
+        } else {
+            return None;
+        };
 
-        for (price, quantity) in ask_map {
-            asks.push([price.to_string(), quantity.to_string()]);
-        }
+        self.adjust_level(&order.side, order.price, -order.quantity);
+        Some(order)
+    }
 
-        bids.sort_by(|a, b| {
-            let price_a: Decimal = a[0].parse().unwrap_or(Decimal::ZERO);
-            let price_b: Decimal = b[0].parse().unwrap_or(Decimal::ZERO);
-            price_b.cmp(&price_a)
-        });
+    /// Removes every order resting for `user_id`, bids first.
+    pub fn remove_user_orders(&mut self, user_id: &str) -> Vec<Order> {
+        let mut removed: Vec<Order> = self.bids.extract_if(.., |o| o.user_id == user_id).collect();
+        removed.extend(self.asks.extract_if(.., |o| o.user_id == user_id));
 
-        asks.sort_by(|a, b| {
-            let price_a: Decimal = a[0].parse().unwrap_or(Decimal::ZERO);
-            let price_b: Decimal = b[0].parse().unwrap_or(Decimal::ZERO);
-            price_a.cmp(&price_b)
-        });
+        for order in &removed {
+            self.adjust_level(&order.side, order.price, -order.quantity);
+        }
+        removed
+    }
 
-        DepthPayload { bids, asks }
+    /// Depth from the level cache, best levels first. With a `bucket` prices
+    /// are grouped outwards from the spread (bids down, asks up) so the
+    /// aggregated book never looks tighter than it is.
+    pub fn get_depth(&self, limit: Option<usize>, bucket: Option<Decimal>) -> DepthPayload {
+        let bids = Self::aggregate_levels(self.bid_levels.iter().rev(), limit, bucket, false);
+        let asks = Self::aggregate_levels(self.ask_levels.iter(), limit, bucket, true);
+
+        let best_bid = self.best_bid();
+        let best_ask = self.best_ask();
+
+        DepthPayload {
+            bids,
+            asks,
+            best_bid,
+            best_ask,
+            spread: best_bid.zip(best_ask).map(|(bid, ask)| ask - bid),
+            sequence: self.sequence,
+        }
     }
 
-    pub fn get_quote_detail(&self, quantity: Decimal, side: OrderSide) -> QuotePayload {
-        let mut remaining_qty = quantity;
-        let mut total_cost = Decimal::from(0);
-        let mut weighted_avg_price = Decimal::from(0);
+    /// Levels changed since the previous call with their new absolute
+    /// quantity, `"0"` for removed levels. Update ids are the book sequence
+    /// numbers covered, so each diff's `first_update_id` is the previous
+    /// diff's `last_update_id + 1`.
+    pub fn take_depth_diff(&mut self) -> Option<DepthDiff> {
+        if self.sequence == self.published_sequence {
+            return None;
+        }
 
-        match side {
-            OrderSide::Bid => {
-                for ask in self.asks.iter() {
-                    if remaining_qty == Decimal::from(0) {
-                        break;
-                    }
+        let level = |levels: &BTreeMap<Decimal, Decimal>, price: Decimal| {
+            let quantity = levels.get(&price).copied().unwrap_or(Decimal::ZERO);
+            [
+                price.normalize().to_string(),
+                quantity.normalize().to_string(),
+            ]
+        };
+        let bids = std::mem::take(&mut self.changed_bids)
+            .into_iter()
+            .rev()
+            .map(|price| level(&self.bid_levels, price))
+            .collect();
+        let asks = std::mem::take(&mut self.changed_asks)
+            .into_iter()
+            .map(|price| level(&self.ask_levels, price))
+            .collect();
+
+        let diff = DepthDiff {
+            first_update_id: self.published_sequence + 1,
+            last_update_id: self.sequence,
+            bids,
+            asks,
+        };
+        self.published_sequence = self.sequence;
+        Some(diff)
+    }
 
-                    if remaining_qty > ask.quantity {
-                        total_cost += ask.price * ask.quantity;
-                        remaining_qty -= ask.quantity;
-                    } else {
-                        total_cost += ask.price * remaining_qty;
-                        remaining_qty = Decimal::from(0);
-                        break;
-                    }
-                }
-            }
-            OrderSide::Ask => {
-                for bid in self.bids.iter() {
-                    if remaining_qty == Decimal::from(0) {
-                        break;
-                    }
-                    if remaining_qty >= bid.quantity {
-                        total_cost += bid.price * bid.quantity;
-                        remaining_qty -= bid.quantity;
-                    } else {
-                        total_cost += bid.price * remaining_qty;
-                        remaining_qty = Decimal::from(0);
+    fn aggregate_levels<'a>(
+        levels: impl Iterator<Item = (&'a Decimal, &'a Decimal)>,
+        limit: Option<usize>,
+        bucket: Option<Decimal>,
+        round_up: bool,
+    ) -> Vec<[String; 2]> {
+        let mut aggregated: Vec<(Decimal, Decimal)> = Vec::new();
+
+        for (price, quantity) in levels {
+            let price = match bucket {
+                Some(bucket) if round_up => (price / bucket).ceil() * bucket,
+                Some(bucket) => (price / bucket).floor() * bucket,
+                None => *price,
+            };
+
+            match aggregated.last_mut() {
+                Some(last) if last.0 == price => last.1 += quantity,
+                _ => {
+                    if limit.is_some_and(|limit| aggregated.len() >= limit) {
                         break;
                     }
+                    aggregated.push((price, *quantity));
                 }
             }
         }
 
-        if remaining_qty < quantity {
-            weighted_avg_price = total_cost / (quantity - remaining_qty);
+        aggregated
+            .into_iter()
+            .map(|(price, quantity)| {
+                [
+                    price.normalize().to_string(),
+                    quantity.normalize().to_string(),
+                ]
+            })
+            .collect()
+    }
+
+    /// Walks the opposite side of the book to price a taker order without
+    /// touching it. Thin books give a partial quote with `fully_filled` unset.
+    pub fn get_quote_detail(&self, side: OrderSide, amount: QuoteAmount) -> QuotePayload {
+        let levels = match side {
+            OrderSide::Bid => &self.asks,
+            OrderSide::Ask => &self.bids,
+        };
+
+        let mut filled_qty = Decimal::ZERO;
+        let mut total_cost = Decimal::ZERO;
+        let mut worst_price = None;
+        let mut levels_consumed = 0;
+
+        for order in levels {
+            let remaining_qty = match amount {
+                QuoteAmount::Base(quantity) => quantity - filled_qty,
+                QuoteAmount::Quote(quote_amount) => (quote_amount - total_cost) / order.price,
+            };
+            if remaining_qty <= Decimal::ZERO {
+                break;
+            }
+
+            let match_qty = order.quantity.min(remaining_qty);
+            filled_qty += match_qty;
+            total_cost += match_qty * order.price;
+
+            if worst_price != Some(order.price) {
+                worst_price = Some(order.price);
+                levels_consumed += 1;
+            }
         }
 
+        let fully_filled = match amount {
+            QuoteAmount::Base(quantity) => filled_qty == quantity,
+            // Division can leave the cost a hair above the requested amount
+            QuoteAmount::Quote(quote_amount) => total_cost >= quote_amount,
+        };
+
+        let avg_price = if filled_qty > Decimal::ZERO {
+            total_cost / filled_qty
+        } else {
+            Decimal::ZERO
+        };
+
+        let mid_price = self.mid_price();
+        let price_impact = mid_price
+            .filter(|mid| *mid > Decimal::ZERO && filled_qty > Decimal::ZERO)
+            .map(|mid| ((avg_price - mid) / mid).abs());
+
+        // No fees are charged yet, they would come out of what the taker receives
+        let fee_asset = match side {
+            OrderSide::Bid => &self.base_asset,
+            OrderSide::Ask => &self.quote_asset,
+        };
+
         QuotePayload {
-            avg_price: weighted_avg_price,
-            quantity,
+            side,
+            avg_price,
+            quantity: filled_qty,
             total_cost,
+            fully_filled,
+            worst_price,
+            mid_price,
+            price_impact,
+            levels_consumed,
+            estimated_fee: Decimal::ZERO,
+            fee_asset: fee_asset.clone(),
         }
     }
 
+    /// Settles `fill` between the two users and updates their positions. A
+    /// failed posting changes nothing, so the fill can be dropped.
     fn flip_balance(
         &self,
         buyer_id: &str,
         seller_id: &str,
-        price: Decimal,
-        quantity: Decimal,
-        users: &Arc<Mutex<Vec<User>>>,
-        base_asset: &str,
-        quote_asset: &str,
-    ) {
-        let mut users_guard = users.lock().unwrap();
+        fill: &Fill,
+        accounts: &AccountStore,
+    ) -> Result<(), EngineError> {
+        let (price, quantity) = (fill.price, fill.quantity);
         let trade_value = price * quantity;
+        let market = self.market();
+
+        let trade_id = &fill.trade_id;
+        let transaction = Transaction::trade(
+            buyer_id,
+            seller_id,
+            &self.base_asset,
+            &self.quote_asset,
+            quantity,
+            trade_value,
+            trade_id,
+        );
+        Ledger::post(accounts, transaction)?;
 
-        if let Some(seller) = users_guard.iter_mut().find(|u| u.id == seller_id) {
-            if let Some(base_balance) = seller.balances.iter_mut().find(|b| b.ticker == base_asset)
-            {
-                base_balance.locked_balance =
-                    base_balance.locked_balance.checked_sub(quantity).unwrap();
-                base_balance.balance = base_balance.balance.checked_sub(quantity).unwrap();
-            }
+        if let Some(seller) = accounts.get(seller_id) {
+            Self::position_for(&mut seller.lock_or_recover(), &market).apply_fill(-quantity, price);
+        }
 
-            if let Some(quote_balance) =
-                seller.balances.iter_mut().find(|b| b.ticker == quote_asset)
-            {
-                quote_balance.balance = quote_balance.balance.checked_add(trade_value).unwrap();
-            }
+        if let Some(buyer) = accounts.get(buyer_id) {
+            Self::position_for(&mut buyer.lock_or_recover(), &market).apply_fill(quantity, price);
         }
+        Ok(())
+    }
 
-        if let Some(buyer) = users_guard.iter_mut().find(|u| u.id == buyer_id) {
-            if let Some(base_balance) = buyer.balances.iter_mut().find(|b| b.ticker == base_asset) {
-                base_balance.balance = base_balance.balance.checked_add(quantity).unwrap();
-            }
+    fn position_for<'a>(user: &'a mut User, market: &str) -> &'a mut Position {
+        user.positions
+            .entry(market.to_string())
+            .or_insert_with(|| Position::new(market))
+    }
+}
 
-            if let Some(quote_balance) = buyer.balances.iter_mut().find(|b| b.ticker == quote_asset)
-            {
-                quote_balance.locked_balance = quote_balance
-                    .locked_balance
-                    .checked_sub(trade_value)
-                    .unwrap();
-                quote_balance.balance = quote_balance.balance.checked_sub(trade_value).unwrap();
-            }
+#[cfg(test)]
+mod tests {
+    use crate::models::Balance;
+
+    use super::*;
+
+    fn fund(
+        accounts: &AccountStore,
+        user_id: &str,
+        asset: &str,
+        balance: Decimal,
+        locked: Decimal,
+    ) {
+        accounts
+            .get_or_create(user_id)
+            .lock()
+            .unwrap()
+            .balances
+            .insert(
+                asset.to_string(),
+                Balance {
+                    ticker: asset.to_string(),
+                    balance,
+                    locked_balance: locked,
+                },
+            );
+    }
+
+    fn ask(id: &str, user_id: &str, price: Decimal, quantity: Decimal) -> Order {
+        Order {
+            id: id.to_string(),
+            user_id: user_id.to_string(),
+            price,
+            quantity,
+            side: OrderSide::Ask,
+            timestamp: 0,
+        }
+    }
+
+    fn bid(user_id: &str, price: Decimal, quantity: Decimal) -> CreateOrderPayload {
+        CreateOrderPayload {
+            user_id: user_id.to_string(),
+            market: String::from("YES_USDC"),
+            price,
+            quantity,
+            side: OrderSide::Bid,
+        }
+    }
+
+    #[test]
+    fn a_maker_that_cannot_settle_is_dropped_and_matching_continues_behind_it() {
+        let accounts = AccountStore::new();
+        // The best ask rests without the YES it would have to hold
+        accounts.get_or_create("broken");
+        fund(&accounts, "seller", "YES", dec!(100), dec!(100));
+        for taker in ["first", "next"] {
+            fund(&accounts, taker, "USDC", dec!(100), dec!(30));
         }
+
+        let mut book = Orderbook::new(String::from("YES"), String::from("USDC"));
+        book.insert_order(ask("lost-hold", "broken", dec!(0.5), dec!(100)));
+        book.insert_order(ask("behind", "seller", dec!(0.6), dec!(100)));
+
+        let matched = book.fill_orders(&bid("first", dec!(0.6), dec!(50)), &accounts);
+        assert!(matched.unsettled.is_none());
+        assert!(matches!(
+            matched.dropped.as_slice(),
+            [(order, EngineError::InsufficientBalance { asset })]
+                if order.id == "lost-hold" && asset == "YES"
+        ));
+        assert_eq!(matched.fills.len(), 1);
+        assert_eq!(matched.fills[0].maker_order_id, "behind");
+        assert_eq!(book.ask_levels.get(&dec!(0.5)), None);
+
+        let matched = book.fill_orders(&bid("next", dec!(0.6), dec!(50)), &accounts);
+        assert!(matched.unsettled.is_none() && matched.dropped.is_empty());
+        assert_eq!(matched.fills[0].maker_order_id, "behind");
+        assert!(book.asks.is_empty());
+        assert_eq!(book.last_price, Some(dec!(0.6)));
+    }
+
+    #[test]
+    fn a_taker_that_cannot_settle_leaves_the_maker_resting() {
+        let accounts = AccountStore::new();
+        fund(&accounts, "seller", "YES", dec!(100), dec!(100));
+        // The taker's hold went missing
+        fund(&accounts, "taker", "USDC", dec!(100), dec!(0));
+
+        let mut book = Orderbook::new(String::from("YES"), String::from("USDC"));
+        book.insert_order(ask("ask", "seller", dec!(0.5), dec!(100)));
+        let sequence = book.sequence;
+
+        let matched = book.fill_orders(&bid("taker", dec!(0.5), dec!(100)), &accounts);
+
+        assert!(matched.fills.is_empty() && matched.dropped.is_empty());
+        assert!(matches!(
+            matched.unsettled,
+            Some(EngineError::InsufficientBalance { asset }) if asset == "USDC"
+        ));
+        assert_eq!(book.asks.len(), 1);
+        assert_eq!(book.ask_levels.get(&dec!(0.5)), Some(&dec!(100)));
+        assert_eq!(book.sequence, sequence);
+        assert_eq!(book.last_price, None);
     }
 }
@@ -1,187 +1,437 @@
 #![allow(unused_variables)]
 
 use std::{
+    any::Any,
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
     sync::{mpsc, Arc, Mutex},
     thread,
+    time::Instant,
 };
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use crossbeam_channel::{select, Sender, TrySendError};
+use metrics::{counter, gauge, histogram};
 use rust_decimal::Decimal;
-use rust_decimal_macros::dec;
 use serde_json::json;
 use tracing::{error, info};
 use uuid::Uuid;
 
 use crate::{
+    constant::DEFAULT_WORKER_QUEUE_CAPACITY,
     models::{
-        AddTradePayload, CancelOrderPayload, CreateOrderPayload, GetOpenOrdersPayload,
-        MessageToApi, OpenOrders, Order, OrderCancelledPayload, OrderPlacedPayload, OrderSide,
-        OrderbookMessage, TradeData, User,
+        AddTradePayload, BookAudit, CancelOrderPayload, CreateOrderPayload, Fill,
+        GetOpenOrdersPayload, MarketSummary, MessageToApi, OpenOrders, Order,
+        OrderCancelledPayload, OrderPlacedPayload, OrderSide, OrderStatus, OrderUpdate,
+        OrderbookMessage, TradeData,
     },
     services::RedisManager,
 };
-use std::str::FromStr;
 
-use super::Orderbook;
+use super::{
+    publish_order_update, reject, AccountStore, AmmConfig, EngineError, Ledger, LmsrAmm, LockExt,
+    Orderbook, QuoteAmount, Transaction,
+};
 
 #[allow(unused)]
 pub struct OrderbookWorker {
     pub market: String,
+    /// Orders are rejected with `MARKET_CLOSED` from this time on
+    pub end_time: Option<DateTime<Utc>>,
+    pub amm_subsidy: Option<Decimal>,
     pub orderbook: Orderbook,
-    pub users: Arc<Mutex<Vec<User>>>,
-    pub sender: mpsc::Sender<OrderbookMessage>,
+    pub accounts: Arc<AccountStore>,
+    /// Orders, queries and the engine's own messages, in arrival order
+    commands: Sender<OrderbookMessage>,
+    /// Cancels, handled ahead of anything waiting in `commands`
+    cancels: Sender<OrderbookMessage>,
     pub thread_handle: Option<thread::JoinHandle<()>>,
+    halt: Arc<Mutex<Option<MarketHalt>>>,
+}
+
+/// Why a market stopped trading.
+#[derive(Debug, Clone)]
+pub struct MarketHalt {
+    pub reason: String,
+    pub time: DateTime<Utc>,
+    /// Whether balances were checked since, as the worker may have stopped
+    /// half way through changing them
+    pub audited: bool,
+}
+
+fn panic_reason(panic: &(dyn Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| String::from("unknown panic"))
 }
 
 impl OrderbookWorker {
+    /// Starts the worker thread for `orderbook`, which holds the orders
+    /// restored from a snapshot, if any.
     pub fn new(
         market: String,
-        base_asset: String,
-        quote_asset: String,
-        users: Arc<Mutex<Vec<User>>>,
+        orderbook: Orderbook,
+        end_time: Option<DateTime<Utc>>,
+        amm_subsidy: Option<Decimal>,
+        accounts: Arc<AccountStore>,
+        market_summaries: Arc<Mutex<HashMap<String, MarketSummary>>>,
     ) -> Self {
-        let (sender, receiver) = mpsc::channel();
-        let orderbook = Orderbook::new(base_asset.clone(), quote_asset.clone());
+        let capacity = worker_queue_capacity();
+        let (commands, command_queue) = crossbeam_channel::bounded(capacity);
+        let (cancels, cancel_queue) = crossbeam_channel::bounded(capacity);
+        gauge!("engine_worker_queue_capacity", "market" => market.clone()).set(capacity as f64);
         let orderbook_clone = orderbook.clone();
-        let users_clone = users.clone();
+        let accounts_clone = accounts.clone();
         let market_clone = market.clone();
+        let halt = Arc::new(Mutex::new(None));
+        let halt_clone = halt.clone();
 
         let thread_handle = thread::spawn(move || {
             info!("Started orderbook thread for market: {}", market_clone);
             let mut orderbook = orderbook;
 
+            let mut amm = amm_subsidy.and_then(|subsidy| {
+                LmsrAmm::new(&orderbook, subsidy, AmmConfig::from_env(), &accounts)
+                    .inspect_err(|e| error!("Failed to start AMM for {}: {}", market_clone, e))
+                    .ok()
+            });
+            if let Some(amm) = amm.as_mut() {
+                amm.requote(&mut orderbook, &accounts);
+            }
+            Self::publish_book_changes(&mut orderbook, &market_summaries);
+
+            let match_duration =
+                histogram!("engine_match_duration_seconds", "market" => market_clone.clone());
+
             loop {
-                match receiver.recv() {
-                    Ok(message) => match message {
-                        OrderbookMessage::CreateOrder { client_id, payload } => {
-                            info!("Processing create order for market: {}", market_clone);
-                            Self::handle_create_order(&mut orderbook, &users, client_id, payload);
-                        }
-                        OrderbookMessage::CancelOrder { client_id, payload } => {
-                            info!("Processing cancel order for market: {}", market_clone);
-                            Self::handle_cancel_order(&mut orderbook, &users, client_id, payload);
-                        }
-                        OrderbookMessage::GetDepth { client_id, market } => {
-                            info!("Processing get depth for market: {}", market_clone);
-                            Self::handle_get_depth(&orderbook, client_id);
-                        }
-                        OrderbookMessage::GetOpenOrders { client_id, payload } => {
-                            info!("Processing get open orders for market: {}", market_clone);
-                            Self::handle_get_open_orders(&orderbook, client_id, payload);
-                        }
-                        OrderbookMessage::GetQuote {
-                            client_id,
-                            market,
-                            quantity,
-                            side,
-                        } => {
-                            info!("Processing get quote for market: {}", market_clone);
-                            Self::handle_get_quote(&orderbook, client_id, quantity, side);
-                        }
-                        OrderbookMessage::ShutDown => {
-                            info!("Processing shutdown for market: {}", market_clone);
-                        }
+                // A cancel waiting behind a burst of orders would otherwise
+                // leave its order exposed to every one of them
+                let received = match cancel_queue.try_recv() {
+                    Ok(message) => Ok(message),
+                    Err(_) => select! {
+                        recv(cancel_queue) -> message => message,
+                        recv(command_queue) -> message => message,
                     },
+                };
+                let message = match received {
+                    Ok(message) => message,
                     Err(e) => {
                         error!("Error receiving message in orderbook thread: {}", e);
                         break;
                     }
+                };
+                record_queue_depth(&market_clone, command_queue.len(), cancel_queue.len());
+
+                if let OrderbookMessage::ShutDown = message {
+                    info!("Processing shutdown for market: {}", market_clone);
+                    break;
+                }
+                if halt.lock_or_recover().is_some() {
+                    Self::handle_halted(&orderbook, &market_clone, message);
+                    continue;
+                }
+
+                let client_id = message.client_id().map(str::to_string);
+                // The book may be half way through a change after a panic, so
+                // the market stops trading instead of carrying on with it
+                let handled = panic::catch_unwind(AssertUnwindSafe(|| match message {
+                    OrderbookMessage::CreateOrder { client_id, payload } => {
+                        info!("Processing create order for market: {}", market_clone);
+                        let side = match payload.side {
+                            OrderSide::Bid => "bid",
+                            OrderSide::Ask => "ask",
+                        };
+                        let started = Instant::now();
+                        let outcome = match Self::handle_create_order(
+                            &mut orderbook,
+                            amm.as_mut(),
+                            &accounts,
+                            &market_summaries,
+                            &client_id,
+                            payload,
+                        ) {
+                            Ok(status) => status.as_str(),
+                            Err(e) => {
+                                let code = e.code();
+                                reject(&client_id, e);
+                                code
+                            }
+                        };
+                        match_duration.record(started.elapsed());
+                        counter!(
+                            "engine_orders_total",
+                            "market" => market_clone.clone(),
+                            "side" => side,
+                            "outcome" => outcome,
+                        )
+                        .increment(1);
+                        Self::publish_book_changes(&mut orderbook, &market_summaries);
+                    }
+                    OrderbookMessage::CancelOrder { client_id, payload } => {
+                        info!("Processing cancel order for market: {}", market_clone);
+                        let outcome = match Self::handle_cancel_order(
+                            &mut orderbook,
+                            &accounts,
+                            &client_id,
+                            payload,
+                        ) {
+                            Ok(()) => OrderStatus::Cancelled.as_str(),
+                            Err(e) => {
+                                let code = e.code();
+                                reject(&client_id, e);
+                                code
+                            }
+                        };
+                        counter!(
+                            "engine_cancels_total",
+                            "market" => market_clone.clone(),
+                            "outcome" => outcome,
+                        )
+                        .increment(1);
+                        Self::publish_book_changes(&mut orderbook, &market_summaries);
+                    }
+                    OrderbookMessage::GetDepth {
+                        client_id,
+                        market,
+                        limit,
+                        bucket,
+                    } => {
+                        info!("Processing get depth for market: {}", market_clone);
+                        Self::handle_get_depth(&orderbook, client_id, limit, bucket);
+                    }
+                    OrderbookMessage::GetOpenOrders { client_id, payload } => {
+                        info!("Processing get open orders for market: {}", market_clone);
+                        Self::handle_get_open_orders(&orderbook, client_id, payload);
+                    }
+                    OrderbookMessage::GetQuote {
+                        client_id,
+                        market,
+                        amount,
+                        side,
+                    } => {
+                        info!("Processing get quote for market: {}", market_clone);
+                        Self::handle_get_quote(&orderbook, client_id, amount, side);
+                    }
+                    OrderbookMessage::GetAmmState { client_id } => {
+                        info!("Processing get AMM state for market: {}", market_clone);
+                        Self::handle_get_amm_state(&orderbook, amm.as_ref(), &accounts, client_id);
+                    }
+                    OrderbookMessage::Audit { reply, resume } => {
+                        Self::handle_audit(&orderbook, &market_clone, reply, resume);
+                    }
+                    // Handled before the book is touched
+                    OrderbookMessage::ShutDown => {}
+                }));
+
+                if let Err(panic) = handled {
+                    let reason = panic_reason(&*panic);
+                    error!(
+                        market = market_clone,
+                        reason, "Orderbook worker panicked, halting market"
+                    );
+                    *halt.lock_or_recover() = Some(MarketHalt {
+                        reason,
+                        time: Utc::now(),
+                        audited: false,
+                    });
+                    if let Some(client_id) = client_id {
+                        reject(&client_id, EngineError::MarketHalted(market_clone.clone()));
+                    }
                 }
             }
         });
 
         OrderbookWorker {
             market,
+            end_time,
+            amm_subsidy,
             orderbook: orderbook_clone,
-            users: users_clone,
-            sender,
+            accounts: accounts_clone,
+            commands,
+            cancels,
             thread_handle: Some(thread_handle),
+            halt: halt_clone,
         }
     }
 
+    /// Why and when the market stopped trading, if it did.
+    pub fn halt(&self) -> Option<MarketHalt> {
+        self.halt.lock_or_recover().clone()
+    }
+
+    /// Marks the market halted unless it already is. Returns whether it wasn't.
+    pub fn mark_halted(&self, reason: &str) -> bool {
+        let mut halt = self.halt.lock_or_recover();
+        if halt.is_some() {
+            return false;
+        }
+        *halt = Some(MarketHalt {
+            reason: reason.to_string(),
+            time: Utc::now(),
+            audited: false,
+        });
+        true
+    }
+
+    /// Marks the halt audited. Returns whether it is one balances weren't
+    /// checked after yet.
+    pub fn take_unaudited_halt(&self) -> bool {
+        match self.halt.lock_or_recover().as_mut() {
+            Some(halt) if !halt.audited => {
+                halt.audited = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// True once the worker thread has stopped, for whatever reason.
+    pub fn is_dead(&self) -> bool {
+        self.thread_handle
+            .as_ref()
+            .is_none_or(|handle| handle.is_finished())
+    }
+
+    /// A halted worker still answers audits so its book can be checked and
+    /// restored, and rejects everything else.
+    fn handle_halted(orderbook: &Orderbook, market: &str, message: OrderbookMessage) {
+        match message {
+            OrderbookMessage::Audit { reply, resume } => {
+                Self::handle_audit(orderbook, market, reply, resume)
+            }
+            message => {
+                if let Some(client_id) = message.client_id() {
+                    reject(client_id, EngineError::MarketHalted(market.to_string()));
+                }
+            }
+        }
+    }
+
+    fn handle_audit(
+        orderbook: &Orderbook,
+        market: &str,
+        reply: mpsc::Sender<BookAudit>,
+        resume: mpsc::Receiver<()>,
+    ) {
+        let audit = BookAudit {
+            market: market.to_string(),
+            base_asset: orderbook.base_asset.clone(),
+            quote_asset: orderbook.quote_asset.clone(),
+            orders: orderbook
+                .bids
+                .iter()
+                .chain(orderbook.asks.iter())
+                .cloned()
+                .collect(),
+        };
+        if reply.send(audit).is_ok() {
+            let _ = resume.recv();
+        }
+    }
+
+    /// Queues `message` for the worker thread. Client requests are refused
+    /// with `Full` when their queue is, while the engine's own messages wait
+    /// for room so an audit or shutdown is never lost.
+    pub fn send(&self, message: OrderbookMessage) -> Result<(), TrySendError<OrderbookMessage>> {
+        let (queue, name) = match message {
+            OrderbookMessage::CancelOrder { .. } => (&self.cancels, "cancels"),
+            _ => (&self.commands, "commands"),
+        };
+        let sent = if message.client_id().is_some() {
+            queue.try_send(message)
+        } else {
+            queue
+                .send(message)
+                .map_err(|e| TrySendError::Disconnected(e.into_inner()))
+        };
+        if sent.as_ref().is_err_and(TrySendError::is_full) {
+            counter!("engine_worker_shed_total", "market" => self.market.clone(), "queue" => name)
+                .increment(1);
+        }
+        sent?;
+        record_queue_depth(&self.market, self.commands.len(), self.cancels.len());
+        Ok(())
+    }
+
+    pub fn is_closed(&self, now: DateTime<Utc>) -> bool {
+        self.end_time.is_some_and(|end_time| now >= end_time)
+    }
+
     fn handle_create_order(
         orderbook: &mut Orderbook,
-        users: &Arc<Mutex<Vec<User>>>,
-        client_id: String,
+        amm: Option<&mut LmsrAmm>,
+        accounts: &AccountStore,
+        market_summaries: &Mutex<HashMap<String, MarketSummary>>,
+        client_id: &str,
         payload: CreateOrderPayload,
-    ) {
+    ) -> Result<OrderStatus, EngineError> {
         let order_id = Uuid::new_v4().to_string();
         let redis_manager = RedisManager::instance();
 
-        let is_valid = {
-            let mut users_guard = users.lock().unwrap();
-
-            let user = users_guard
-                .iter_mut()
-                .find(|u| u.id == payload.user_id)
-                .expect("User not found");
-
-            match payload.side {
-                OrderSide::Bid => {
-                    let usdc_balance = user
-                        .balances
-                        .iter()
-                        .find(|b| b.ticker == "USDC".to_string())
-                        .map(|b| b.balance - b.locked_balance)
-                        .unwrap_or(dec!(0));
-
-                    let required_amount = payload.price * payload.quantity;
-                    if usdc_balance >= required_amount {
-                        if let Some(balance) = user
-                            .balances
-                            .iter_mut()
-                            .find(|b| b.ticker == "USDC".to_string())
-                        {
-                            balance.locked_balance += required_amount;
-                        }
-                        true
-                    } else {
-                        false
-                    }
-                }
-                OrderSide::Ask => {
-                    let sol_balance = user
-                        .balances
-                        .iter()
-                        .find(|b| b.ticker == "SOL".to_string())
-                        .map(|b| b.balance - b.locked_balance)
-                        .unwrap_or(dec!(0));
-
-                    if sol_balance >= payload.quantity {
-                        if let Some(balance) = user
-                            .balances
-                            .iter_mut()
-                            .find(|b| b.ticker == "SOL".to_string())
-                        {
-                            balance.locked_balance += payload.quantity;
-                        }
-                        true
-                    } else {
-                        false
-                    }
-                }
-            }
+        let (hold_asset, hold_amount) = match payload.side {
+            OrderSide::Bid => (
+                orderbook.quote_asset.clone(),
+                payload.price * payload.quantity,
+            ),
+            OrderSide::Ask => (orderbook.base_asset.clone(), payload.quantity),
         };
 
-        if !is_valid {
-            error!("Insufficient balance for trade");
-            let message = MessageToApi::OrderCancelled {
-                payload: OrderCancelledPayload {
-                    message: Some(String::from("Insufficient balance for trade")),
-                },
+        let transaction = Transaction::hold(&payload.user_id, &hold_asset, hold_amount, &order_id);
+        Ledger::post(accounts, transaction)?;
+
+        let matched = orderbook.fill_orders(&payload, accounts);
+        for (maker, e) in matched.dropped {
+            Self::cancel_unsettled_maker(orderbook, accounts, &payload.market, maker, &e);
+        }
+        let (fills, unsettled) = (matched.fills, matched.unsettled);
+        let filled_qty: Decimal = fills.iter().map(|fill| fill.quantity).sum();
+        let remaining_qty = payload.quantity - filled_qty;
+
+        // Resting the rest would cross the maker it failed against, so it's
+        // cancelled and its hold given back
+        if let Some(e) = &unsettled {
+            error!(
+                order_id,
+                "Failed to settle the taker's side of a fill, cancelling the rest: {}", e
+            );
+            let unfilled_hold = match payload.side {
+                OrderSide::Bid => payload.price * remaining_qty,
+                OrderSide::Ask => remaining_qty,
             };
-            let _ = redis_manager.send_to_api(&client_id, &message);
-            return;
+            let transaction =
+                Transaction::release(&payload.user_id, &hold_asset, unfilled_hold, &order_id);
+            if let Err(e) = Ledger::post(accounts, transaction) {
+                error!(order_id, "Failed to release the cancelled rest: {}", e);
+            }
+            if fills.is_empty() {
+                return Err(EngineError::Internal(String::from(
+                    "Order could not be settled",
+                )));
+            }
         }
 
-        let market: Vec<&str> = payload.market.split('_').collect();
-        let base_asset = market[0];
-        let quote_asset = market[1];
-
-        let remaining_qty = orderbook.fill_orders(&payload, users, base_asset, quote_asset);
-        let filled_qty = payload.quantity.checked_sub(remaining_qty).unwrap();
+        // A bid holds its own price but pays the maker's, give back the difference
+        if let OrderSide::Bid = payload.side {
+            let improvement: Decimal = fills
+                .iter()
+                .map(|fill| (payload.price - fill.price) * fill.quantity)
+                .sum();
+            if improvement > Decimal::ZERO {
+                let transaction = Transaction::release(
+                    &payload.user_id,
+                    &orderbook.quote_asset,
+                    improvement,
+                    &order_id,
+                );
+                if let Err(e) = Ledger::post(accounts, transaction) {
+                    error!(order_id, "Failed to release price improvement: {}", e);
+                }
+            }
+        }
 
-        if remaining_qty > Decimal::ZERO {
+        if remaining_qty > Decimal::ZERO && unsettled.is_none() {
             let new_order = Order {
                 id: order_id.clone(),
                 user_id: payload.user_id.clone(),
@@ -191,24 +441,11 @@ impl OrderbookWorker {
                 timestamp: Utc::now().timestamp(),
             };
 
-            match payload.side {
-                OrderSide::Bid => {
-                    orderbook.bids.push(new_order);
-                    orderbook.bids.sort_by(|a, b| {
-                        b.price
-                            .cmp(&a.price)
-                            .then_with(|| a.timestamp.cmp(&b.timestamp))
-                    });
-                }
-                OrderSide::Ask => {
-                    orderbook.asks.push(new_order);
-                    orderbook.asks.sort_by(|a, b| {
-                        a.price
-                            .cmp(&b.price)
-                            .then_with(|| a.timestamp.cmp(&b.timestamp))
-                    });
-                }
-            }
+            orderbook.insert_order(new_order);
+        }
+
+        if let Some(amm) = amm {
+            amm.requote(orderbook, accounts);
         }
 
         info!(
@@ -226,145 +463,259 @@ impl OrderbookWorker {
             },
         };
 
-        let _ = redis_manager.send_to_api(&client_id, &message);
-
-        let depth = orderbook.get_depth();
-
-        match payload.side {
-            OrderSide::Bid => {
-                let matching_ask = depth
-                    .asks
-                    .iter()
-                    .find(|x| {
-                        Decimal::from_str(&x[0].to_string()).unwrap_or(dec!(0)) <= payload.price
-                    })
-                    .cloned();
-
-                info!("publish ws depth updates for bid");
-
-                let message = json!({
-                    "stream": format!("depth@{}", payload.market),
-                    "data": {
-                        "a": if let Some(ask) = matching_ask { vec![ask] } else { Vec::new() },
-                        "b": depth.bids,
-                        "e": "depth"
-                    }
-                });
+        let _ = redis_manager.send_to_api(client_id, &message);
+        Self::publish_order_updates(&payload, &order_id, &fills);
+        if unsettled.is_some() {
+            publish_order_update(
+                &payload.user_id,
+                OrderUpdate {
+                    order_id: order_id.clone(),
+                    market: payload.market.clone(),
+                    side: payload.side.clone(),
+                    price: payload.price,
+                    status: OrderStatus::Cancelled,
+                    remaining_quantity: remaining_qty,
+                    trade_id: None,
+                    last_fill_price: None,
+                    last_fill_quantity: None,
+                    time: Utc::now().timestamp(),
+                },
+            );
+        }
 
-                let _ =
-                    redis_manager.publish_message(&format!("depth@{}", payload.market), &message);
-            }
-            OrderSide::Ask => {
-                let matching_bid = depth
-                    .bids
-                    .iter()
-                    .find(|x| {
-                        Decimal::from_str(&x[0].to_string()).unwrap_or(dec!(0)) >= payload.price
-                    })
-                    .cloned();
-
-                let message = json!({
-                    "stream": format!("depth@{}", payload.market),
-                    "data": {
-                        "a": depth.asks,
-                        "b": if let Some(bid) = matching_bid { vec![bid] } else { Vec::new() },
-                        "e": "depth"
-                    }
-                });
+        if fills.is_empty() {
+            return Ok(OrderStatus::New);
+        }
+        let status = if unsettled.is_some() {
+            OrderStatus::Cancelled
+        } else if remaining_qty > Decimal::ZERO {
+            OrderStatus::PartiallyFilled
+        } else {
+            OrderStatus::Filled
+        };
 
-                let _ =
-                    redis_manager.publish_message(&format!("depth@{}", payload.market), &message);
+        let now = Utc::now();
+        let ticker = {
+            let mut summaries = market_summaries.lock_or_recover();
+            let summary = summaries.entry(payload.market.clone()).or_default();
+            for fill in &fills {
+                summary.stats.record(now, fill.price, fill.quantity);
             }
-        }
+            summary.stats.snapshot(&payload.market, now)
+        };
 
-        if filled_qty > Decimal::ZERO {
+        for fill in &fills {
             let trade_info = json!({
-                "price": payload.price,
-                "quantity": filled_qty,
+                "id": fill.trade_id,
+                "price": fill.price,
+                "quantity": fill.quantity,
                 "side": payload.side,
-                "timestamp": Utc::now().timestamp()
-            });
-
-            let ticker_info = json!({
-                "stream": format!("ticker@{}", payload.market),
-                "data": {
-                    "s": payload.market,
-                    "p": payload.price.to_string(),
-                    "q": filled_qty.to_string(),
-                    "t": Utc::now().timestamp(),
-                    "e": "ticker"
-                }
+                "timestamp": now.timestamp()
             });
 
             let db_info = AddTradePayload {
                 data: TradeData {
                     ticker: payload.market.clone(),
-                    time: Utc::now(),
-                    price: payload.price,
-                    quantity: payload.quantity,
+                    time: now,
+                    price: fill.price,
+                    quantity: fill.quantity,
                 },
             };
 
             let _ =
                 redis_manager.publish_message(&format!("trade@{}", payload.market), &trade_info);
-            let _ =
-                redis_manager.publish_message(&format!("ticker@{}", payload.market), &ticker_info);
             let _ = redis_manager.push_message_to_db(&db_info);
         }
-    }
 
-    fn handle_cancel_order(
-        orderbook: &mut Orderbook,
-        users: &Arc<Mutex<Vec<User>>>,
-        client_id: String,
-        payload: CancelOrderPayload,
-    ) {
-        let market: Vec<&str> = payload.market.split('_').collect();
-        let base_asset = market[0];
-        let quote_asset = market[1];
-        let redis_manager = RedisManager::instance();
-
-        let mut order_found = false;
+        let last_fill = &fills[fills.len() - 1];
+        let ticker_info = json!({
+            "stream": format!("ticker@{}", payload.market),
+            "data": {
+                "s": payload.market,
+                "p": last_fill.price.to_string(),
+                "q": filled_qty.to_string(),
+                "t": now.timestamp(),
+                "e": "ticker",
+                "o": ticker.open,
+                "h": ticker.high,
+                "l": ticker.low,
+                "c": ticker.last,
+                "v": ticker.volume,
+                "qv": ticker.quote_volume,
+                "n": ticker.trade_count,
+                "P": ticker.price_change_percent,
+            }
+        });
+        let _ = redis_manager.publish_message(&format!("ticker@{}", payload.market), &ticker_info);
 
-        if let Some(bid_index) = orderbook
-            .bids
-            .iter()
-            .position(|order| order.id == payload.order_id)
-        {
-            let order = &orderbook.bids[bid_index];
+        Ok(status)
+    }
 
-            let mut users_guard = users.lock().unwrap();
-            if let Some(user) = users_guard.iter_mut().find(|u| u.id == order.user_id) {
-                if let Some(balance) = user.balances.iter_mut().find(|b| b.ticker == quote_asset) {
-                    let locked_amount = order.price * order.quantity;
-                    balance.locked_balance -= locked_amount;
-                }
+    /// Tells the taker its order was accepted, then tells the taker and each
+    /// maker it matched how every fill changed their orders.
+    fn publish_order_updates(payload: &CreateOrderPayload, order_id: &str, fills: &[Fill]) {
+        let time = Utc::now().timestamp();
+        let maker_side = match payload.side {
+            OrderSide::Bid => OrderSide::Ask,
+            OrderSide::Ask => OrderSide::Bid,
+        };
+        let status = |remaining: Decimal| {
+            if remaining > Decimal::ZERO {
+                OrderStatus::PartiallyFilled
+            } else {
+                OrderStatus::Filled
             }
+        };
 
-            orderbook.bids.remove(bid_index);
-            order_found = true;
+        publish_order_update(
+            &payload.user_id,
+            OrderUpdate {
+                order_id: order_id.to_string(),
+                market: payload.market.clone(),
+                side: payload.side.clone(),
+                price: payload.price,
+                status: OrderStatus::New,
+                remaining_quantity: payload.quantity,
+                trade_id: None,
+                last_fill_price: None,
+                last_fill_quantity: None,
+                time,
+            },
+        );
+
+        let mut remaining = payload.quantity;
+        for fill in fills {
+            remaining -= fill.quantity;
+            publish_order_update(
+                &payload.user_id,
+                OrderUpdate {
+                    order_id: order_id.to_string(),
+                    market: payload.market.clone(),
+                    side: payload.side.clone(),
+                    price: payload.price,
+                    status: status(remaining),
+                    remaining_quantity: remaining,
+                    trade_id: Some(fill.trade_id.clone()),
+                    last_fill_price: Some(fill.price),
+                    last_fill_quantity: Some(fill.quantity),
+                    time,
+                },
+            );
+            publish_order_update(
+                &fill.maker_user_id,
+                OrderUpdate {
+                    order_id: fill.maker_order_id.clone(),
+                    market: payload.market.clone(),
+                    side: maker_side.clone(),
+                    price: fill.price,
+                    status: status(fill.maker_remaining),
+                    remaining_quantity: fill.maker_remaining,
+                    trade_id: Some(fill.trade_id.clone()),
+                    last_fill_price: Some(fill.price),
+                    last_fill_quantity: Some(fill.quantity),
+                    time,
+                },
+            );
         }
+    }
 
-        if !order_found {
-            if let Some(ask_index) = orderbook
-                .asks
-                .iter()
-                .position(|order| order.id == payload.order_id)
-            {
-                let order = &orderbook.asks[ask_index];
-
-                let mut users_guard = users.lock().unwrap();
-                if let Some(user) = users_guard.iter_mut().find(|u| u.id == order.user_id) {
-                    if let Some(balance) = user.balances.iter_mut().find(|b| b.ticker == base_asset)
-                    {
-                        balance.locked_balance -= order.quantity;
-                    }
-                }
+    /// Cancels a maker whose side of a fill couldn't be settled, already taken
+    /// off the book, and gives back whatever is left of its hold.
+    fn cancel_unsettled_maker(
+        orderbook: &Orderbook,
+        accounts: &AccountStore,
+        market: &str,
+        order: Order,
+        error: &EngineError,
+    ) {
+        error!(
+            order_id = order.id,
+            user_id = order.user_id,
+            "Failed to settle a maker's side of a fill, cancelling it: {}",
+            error
+        );
+        counter!("engine_unsettled_makers_total", "market" => market.to_string()).increment(1);
 
-                orderbook.asks.remove(ask_index);
+        let (hold_asset, hold_amount) = match order.side {
+            OrderSide::Bid => (&orderbook.quote_asset, order.price * order.quantity),
+            OrderSide::Ask => (&orderbook.base_asset, order.quantity),
+        };
+        // Its hold is what went missing, so only what remains of it is released
+        let locked = accounts
+            .get(&order.user_id)
+            .and_then(|user| {
+                user.lock_or_recover()
+                    .balances
+                    .get(hold_asset)
+                    .map(|balance| balance.locked_balance)
+            })
+            .unwrap_or(Decimal::ZERO);
+        let release = hold_amount.min(locked);
+        if release > Decimal::ZERO {
+            let transaction = Transaction::release(&order.user_id, hold_asset, release, &order.id);
+            if let Err(e) = Ledger::post(accounts, transaction) {
+                error!(
+                    order_id = order.id,
+                    "Failed to release funds for order: {}", e
+                );
             }
         }
 
+        publish_order_update(
+            &order.user_id,
+            OrderUpdate {
+                order_id: order.id.clone(),
+                market: market.to_string(),
+                side: order.side.clone(),
+                price: order.price,
+                status: OrderStatus::Cancelled,
+                remaining_quantity: order.quantity,
+                trade_id: None,
+                last_fill_price: None,
+                last_fill_quantity: None,
+                time: Utc::now().timestamp(),
+            },
+        );
+    }
+
+    fn handle_cancel_order(
+        orderbook: &mut Orderbook,
+        accounts: &AccountStore,
+        client_id: &str,
+        payload: CancelOrderPayload,
+    ) -> Result<(), EngineError> {
+        let redis_manager = RedisManager::instance();
+
+        // Orders owned by someone else are reported as unknown so ids can't be probed
+        let order = orderbook
+            .remove_order(&payload.order_id, &payload.user_id)
+            .ok_or_else(|| EngineError::UnknownOrder(payload.order_id.clone()))?;
+        let (hold_asset, hold_amount) = match order.side {
+            OrderSide::Bid => (&orderbook.quote_asset, order.price * order.quantity),
+            OrderSide::Ask => (&orderbook.base_asset, order.quantity),
+        };
+
+        let transaction = Transaction::release(&order.user_id, hold_asset, hold_amount, &order.id);
+        if let Err(e) = Ledger::post(accounts, transaction) {
+            error!(order_id = ?order.id, "Failed to release funds for order: {}", e);
+        }
+
+        publish_order_update(
+            &order.user_id,
+            OrderUpdate {
+                order_id: order.id.clone(),
+                market: payload.market.clone(),
+                side: order.side.clone(),
+                price: order.price,
+                status: OrderStatus::Cancelled,
+                remaining_quantity: order.quantity,
+                trade_id: None,
+                last_fill_price: None,
+                last_fill_quantity: None,
+                time: Utc::now().timestamp(),
+            },
+        );
+
         info!(order_id = ?payload.order_id, "Order cancelled successfully");
         let message = MessageToApi::OrderCancelled {
             payload: OrderCancelledPayload {
@@ -372,11 +723,17 @@ impl OrderbookWorker {
             },
         };
 
-        let _ = redis_manager.send_to_api(&client_id, &message);
+        let _ = redis_manager.send_to_api(client_id, &message);
+        Ok(())
     }
 
-    fn handle_get_depth(orderbook: &Orderbook, client_id: String) {
-        let depth = orderbook.get_depth();
+    fn handle_get_depth(
+        orderbook: &Orderbook,
+        client_id: String,
+        limit: Option<usize>,
+        bucket: Option<Decimal>,
+    ) {
+        let depth = orderbook.get_depth(limit, bucket);
 
         let redis_manager = RedisManager::instance();
         let message = MessageToApi::Depth { payload: depth };
@@ -416,13 +773,106 @@ impl OrderbookWorker {
     fn handle_get_quote(
         orderbook: &Orderbook,
         client_id: String,
-        quantity: Decimal,
+        amount: QuoteAmount,
         side: OrderSide,
     ) {
-        let quote = orderbook.get_quote_detail(quantity, side);
+        let quote = orderbook.get_quote_detail(side, amount);
 
         let redis_manager = RedisManager::instance();
         let message = MessageToApi::Quote { payload: quote };
         let _ = redis_manager.send_to_api(&client_id, &message);
     }
+
+    fn handle_get_amm_state(
+        orderbook: &Orderbook,
+        amm: Option<&LmsrAmm>,
+        accounts: &AccountStore,
+        client_id: String,
+    ) {
+        let Some(amm) = amm else {
+            reject(
+                &client_id,
+                EngineError::InvalidRequest(format!("Market {} has no AMM", orderbook.market())),
+            );
+            return;
+        };
+
+        let message = MessageToApi::AmmState {
+            payload: amm.state(orderbook, accounts),
+        };
+        let _ = RedisManager::instance().send_to_api(&client_id, &message);
+    }
+
+    /// Runs after every message that can mutate the book: refreshes the shared
+    /// market summary and publishes the depth diff, plus the book ticker when
+    /// the top of book moved.
+    fn publish_book_changes(
+        orderbook: &mut Orderbook,
+        market_summaries: &Arc<Mutex<HashMap<String, MarketSummary>>>,
+    ) {
+        let Some(diff) = orderbook.take_depth_diff() else {
+            return;
+        };
+
+        let redis_manager = RedisManager::instance();
+        let market = orderbook.market();
+        let ticker = orderbook.book_ticker();
+
+        let ticker_changed = {
+            let mut summaries = market_summaries.lock_or_recover();
+            let summary = summaries.entry(market.clone()).or_default();
+            let changed = summary.book_ticker != ticker;
+            orderbook.update_summary(summary);
+            changed
+        };
+
+        let stream = format!("depth@{}", market);
+        let message = json!({
+            "stream": stream,
+            "data": {
+                "e": "depth",
+                "s": market,
+                "U": diff.first_update_id,
+                "u": diff.last_update_id,
+                "b": diff.bids,
+                "a": diff.asks,
+            }
+        });
+        let _ = redis_manager.publish_message(&stream, &message);
+
+        if !ticker_changed {
+            return;
+        }
+
+        let stream = format!("bookTicker@{}", market);
+        let message = json!({
+            "stream": stream,
+            "data": {
+                "e": "bookTicker",
+                "s": market,
+                "u": diff.last_update_id,
+                "b": ticker.bid_price,
+                "B": ticker.bid_quantity,
+                "a": ticker.ask_price,
+                "A": ticker.ask_quantity,
+            }
+        });
+        let _ = redis_manager.publish_message(&stream, &message);
+    }
+}
+
+/// Publishes how full each of a market's queues is.
+fn record_queue_depth(market: &str, commands: usize, cancels: usize) {
+    for (queue, len) in [("commands", commands), ("cancels", cancels)] {
+        gauge!("engine_worker_queue_depth", "market" => market.to_string(), "queue" => queue)
+            .set(len as f64);
+    }
+}
+
+fn worker_queue_capacity() -> usize {
+    std::env::var("WORKER_QUEUE_CAPACITY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&capacity| capacity > 0)
+        .unwrap_or(DEFAULT_WORKER_QUEUE_CAPACITY)
 }
@@ -0,0 +1,65 @@
+use rust_decimal::Decimal;
+
+use crate::models::{Position, PositionSummary};
+
+impl Position {
+    pub fn new(market: &str) -> Self {
+        Position {
+            market: market.to_string(),
+            net_quantity: Decimal::ZERO,
+            avg_entry_price: Decimal::ZERO,
+            realised_pnl: Decimal::ZERO,
+        }
+    }
+
+    /// Applies a fill to the position. A positive `quantity` is a buy and a
+    /// negative one a sell; fills that reduce the position realise PnL against
+    /// the average entry price, fills that extend it re-average the entry.
+    pub fn apply_fill(&mut self, quantity: Decimal, price: Decimal) {
+        if quantity.is_zero() {
+            return;
+        }
+
+        let current = self.net_quantity;
+        let next = current + quantity;
+
+        if current.is_zero() || current.is_sign_positive() == quantity.is_sign_positive() {
+            self.avg_entry_price =
+                (self.avg_entry_price * current.abs() + price * quantity.abs()) / next.abs();
+        } else {
+            let closed_qty = quantity.abs().min(current.abs());
+            let pnl_per_unit = if current.is_sign_positive() {
+                price - self.avg_entry_price
+            } else {
+                self.avg_entry_price - price
+            };
+            self.realised_pnl += pnl_per_unit * closed_qty;
+
+            if next.is_zero() {
+                self.avg_entry_price = Decimal::ZERO;
+            } else if next.is_sign_positive() != current.is_sign_positive() {
+                // The fill flipped the position, the remainder opens at the fill price
+                self.avg_entry_price = price;
+            }
+        }
+
+        self.net_quantity = next;
+    }
+
+    pub fn unrealised_pnl(&self, mark_price: Decimal) -> Decimal {
+        (mark_price - self.avg_entry_price) * self.net_quantity
+    }
+
+    pub fn summary(&self, mark_price: Option<Decimal>) -> PositionSummary {
+        PositionSummary {
+            market: self.market.clone(),
+            net_quantity: self.net_quantity,
+            avg_entry_price: self.avg_entry_price,
+            mark_price,
+            realised_pnl: self.realised_pnl,
+            unrealised_pnl: mark_price
+                .map(|mark| self.unrealised_pnl(mark))
+                .unwrap_or(Decimal::ZERO),
+        }
+    }
+}
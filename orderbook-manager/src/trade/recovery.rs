@@ -0,0 +1,212 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::{
+    constant::RECOVERY_REPLY_TIMEOUT_SECS,
+    models::{IncomingMessage, MessageToApi, Withdrawal},
+    services::{JournalEntry, MemorySink, RedisManager},
+};
+
+use super::{reject, Engine, EngineError, EngineSnapshot};
+
+/// Recorded ids to the ones a replay generated, and back.
+#[derive(Default)]
+pub struct IdMap {
+    to_replay: HashMap<String, String>,
+    to_recorded: HashMap<String, String>,
+}
+
+impl IdMap {
+    pub fn learn(&mut self, recorded: &Value, replayed: &Value) {
+        for pointer in ["/payload/order_id", "/payload/id"] {
+            if let (Some(Value::String(recorded)), Some(Value::String(replayed))) =
+                (recorded.pointer(pointer), replayed.pointer(pointer))
+            {
+                self.to_replay.insert(recorded.clone(), replayed.clone());
+                self.to_recorded.insert(replayed.clone(), recorded.clone());
+            }
+        }
+    }
+
+    /// Swaps every string in `value` that is a known recorded id.
+    pub fn translate(&self, value: &mut Value) {
+        match value {
+            Value::String(s) => {
+                if let Some(id) = self.to_replay.get(s) {
+                    *s = id.clone();
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|v| self.translate(v)),
+            Value::Object(map) => map.values_mut().for_each(|v| self.translate(v)),
+            _ => {}
+        }
+    }
+
+    pub fn replayed_id(&self, id: &str) -> String {
+        self.to_replay
+            .get(id)
+            .cloned()
+            .unwrap_or_else(|| id.to_string())
+    }
+
+    pub fn recorded_id(&self, id: &str) -> String {
+        self.to_recorded
+            .get(id)
+            .cloned()
+            .unwrap_or_else(|| id.to_string())
+    }
+}
+
+/// Recorded replies for each client, last one first so they can be popped in
+/// the order they were sent.
+pub fn recorded_replies<'a>(
+    entries: impl IntoIterator<Item = &'a JournalEntry>,
+) -> HashMap<String, Vec<Value>> {
+    let mut replies: HashMap<String, Vec<Value>> = HashMap::new();
+    for entry in entries {
+        if let JournalEntry::Reply {
+            client_id, message, ..
+        } = entry
+        {
+            if let Ok(value) = serde_json::from_str(message) {
+                replies.entry(client_id.clone()).or_default().push(value);
+            }
+        }
+    }
+    replies.values_mut().for_each(|r| r.reverse());
+    replies
+}
+
+/// Hands one recorded request to the engine the way the main loop does and
+/// returns the client expecting a reply, if any.
+pub fn feed(
+    engine: &mut Engine,
+    ids: &IdMap,
+    message: &str,
+    time: DateTime<Utc>,
+) -> Option<String> {
+    let mut value = match serde_json::from_str::<Value>(message) {
+        Ok(value) => value,
+        Err(_) => return None,
+    };
+    let client_id = value.get("client_id")?.as_str()?.to_string();
+    ids.translate(&mut value);
+
+    match serde_json::from_value::<IncomingMessage>(value) {
+        Ok(incoming) => engine.process_at(incoming.client_id, incoming.message, time),
+        Err(e) => reject(&client_id, EngineError::InvalidRequest(e.to_string())),
+    }
+    Some(client_id)
+}
+
+impl Engine {
+    /// Rebuilds the engine as it stood when it last stopped: `snapshot`, then
+    /// every request in `journal` recorded after it, i.e. the ones handled by
+    /// a run that died before writing a snapshot of its own.
+    ///
+    /// Their replies, trades and ledger entries went out the first time, so
+    /// nothing is sent while replaying, and approved withdrawals settle by the
+    /// chain receipts journaled for them. Replayed orders and withdrawals get
+    /// new ids, which are mapped back to the ones their clients were given,
+    /// and withdrawals keep the timestamps recorded in their replies.
+    pub fn recover(snapshot: Option<EngineSnapshot>, journal: &[JournalEntry]) -> Engine {
+        let tail: Vec<&JournalEntry> = match &snapshot {
+            Some(snapshot) => match snapshot.journal_sequence {
+                Some(sequence) => journal.iter().filter(|e| e.sequence() > sequence).collect(),
+                // Written while no journal was kept, which can only have
+                // started after it
+                None => journal
+                    .iter()
+                    .filter(|e| e.time() > snapshot.time)
+                    .collect(),
+            },
+            None => journal.iter().collect(),
+        };
+
+        let mut engine = Engine::new();
+        if let Some(snapshot) = snapshot {
+            engine.restore(snapshot);
+        }
+        if !tail.iter().any(|entry| {
+            matches!(
+                entry,
+                JournalEntry::Request { .. } | JournalEntry::ChainReceipt { .. }
+            )
+        }) {
+            return engine;
+        }
+
+        let sink = Arc::new(MemorySink::new());
+        RedisManager::instance().divert(Some(sink.clone()));
+
+        let timeout = Duration::from_secs(RECOVERY_REPLY_TIMEOUT_SECS);
+        let mut recorded = recorded_replies(tail.iter().copied());
+        let mut ids = IdMap::default();
+        let mut withdrawals: HashMap<String, Withdrawal> = HashMap::new();
+        let mut requests = 0;
+        for entry in &tail {
+            match entry {
+                JournalEntry::Markets { time, markets, .. } => {
+                    engine.reconcile_markets(markets, *time);
+                }
+                JournalEntry::Request { time, message, .. } => {
+                    requests += 1;
+                    let Some(client_id) = feed(&mut engine, &ids, message, *time) else {
+                        continue;
+                    };
+                    let replayed = sink
+                        .wait_for_reply(&client_id, timeout)
+                        .map(|reply| serde_json::to_value(reply).unwrap());
+                    let recorded = recorded.get_mut(&client_id).and_then(Vec::pop);
+                    if let (Some(recorded), Some(replayed)) = (&recorded, &replayed) {
+                        ids.learn(recorded, replayed);
+                    }
+                    if let Some(Ok(MessageToApi::Withdrawal { payload })) =
+                        recorded.map(serde_json::from_value::<MessageToApi>)
+                    {
+                        withdrawals.insert(payload.id.clone(), payload);
+                    }
+                }
+                JournalEntry::ChainReceipt { receipt, .. } => {
+                    if let Some(withdrawal) = withdrawals.get_mut(&receipt.withdrawal_id) {
+                        withdrawal.updated_at = receipt.time;
+                    }
+                    let mut receipt = receipt.clone();
+                    receipt.withdrawal_id = ids.replayed_id(&receipt.withdrawal_id);
+                    engine.settle_withdrawal(receipt);
+                }
+                JournalEntry::Reply { .. } => {}
+            }
+        }
+
+        let mut snapshot = engine.snapshot();
+        drop(engine);
+        RedisManager::instance().divert(None);
+
+        if !snapshot.unavailable_markets.is_empty() {
+            warn!(
+                markets = ?snapshot.unavailable_markets,
+                "Books lost during journal replay"
+            );
+        }
+        for order in snapshot.books.values_mut().flatten() {
+            order.id = ids.recorded_id(&order.id);
+        }
+        for withdrawal in &mut snapshot.withdrawals {
+            withdrawal.id = ids.recorded_id(&withdrawal.id);
+            // Stamped with the replay's clock, not when the client was told
+            if let Some(recorded) = withdrawals.get(&withdrawal.id) {
+                withdrawal.created_at = recorded.created_at;
+                withdrawal.updated_at = recorded.updated_at;
+            }
+        }
+        info!(requests, "Replayed the command journal after the snapshot");
+
+        let mut recovered = Engine::new();
+        recovered.restore(snapshot);
+        recovered
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    sync::mpsc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    constant::AUDIT_REPLY_TIMEOUT_SECS,
+    models::{Balance, BookAudit, Order, OrderbookMessage, Position, Withdrawal, WithdrawalStatus},
+};
+
+use super::{Engine, LockExt, OrderbookWorker};
+
+/// Resting orders of every market that answered the audit. The workers stay
+/// paused until this is dropped.
+pub(super) struct PausedBooks {
+    pub audits: Vec<BookAudit>,
+    /// Markets whose worker didn't answer in time
+    pub unavailable: Vec<String>,
+    _resumers: Vec<mpsc::Sender<()>>,
+}
+
+/// Books, balances, positions and withdrawals as of one instant, enough to
+/// start the engine again where it stopped.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EngineSnapshot {
+    pub time: DateTime<Utc>,
+    pub books: BTreeMap<String, Vec<Order>>,
+    pub unavailable_markets: Vec<String>,
+    pub balances: BTreeMap<String, Vec<Balance>>,
+    pub positions: BTreeMap<String, Vec<Position>>,
+    /// Net amount of each asset deposited, which the balances add up to
+    pub external_flows: BTreeMap<String, Decimal>,
+    pub withdrawals: Vec<Withdrawal>,
+    /// References of every deposit credited
+    #[serde(default)]
+    pub deposit_references: BTreeSet<String>,
+    /// When each user last claimed from the faucet
+    #[serde(default)]
+    pub faucet_claims: BTreeMap<String, DateTime<Utc>>,
+    /// Last command journal entry the snapshot includes, if a journal is kept
+    pub journal_sequence: Option<u64>,
+}
+
+impl EngineSnapshot {
+    /// Writes the snapshot to `path`, through a temporary file so a crash
+    /// midway never leaves a truncated one behind. A snapshot missing the
+    /// books of unresponsive markets would still hold the balances those
+    /// books locked, so it is refused and the previous file kept.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if !self.unavailable_markets.is_empty() {
+            bail!(
+                "Snapshot is missing the books of {:?}",
+                self.unavailable_markets
+            );
+        }
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to move snapshot to {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Reads the snapshot at `path`, or `None` if there is none.
+    pub fn read(path: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = std::fs::read(path)
+            .with_context(|| format!("Failed to read snapshot {}", path.display()))?;
+        let snapshot = serde_json::from_slice(&json)
+            .with_context(|| format!("{} is not an engine snapshot", path.display()))?;
+        Ok(Some(snapshot))
+    }
+}
+
+/// Asks `worker` for its resting orders. It stays paused until the returned
+/// sender is dropped.
+pub(super) fn audit_worker(
+    worker: &OrderbookWorker,
+    timeout: Duration,
+) -> (Option<BookAudit>, mpsc::Sender<()>) {
+    let (reply_tx, reply_rx) = mpsc::channel();
+    let (resume_tx, resume_rx) = mpsc::channel::<()>();
+    let sent = worker.send(OrderbookMessage::Audit {
+        reply: reply_tx,
+        resume: resume_rx,
+    });
+
+    let audit = sent.ok().and_then(|_| reply_rx.recv_timeout(timeout).ok());
+    (audit, resume_tx)
+}
+
+impl Engine {
+    /// Pauses every market worker once it has worked through what is already
+    /// queued for it and collects its resting orders.
+    pub(super) fn pause_books(&self) -> PausedBooks {
+        let timeout = Duration::from_secs(AUDIT_REPLY_TIMEOUT_SECS);
+        let mut paused = PausedBooks {
+            audits: Vec::new(),
+            unavailable: Vec::new(),
+            _resumers: Vec::new(),
+        };
+
+        for worker in self.orderbook_workers.values() {
+            let (audit, resume) = audit_worker(worker, timeout);
+            match audit {
+                Some(audit) => paused.audits.push(audit),
+                None => paused.unavailable.push(worker.market.clone()),
+            }
+            paused._resumers.push(resume);
+        }
+
+        paused
+    }
+
+    pub fn snapshot(&self) -> EngineSnapshot {
+        let paused = self.pause_books();
+
+        let mut balances = BTreeMap::new();
+        let mut positions = BTreeMap::new();
+        for user_id in self.accounts.user_ids() {
+            if let Some(account) = self.accounts.get(&user_id) {
+                let user = account.lock_or_recover();
+                let mut list = user.balance_list();
+                list.sort_by(|a, b| a.ticker.cmp(&b.ticker));
+                balances.insert(user_id.clone(), list);
+
+                if !user.positions.is_empty() {
+                    let mut list: Vec<Position> = user.positions.values().cloned().collect();
+                    list.sort_by(|a, b| a.market.cmp(&b.market));
+                    positions.insert(user_id, list);
+                }
+            }
+        }
+
+        let mut withdrawals: Vec<Withdrawal> = self.withdrawals.values().cloned().collect();
+        withdrawals.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+
+        let mut unavailable_markets = paused.unavailable.clone();
+        unavailable_markets.sort();
+
+        // Restored books of markets that were never loaded again are kept
+        let mut books = self
+            .restored_books
+            .iter()
+            .map(|(market, orders)| (market.clone(), orders.clone()))
+            .collect::<BTreeMap<_, _>>();
+        books.extend(
+            paused
+                .audits
+                .iter()
+                .map(|audit| (audit.market.clone(), audit.orders.clone())),
+        );
+
+        EngineSnapshot {
+            time: Utc::now(),
+            books,
+            unavailable_markets,
+            balances,
+            positions,
+            external_flows: self.accounts.external_flows().into_iter().collect(),
+            withdrawals,
+            deposit_references: self.deposit_references.clone(),
+            faucet_claims: self
+                .faucet
+                .as_ref()
+                .map(|faucet| faucet.last_claims().clone())
+                .unwrap_or_default(),
+            journal_sequence: None,
+        }
+    }
+
+    /// Loads `snapshot` into a fresh engine. Books are held back until their
+    /// market is loaded, so call this before `reconcile_markets`.
+    pub fn restore(&mut self, snapshot: EngineSnapshot) {
+        for (user_id, balances) in snapshot.balances {
+            let account = self.accounts.get_or_create(&user_id);
+            account.lock_or_recover().balances = balances
+                .into_iter()
+                .map(|balance| (balance.ticker.clone(), balance))
+                .collect();
+        }
+        for (user_id, positions) in snapshot.positions {
+            let account = self.accounts.get_or_create(&user_id);
+            account.lock_or_recover().positions = positions
+                .into_iter()
+                .map(|position| (position.market.clone(), position))
+                .collect();
+        }
+        for (asset, amount) in snapshot.external_flows {
+            self.accounts.record_external_flow(&asset, amount);
+        }
+        self.withdrawals = snapshot
+            .withdrawals
+            .into_iter()
+            .map(|withdrawal| (withdrawal.id.clone(), withdrawal))
+            .collect();
+        for withdrawal in self.withdrawals.values() {
+            if withdrawal.status == WithdrawalStatus::Approved {
+                warn!(
+                    withdrawal_id = withdrawal.id,
+                    "Withdrawal was on its way to the chain when the engine stopped, reconcile it"
+                );
+            }
+        }
+        self.deposit_references = snapshot.deposit_references;
+        if let Some(faucet) = &mut self.faucet {
+            faucet.restore_claims(snapshot.faucet_claims);
+        }
+        self.restored_books = snapshot.books.into_iter().collect();
+    }
+}
@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use metrics::gauge;
+use tracing::{error, info, warn};
+
+use crate::{
+    constant::AUDIT_REPLY_TIMEOUT_SECS,
+    models::{
+        HealthPayload, MarketHealth, MarketRestoredPayload, MessageToApi, OrderbookMessage,
+        WithdrawalStatus, WorkerStatus,
+    },
+    services::RedisManager,
+};
+
+use super::{audit_worker, Engine, EngineError};
+
+impl Engine {
+    /// Halts every market whose worker thread has died without noticing it
+    /// itself, e.g. a panic outside of message handling. Balances are
+    /// re-audited once after each new halt, since the worker may have
+    /// panicked while holding a user's lock half way through a change.
+    pub fn supervise(&self) {
+        let mut unaudited = Vec::new();
+        for worker in self.orderbook_workers.values() {
+            if worker.is_dead() && worker.mark_halted("Worker thread exited") {
+                error!(
+                    market = worker.market,
+                    "Orderbook worker died, halting market"
+                );
+            }
+            if worker.take_unaudited_halt() {
+                unaudited.push(worker.market.clone());
+            }
+
+            let halted = if worker.halt().is_some() { 1.0 } else { 0.0 };
+            gauge!("engine_market_halted", "market" => worker.market.clone()).set(halted);
+        }
+
+        if !unaudited.is_empty() {
+            warn!(markets = ?unaudited, "Re-auditing balances after a market halt");
+            self.run_invariant_check();
+        }
+    }
+
+    pub fn health(&self) -> HealthPayload {
+        self.supervise();
+
+        let mut markets: Vec<MarketHealth> = self
+            .orderbook_workers
+            .values()
+            .map(|worker| {
+                let halt = worker.halt();
+                MarketHealth {
+                    market: worker.market.clone(),
+                    status: match halt {
+                        Some(_) => WorkerStatus::Halted,
+                        None => WorkerStatus::Running,
+                    },
+                    halted_at: halt.as_ref().map(|halt| halt.time),
+                    reason: halt.map(|halt| halt.reason),
+                }
+            })
+            .collect();
+        markets.sort_by(|a, b| a.market.cmp(&b.market));
+
+        let mut unsettled_withdrawals: Vec<String> = self
+            .withdrawals
+            .values()
+            .filter(|w| w.status == WithdrawalStatus::Unsettled)
+            .map(|w| w.id.clone())
+            .collect();
+        unsettled_withdrawals.sort();
+
+        HealthPayload {
+            healthy: markets.iter().all(|m| m.status == WorkerStatus::Running)
+                && unsettled_withdrawals.is_empty(),
+            markets,
+            unsettled_withdrawals,
+        }
+    }
+
+    /// Replaces the worker of a halted market with a fresh one holding the
+    /// halted worker's resting orders. Only done when the invariant check
+    /// passes, i.e. that book still agrees with the balances it locked. An
+    /// AMM replaces the ladder it had with one quoted off its inventory.
+    /// Returns how many orders were carried over.
+    pub fn restore_market(&mut self, market: &str) -> Result<usize, EngineError> {
+        self.supervise();
+
+        let worker = self.worker(market)?;
+        if worker.halt().is_none() {
+            return Err(EngineError::InvalidRequest(format!(
+                "Market {} is not halted",
+                market
+            )));
+        }
+        if worker.is_dead() {
+            return Err(EngineError::Internal(format!(
+                "The book of {} went down with its worker",
+                market
+            )));
+        }
+
+        let report = self.check_invariants();
+        if !report.violations.is_empty() {
+            return Err(EngineError::Internal(format!(
+                "{} invariant violations, {} stays halted",
+                report.violations.len(),
+                market
+            )));
+        }
+
+        let timeout = Duration::from_secs(AUDIT_REPLY_TIMEOUT_SECS);
+        let (audit, resume) = audit_worker(self.worker(market)?, timeout);
+        drop(resume);
+        let audit = audit.ok_or_else(|| {
+            EngineError::Internal(format!("Halted worker of {} didn't answer", market))
+        })?;
+
+        let mut halted = self
+            .orderbook_workers
+            .remove(market)
+            .ok_or_else(|| EngineError::UnknownMarket(market.to_string()))?;
+        let _ = halted.send(OrderbookMessage::ShutDown);
+        if let Some(thread_handle) = halted.thread_handle.take() {
+            let _ = thread_handle.join();
+        }
+
+        // Restored like a snapshot's book, so an AMM takes over its account
+        // and swaps its old ladder for one quoted off its inventory
+        let orders = audit.orders.len();
+        self.restored_books.insert(market.to_string(), audit.orders);
+        self.create_market(
+            audit.base_asset,
+            audit.quote_asset,
+            halted.end_time,
+            halted.amm_subsidy,
+        )?;
+        gauge!("engine_market_halted", "market" => market.to_string()).set(0.0);
+        info!(market, orders, "Restored halted market");
+
+        Ok(orders)
+    }
+
+    pub(super) fn handle_get_health(&self, client_id: &str) -> Result<(), EngineError> {
+        let message = MessageToApi::Health {
+            payload: self.health(),
+        };
+        let _ = RedisManager::instance().send_to_api(client_id, &message);
+        Ok(())
+    }
+
+    pub(super) fn handle_restore_market(
+        &mut self,
+        client_id: &str,
+        market: &str,
+    ) -> Result<(), EngineError> {
+        let orders = self.restore_market(market)?;
+
+        let message = MessageToApi::MarketRestored {
+            payload: MarketRestoredPayload {
+                market: market.to_string(),
+                orders,
+            },
+        };
+        let _ = RedisManager::instance().send_to_api(client_id, &message);
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+
+use crate::models::Ticker24h;
+
+const BUCKET_SECS: i64 = 60;
+const WINDOW_BUCKETS: i64 = 24 * 60;
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    minute: i64,
+    open: Decimal,
+    high: Decimal,
+    low: Decimal,
+    close: Decimal,
+    volume: Decimal,
+    quote_volume: Decimal,
+    count: u64,
+}
+
+/// Trades of the last 24 hours rolled up into one-minute buckets, so memory
+/// stays bounded however busy the market is. The window edge is therefore
+/// accurate to the minute.
+#[derive(Debug, Clone, Default)]
+pub struct RollingStats {
+    buckets: VecDeque<Bucket>,
+}
+
+impl RollingStats {
+    pub fn record(&mut self, time: DateTime<Utc>, price: Decimal, quantity: Decimal) {
+        let minute = time.timestamp().div_euclid(BUCKET_SECS);
+
+        match self.buckets.back_mut() {
+            Some(bucket) if bucket.minute == minute => {
+                bucket.high = bucket.high.max(price);
+                bucket.low = bucket.low.min(price);
+                bucket.close = price;
+                bucket.volume += quantity;
+                bucket.quote_volume += price * quantity;
+                bucket.count += 1;
+            }
+            _ => self.buckets.push_back(Bucket {
+                minute,
+                open: price,
+                high: price,
+                low: price,
+                close: price,
+                volume: quantity,
+                quote_volume: price * quantity,
+                count: 1,
+            }),
+        }
+
+        self.evict(minute);
+    }
+
+    fn evict(&mut self, current_minute: i64) {
+        while self
+            .buckets
+            .front()
+            .is_some_and(|b| b.minute <= current_minute - WINDOW_BUCKETS)
+        {
+            self.buckets.pop_front();
+        }
+    }
+
+    pub fn snapshot(&self, market: &str, now: DateTime<Utc>) -> Ticker24h {
+        let current_minute = now.timestamp().div_euclid(BUCKET_SECS);
+        let mut window = self
+            .buckets
+            .iter()
+            .filter(|b| b.minute > current_minute - WINDOW_BUCKETS);
+
+        let mut ticker = Ticker24h {
+            market: market.to_string(),
+            ..Default::default()
+        };
+        let Some(first) = window.next() else {
+            return ticker;
+        };
+
+        let (mut high, mut low, mut last) = (first.high, first.low, first.close);
+        let (mut volume, mut quote_volume, mut count) =
+            (first.volume, first.quote_volume, first.count);
+        for bucket in window {
+            high = high.max(bucket.high);
+            low = low.min(bucket.low);
+            last = bucket.close;
+            volume += bucket.volume;
+            quote_volume += bucket.quote_volume;
+            count += bucket.count;
+        }
+
+        let open = first.open;
+        let price_change = last - open;
+        ticker.open = Some(open);
+        ticker.high = Some(high);
+        ticker.low = Some(low);
+        ticker.last = Some(last);
+        ticker.volume = volume;
+        ticker.quote_volume = quote_volume;
+        ticker.trade_count = count;
+        ticker.price_change = Some(price_change);
+        ticker.price_change_percent =
+            (!open.is_zero()).then(|| (price_change / open * Decimal::ONE_HUNDRED).round_dp(4));
+        ticker
+    }
+}
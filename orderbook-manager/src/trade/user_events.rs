@@ -0,0 +1,66 @@
+use std::collections::BTreeSet;
+
+use chrono::Utc;
+use serde_json::json;
+use tracing::error;
+
+use crate::{
+    models::{BalanceUpdate, LedgerAccount, LedgerEntry, OrderUpdate, UserEvent},
+    services::RedisManager,
+};
+
+use super::{AccountStore, LockExt};
+
+/// Private stream for `user_id`. wss only lets a client subscribe to it with a
+/// listen key issued for that user.
+pub fn user_stream(user_id: &str) -> String {
+    format!("user@{}", user_id)
+}
+
+pub fn publish_user_event(user_id: &str, event: &UserEvent) {
+    let stream = user_stream(user_id);
+    let message = json!({
+        "stream": stream,
+        "data": event,
+    });
+    if let Err(e) = RedisManager::instance().publish_message(&stream, &message) {
+        error!(user_id, "Failed to publish user event: {}", e);
+    }
+}
+
+pub fn publish_order_update(user_id: &str, update: OrderUpdate) {
+    publish_user_event(user_id, &UserEvent::OrderUpdate(update));
+}
+
+/// Publishes the new balance of every (user, asset) pair touched by `entries`.
+pub fn publish_balance_updates(accounts: &AccountStore, entries: &[LedgerEntry]) {
+    let Some(first) = entries.first() else {
+        return;
+    };
+
+    let touched: BTreeSet<(&str, &str)> = entries
+        .iter()
+        .filter(|entry| entry.account != LedgerAccount::External)
+        .map(|entry| (entry.user_id.as_str(), entry.asset.as_str()))
+        .collect();
+
+    let time = Utc::now().timestamp();
+    for (user_id, asset) in touched {
+        let Some(balance) = accounts
+            .get(user_id)
+            .and_then(|user| user.lock_or_recover().balances.get(asset).cloned())
+        else {
+            continue;
+        };
+
+        let event = UserEvent::BalanceUpdate(BalanceUpdate {
+            asset: balance.ticker,
+            balance: balance.balance,
+            locked_balance: balance.locked_balance,
+            reason: first.reason,
+            reference_id: first.reference_id.clone(),
+            time,
+        });
+        publish_user_event(user_id, &event);
+    }
+}
@@ -0,0 +1,435 @@
+//! Restarting the engine from its shutdown snapshot, and from that snapshot
+//! plus the journal tail a crashed run left behind.
+
+use std::{
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+
+use chrono::{TimeDelta, Utc};
+use orderbook_manager::{
+    models::{
+        CancelOrderPayload, CreateOrderPayload, DepositPayload, FaucetPayload, IncomingMessage,
+        MessageFromApi, MessageToApi, OrderSide, WithdrawPayload, WithdrawalActionPayload,
+        WithdrawalStatus,
+    },
+    services::{JournalEntry, MarketDefinition, MemorySink, RedisManager},
+    trade::{Engine, EngineSnapshot},
+};
+use rust_decimal::Decimal;
+use serde_json::Value;
+
+const MARKET: &str = "YES_USDC";
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn sink() -> &'static Arc<MemorySink> {
+    static SINK: OnceLock<Arc<MemorySink>> = OnceLock::new();
+    SINK.get_or_init(|| {
+        let sink = Arc::new(MemorySink::new());
+        if RedisManager::instance().install_sink(sink.clone()).is_err() {
+            panic!("another sink is already installed");
+        }
+        sink
+    })
+}
+
+/// Recovery diverts every engine message in the process while it replays, so
+/// the tests can't overlap.
+fn serial() -> std::sync::MutexGuard<'static, ()> {
+    static SERIAL: Mutex<()> = Mutex::new(());
+    SERIAL.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn markets() -> Vec<MarketDefinition> {
+    vec![MarketDefinition {
+        base_asset: String::from("YES"),
+        quote_asset: String::from("USDC"),
+        end_time: Utc::now() + TimeDelta::days(1),
+        status: None,
+        amm_subsidy: None,
+    }]
+}
+
+/// A running engine that journals its requests and replies the way `main`
+/// does with `COMMAND_JOURNAL_PATH` set.
+struct Run {
+    engine: Engine,
+    journal: Vec<JournalEntry>,
+    next_client: u64,
+}
+
+impl Run {
+    fn start(engine: Engine) -> Self {
+        let mut run = Run {
+            engine,
+            journal: Vec::new(),
+            next_client: 0,
+        };
+        run.load_markets();
+        run
+    }
+
+    fn continue_journal(engine: Engine, journal: Vec<JournalEntry>) -> Self {
+        let mut run = Run {
+            engine,
+            next_client: journal.len() as u64,
+            journal,
+        };
+        run.load_markets();
+        run
+    }
+
+    fn next_sequence(&self) -> u64 {
+        self.journal.len() as u64
+    }
+
+    fn load_markets(&mut self) {
+        let markets = markets();
+        self.journal.push(JournalEntry::Markets {
+            sequence: self.next_sequence(),
+            time: Utc::now(),
+            markets: markets.clone(),
+        });
+        self.engine.reconcile_markets(&markets, Utc::now());
+    }
+
+    fn request(&mut self, message: MessageFromApi) -> MessageToApi {
+        let client_id = format!("recovery-client-{}", self.next_client);
+        self.next_client += 1;
+
+        let incoming = IncomingMessage {
+            client_id: client_id.clone(),
+            message,
+        };
+        self.journal.push(JournalEntry::Request {
+            sequence: self.next_sequence(),
+            time: Utc::now(),
+            message: serde_json::to_string(&incoming).unwrap(),
+        });
+        self.engine.process(incoming.client_id, incoming.message);
+
+        let reply = sink()
+            .wait_for_reply(&client_id, REPLY_TIMEOUT)
+            .expect("no reply from the engine");
+        self.journal.push(JournalEntry::Reply {
+            sequence: self.next_sequence(),
+            time: Utc::now(),
+            client_id,
+            message: serde_json::to_string(&reply).unwrap(),
+        });
+        reply
+    }
+
+    fn deposit(&mut self, user_id: &str, asset: &str, amount: i64) {
+        self.request(MessageFromApi::Deposit {
+            data: DepositPayload {
+                user_id: user_id.to_string(),
+                asset: asset.to_string(),
+                amount: Decimal::from(amount),
+                reference: format!("deposit-{}", self.next_client),
+            },
+        });
+    }
+
+    /// Places an order at `price` cents and returns its id.
+    fn order(&mut self, user_id: &str, side: OrderSide, price: i64, quantity: i64) -> String {
+        let reply = self.request(MessageFromApi::CreateOrder {
+            data: CreateOrderPayload {
+                user_id: user_id.to_string(),
+                market: MARKET.to_string(),
+                price: Decimal::new(price, 2),
+                quantity: Decimal::from(quantity),
+                side,
+            },
+        });
+        match reply {
+            MessageToApi::OrderPlaced { payload } => payload.order_id,
+            other => panic!("order rejected: {:?}", other),
+        }
+    }
+
+    fn cancel(&mut self, user_id: &str, order_id: &str) -> MessageToApi {
+        self.request(MessageFromApi::CancelOrder {
+            data: CancelOrderPayload {
+                order_id: order_id.to_string(),
+                user_id: user_id.to_string(),
+                market: MARKET.to_string(),
+            },
+        })
+    }
+
+    fn withdraw(&mut self, user_id: &str, asset: &str, amount: i64) -> String {
+        let reply = self.request(MessageFromApi::Withdraw {
+            data: WithdrawPayload {
+                user_id: user_id.to_string(),
+                asset: asset.to_string(),
+                amount: Decimal::from(amount),
+                destination: String::from("wallet"),
+            },
+        });
+        match reply {
+            MessageToApi::Withdrawal { payload } => payload.id,
+            other => panic!("withdrawal rejected: {:?}", other),
+        }
+    }
+
+    /// Approves a withdrawal and settles it with the chain adapter's receipt,
+    /// journaling the receipt as `main` does.
+    fn approve(&mut self, withdrawal_id: &str) {
+        self.request(MessageFromApi::ApproveWithdrawal {
+            data: WithdrawalActionPayload {
+                withdrawal_id: withdrawal_id.to_string(),
+                reason: None,
+            },
+        });
+        let receipt = self
+            .engine
+            .chain
+            .wait_for_receipt(REPLY_TIMEOUT)
+            .expect("no receipt from the chain adapter");
+        self.journal.push(JournalEntry::ChainReceipt {
+            sequence: self.next_sequence(),
+            time: Utc::now(),
+            receipt: receipt.clone(),
+        });
+        self.engine.settle_withdrawal(receipt);
+    }
+
+    /// The snapshot `main` writes on shutdown.
+    fn snapshot(&self) -> EngineSnapshot {
+        let mut snapshot = self.engine.snapshot();
+        snapshot.journal_sequence = self.journal.len().checked_sub(1).map(|s| s as u64);
+        snapshot
+    }
+}
+
+/// Everything a snapshot holds apart from when it was taken.
+fn state(snapshot: &EngineSnapshot) -> Value {
+    let mut state = serde_json::to_value(snapshot).unwrap();
+    let state_map = state.as_object_mut().unwrap();
+    state_map.remove("time");
+    state_map.remove("journal_sequence");
+    state
+}
+
+/// Round trips `snapshot` through JSON, like writing and reading the file.
+fn reread(snapshot: &EngineSnapshot) -> EngineSnapshot {
+    serde_json::from_str(&serde_json::to_string(snapshot).unwrap()).unwrap()
+}
+
+fn trade(run: &mut Run) -> (String, String) {
+    for user in ["alice", "bob"] {
+        run.deposit(user, "USDC", 1_000);
+        run.deposit(user, "YES", 100);
+    }
+    let resting_bid = run.order("alice", OrderSide::Bid, 40, 10);
+    let ask = run.order("bob", OrderSide::Ask, 55, 20);
+    // Takes half of bob's ask, so both end up with a position
+    run.order("alice", OrderSide::Bid, 60, 10);
+    run.withdraw("bob", "USDC", 50);
+    (resting_bid, ask)
+}
+
+#[test]
+fn restarts_from_the_shutdown_snapshot() {
+    let _serial = serial();
+    sink();
+
+    let mut first = Run::start(Engine::new());
+    let (resting_bid, _) = trade(&mut first);
+    let snapshot = first.snapshot();
+    drop(first);
+
+    assert!(!snapshot.positions.is_empty());
+    assert_eq!(snapshot.withdrawals.len(), 1);
+
+    let mut second = Run::start(Engine::recover(Some(reread(&snapshot)), &[]));
+    assert_eq!(state(&second.snapshot()), state(&snapshot));
+    assert!(second.engine.check_invariants().violations.is_empty());
+
+    // Orders rest under the ids their owners were given
+    assert!(matches!(
+        second.cancel("alice", &resting_bid),
+        MessageToApi::OrderCancelled { .. }
+    ));
+    assert!(second.engine.check_invariants().violations.is_empty());
+}
+
+#[test]
+fn replays_the_journal_after_the_snapshot() {
+    let _serial = serial();
+    sink();
+
+    let mut first = Run::start(Engine::new());
+    let (resting_bid, _) = trade(&mut first);
+    let snapshot = first.snapshot();
+    let journal = std::mem::take(&mut first.journal);
+    drop(first);
+
+    // The next run handles more requests and dies without a snapshot
+    let mut crashed =
+        Run::continue_journal(Engine::recover(Some(reread(&snapshot)), &journal), journal);
+    crashed.cancel("alice", &resting_bid);
+    let late_ask = crashed.order("alice", OrderSide::Ask, 70, 5);
+    crashed.order("bob", OrderSide::Bid, 30, 8);
+    crashed.withdraw("alice", "YES", 3);
+    let expected = crashed.snapshot();
+    let journal = std::mem::take(&mut crashed.journal);
+    drop(crashed);
+
+    let mut recovered =
+        Run::continue_journal(Engine::recover(Some(reread(&snapshot)), &journal), journal);
+    assert_eq!(state(&recovered.snapshot()), state(&expected));
+    assert!(recovered.engine.check_invariants().violations.is_empty());
+
+    // Replayed orders keep the ids their owners were given too
+    assert!(matches!(
+        recovered.cancel("alice", &late_ask),
+        MessageToApi::OrderCancelled { .. }
+    ));
+}
+
+#[test]
+fn replays_the_whole_journal_without_a_snapshot() {
+    let _serial = serial();
+    sink();
+
+    let mut first = Run::start(Engine::new());
+    trade(&mut first);
+    let expected = first.snapshot();
+    let journal = std::mem::take(&mut first.journal);
+    drop(first);
+
+    let recovered = Run::continue_journal(Engine::recover(None, &journal), journal);
+    assert_eq!(state(&recovered.snapshot()), state(&expected));
+}
+
+#[test]
+fn credits_a_deposit_reference_once_across_restarts() {
+    let _serial = serial();
+    sink();
+
+    let credit = |run: &mut Run| {
+        run.request(MessageFromApi::Deposit {
+            data: DepositPayload {
+                user_id: String::from("alice"),
+                asset: String::from("USDC"),
+                amount: Decimal::from(100),
+                reference: String::from("chain-tx-1"),
+            },
+        })
+    };
+
+    let mut first = Run::start(Engine::new());
+    assert!(matches!(
+        credit(&mut first),
+        MessageToApi::UserBalances { .. }
+    ));
+    // A retry after the first reply was lost
+    let MessageToApi::Rejected { payload } = credit(&mut first) else {
+        panic!("the deposit was credited twice");
+    };
+    assert_eq!(payload.code, "DUPLICATE_DEPOSIT");
+    let snapshot = first.snapshot();
+    drop(first);
+
+    let mut second = Run::start(Engine::recover(Some(reread(&snapshot)), &[]));
+    let MessageToApi::Rejected { payload } = credit(&mut second) else {
+        panic!("the deposit was credited again after a restart");
+    };
+    assert_eq!(payload.code, "DUPLICATE_DEPOSIT");
+    assert_eq!(state(&second.snapshot()), state(&snapshot));
+}
+
+#[test]
+fn settles_replayed_withdrawals_with_the_journaled_receipts() {
+    let _serial = serial();
+    sink();
+
+    let mut first = Run::start(Engine::new());
+    first.deposit("alice", "USDC", 100);
+    let completed = first.withdraw("alice", "USDC", 40);
+    first.approve(&completed);
+    // Approved, but the run dies before the chain answers
+    let in_flight = first.withdraw("alice", "USDC", 10);
+    first.request(MessageFromApi::ApproveWithdrawal {
+        data: WithdrawalActionPayload {
+            withdrawal_id: in_flight.clone(),
+            reason: None,
+        },
+    });
+    let expected = first.snapshot();
+    let journal = std::mem::take(&mut first.journal);
+    drop(first);
+
+    let recovered = Run::continue_journal(Engine::recover(None, &journal), journal);
+    assert_eq!(state(&recovered.snapshot()), state(&expected));
+
+    let status = |id: &str| {
+        expected
+            .withdrawals
+            .iter()
+            .find(|w| w.id == id)
+            .map(|w| w.status)
+    };
+    assert_eq!(status(&completed), Some(WithdrawalStatus::Completed));
+    assert_eq!(status(&in_flight), Some(WithdrawalStatus::Approved));
+}
+
+#[test]
+fn keeps_faucet_cooldowns_across_restarts() {
+    let _serial = serial();
+    sink();
+    // Only this test claims, the other engines don't mind the faucet
+    std::env::set_var("FAUCET_ENABLED", "true");
+
+    let claim = |run: &mut Run, asset: &str| {
+        run.request(MessageFromApi::Faucet {
+            data: FaucetPayload {
+                user_id: String::from("alice"),
+                asset: asset.to_string(),
+            },
+        })
+    };
+
+    let mut first = Run::start(Engine::new());
+    let MessageToApi::Rejected { payload } = claim(&mut first, "YES") else {
+        panic!("the faucet handed out an asset it wasn't configured for");
+    };
+    assert_eq!(payload.code, "INVALID_REQUEST");
+    assert!(matches!(
+        claim(&mut first, "USDC"),
+        MessageToApi::UserBalances { .. }
+    ));
+    let snapshot = first.snapshot();
+    drop(first);
+    assert!(snapshot.faucet_claims.contains_key("alice"));
+
+    let mut second = Run::start(Engine::recover(Some(reread(&snapshot)), &[]));
+    assert!(matches!(
+        claim(&mut second, "USDC"),
+        MessageToApi::Rejected { .. }
+    ));
+}
+
+#[test]
+fn keeps_the_previous_snapshot_over_one_missing_a_market() {
+    let _serial = serial();
+    sink();
+
+    let path = std::env::temp_dir().join(format!("engine-snapshot-{}.json", std::process::id()));
+    let mut run = Run::start(Engine::new());
+    run.deposit("alice", "USDC", 100);
+    let previous = run.snapshot();
+    previous.write(&path).unwrap();
+
+    run.order("alice", OrderSide::Bid, 40, 10);
+    let mut partial = run.snapshot();
+    partial.books.remove(MARKET);
+    partial.unavailable_markets.push(MARKET.to_string());
+    assert!(partial.write(&path).is_err());
+
+    let kept = EngineSnapshot::read(&path).unwrap().unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(state(&kept), state(&previous));
+}
@@ -0,0 +1,295 @@
+//! A market worker that panics while holding a user's lock poisons it. The
+//! market halts, and the rest of the engine has to keep serving that user
+//! until the market is restored.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
+
+use chrono::{TimeDelta, Utc};
+use orderbook_manager::{
+    models::{
+        CreateOrderPayload, DepositPayload, GetAmmStatePayload, GetUserBalancesPayload,
+        MessageFromApi, MessageToApi, OrderSide, Position, ViolationKind, WorkerStatus,
+    },
+    services::{MarketDefinition, MemorySink, RedisManager},
+    trade::Engine,
+};
+use rust_decimal::Decimal;
+
+const POISONED_MARKET: &str = "YES_USDC";
+const HEALTHY_MARKET: &str = "NO_USDC";
+const AMM_MARKET: &str = "RAIN_USDC";
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn sink() -> &'static Arc<MemorySink> {
+    static SINK: OnceLock<Arc<MemorySink>> = OnceLock::new();
+    SINK.get_or_init(|| {
+        let sink = Arc::new(MemorySink::new());
+        if RedisManager::instance().install_sink(sink.clone()).is_err() {
+            panic!("another sink is already installed");
+        }
+        sink
+    })
+}
+
+fn request(engine: &mut Engine, client_id: &str, message: MessageFromApi) -> MessageToApi {
+    engine.process(client_id.to_string(), message);
+    sink()
+        .wait_for_reply(client_id, REPLY_TIMEOUT)
+        .expect("no reply from the engine")
+}
+
+fn deposit(engine: &mut Engine, user_id: &str, asset: &str) {
+    static DEPOSITS: AtomicU64 = AtomicU64::new(0);
+    let client_id = format!("deposit-{}-{}", user_id, asset);
+    let reference = format!("{}-{}", client_id, DEPOSITS.fetch_add(1, Ordering::Relaxed));
+    request(
+        engine,
+        &client_id,
+        MessageFromApi::Deposit {
+            data: DepositPayload {
+                user_id: user_id.to_string(),
+                asset: asset.to_string(),
+                amount: Decimal::from(1_000),
+                reference,
+            },
+        },
+    );
+}
+
+fn order(
+    engine: &mut Engine,
+    client_id: &str,
+    user_id: &str,
+    market: &str,
+    side: OrderSide,
+) -> MessageToApi {
+    order_at(
+        engine,
+        client_id,
+        user_id,
+        market,
+        side,
+        Decimal::new(50, 2),
+    )
+}
+
+fn order_at(
+    engine: &mut Engine,
+    client_id: &str,
+    user_id: &str,
+    market: &str,
+    side: OrderSide,
+    price: Decimal,
+) -> MessageToApi {
+    request(
+        engine,
+        client_id,
+        MessageFromApi::CreateOrder {
+            data: CreateOrderPayload {
+                user_id: user_id.to_string(),
+                market: market.to_string(),
+                price,
+                quantity: Decimal::from(10),
+                side,
+            },
+        },
+    )
+}
+
+#[test]
+fn a_worker_panic_under_a_user_lock_only_halts_its_market() {
+    sink();
+
+    let markets: Vec<MarketDefinition> = ["YES", "NO"]
+        .into_iter()
+        .map(|base| MarketDefinition {
+            base_asset: base.to_string(),
+            quote_asset: String::from("USDC"),
+            end_time: Utc::now() + TimeDelta::days(1),
+            status: None,
+            amm_subsidy: None,
+        })
+        .collect();
+
+    let mut engine = Engine::new();
+    engine.reconcile_markets(&markets, Utc::now());
+    for user in ["alice", "bob"] {
+        for asset in ["YES", "NO", "USDC"] {
+            deposit(&mut engine, user, asset);
+        }
+    }
+
+    // A position no fill can extend without overflowing, so the next buy
+    // panics in the worker while it holds alice's lock
+    let mut snapshot = engine.snapshot();
+    drop(engine);
+    snapshot.positions.insert(
+        String::from("alice"),
+        vec![Position {
+            market: POISONED_MARKET.to_string(),
+            net_quantity: Decimal::MAX,
+            avg_entry_price: Decimal::ONE,
+            realised_pnl: Decimal::ZERO,
+        }],
+    );
+    let mut engine = Engine::recover(Some(snapshot), &[]);
+    engine.reconcile_markets(&markets, Utc::now());
+
+    order(
+        &mut engine,
+        "bob-ask",
+        "bob",
+        POISONED_MARKET,
+        OrderSide::Ask,
+    );
+    let MessageToApi::Rejected { payload } = order(
+        &mut engine,
+        "alice-bid",
+        "alice",
+        POISONED_MARKET,
+        OrderSide::Bid,
+    ) else {
+        panic!("the panicking order wasn't rejected");
+    };
+    assert_eq!(payload.code, "MARKET_HALTED");
+
+    // Noticing the halt re-audits balances. The trade settled before the
+    // panic and bob's ask still rests, so his hold no longer matches
+    engine.supervise();
+    assert!(engine
+        .orderbook_workers
+        .values()
+        .all(|w| !w.take_unaudited_halt()));
+    let report = engine.check_invariants();
+    assert!(report.violations.iter().any(|v| {
+        v.kind == ViolationKind::LockedMismatch && v.user_id.as_deref() == Some("bob")
+    }));
+
+    let health = engine.health();
+    assert!(!health.healthy);
+    for market in health.markets {
+        let expected = if market.market == POISONED_MARKET {
+            WorkerStatus::Halted
+        } else {
+            WorkerStatus::Running
+        };
+        assert_eq!(market.status, expected, "{}", market.market);
+    }
+
+    // Alice's lock is poisoned, yet the engine and the other market still
+    // serve her
+    let balances = request(
+        &mut engine,
+        "alice-balances",
+        MessageFromApi::GetUserBalances {
+            data: GetUserBalancesPayload {
+                user_id: String::from("alice"),
+            },
+        },
+    );
+    assert!(matches!(balances, MessageToApi::UserBalances { .. }));
+    deposit(&mut engine, "alice", "USDC");
+    assert!(matches!(
+        order(
+            &mut engine,
+            "alice-other-bid",
+            "alice",
+            HEALTHY_MARKET,
+            OrderSide::Bid
+        ),
+        MessageToApi::OrderPlaced { .. }
+    ));
+    assert!(matches!(
+        order(
+            &mut engine,
+            "bob-other-ask",
+            "bob",
+            HEALTHY_MARKET,
+            OrderSide::Ask
+        ),
+        MessageToApi::OrderPlaced { .. }
+    ));
+}
+
+#[test]
+fn a_halted_amm_market_is_restored_with_a_fresh_ladder() {
+    sink();
+
+    let mut engine = Engine::new();
+    engine.reconcile_markets(
+        &[MarketDefinition {
+            base_asset: String::from("RAIN"),
+            quote_asset: String::from("USDC"),
+            end_time: Utc::now() + TimeDelta::days(1),
+            status: None,
+            amm_subsidy: Some(Decimal::from(100)),
+        }],
+        Utc::now(),
+    );
+    deposit(&mut engine, "carol", "USDC");
+
+    // Buys a level off the AMM, then rests a bid below its ladder
+    let MessageToApi::OrderPlaced { payload } = order_at(
+        &mut engine,
+        "carol-take",
+        "carol",
+        AMM_MARKET,
+        OrderSide::Bid,
+        Decimal::new(99, 2),
+    ) else {
+        panic!("the AMM's ask wasn't taken");
+    };
+    assert_eq!(payload.filled_qty, Decimal::from(10));
+    let MessageToApi::OrderPlaced { payload } = order_at(
+        &mut engine,
+        "carol-rest",
+        "carol",
+        AMM_MARKET,
+        OrderSide::Bid,
+        Decimal::new(5, 2),
+    ) else {
+        panic!("the resting bid was rejected");
+    };
+    let resting_bid = payload.order_id;
+
+    assert!(engine.orderbook_workers[AMM_MARKET].mark_halted("Halted by the test"));
+    engine.supervise();
+    assert!(engine.check_invariants().violations.is_empty());
+
+    assert!(engine.restore_market(AMM_MARKET).unwrap() > 1);
+    assert!(engine.health().healthy);
+    assert!(engine.check_invariants().violations.is_empty());
+
+    // The old ladder was swapped for a new one rather than doubled
+    let snapshot = engine.snapshot();
+    let book = &snapshot.books[AMM_MARKET];
+    assert!(book.iter().any(|order| order.id == resting_bid));
+    let amm_orders = book
+        .iter()
+        .filter(|order| order.user_id.starts_with("amm:"))
+        .count();
+    assert!(
+        amm_orders > 0 && amm_orders <= 10,
+        "{} AMM orders",
+        amm_orders
+    );
+
+    let MessageToApi::AmmState { payload } = request(
+        &mut engine,
+        "amm-state",
+        MessageFromApi::GetAmmState {
+            data: GetAmmStatePayload {
+                market: AMM_MARKET.to_string(),
+            },
+        },
+    ) else {
+        panic!("the restored market has no AMM");
+    };
+    assert_eq!(payload.net_sold, Decimal::from(10));
+}
@@ -0,0 +1,193 @@
+//! Approved withdrawals go to the chain adapter off the engine loop and are
+//! settled when its receipt comes back.
+
+use std::{
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use orderbook_manager::{
+    models::{
+        DepositPayload, GetUserBalancesPayload, GetWithdrawalsPayload, MessageFromApi,
+        MessageToApi, WithdrawPayload, Withdrawal, WithdrawalActionPayload, WithdrawalStatus,
+    },
+    services::{MemorySink, MockChainAdapter, RedisManager},
+    trade::Engine,
+};
+use rust_decimal::Decimal;
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn sink() -> &'static Arc<MemorySink> {
+    static SINK: OnceLock<Arc<MemorySink>> = OnceLock::new();
+    SINK.get_or_init(|| {
+        let sink = Arc::new(MemorySink::new());
+        if RedisManager::instance().install_sink(sink.clone()).is_err() {
+            panic!("another sink is already installed");
+        }
+        sink
+    })
+}
+
+fn request(engine: &mut Engine, client_id: &str, message: MessageFromApi) -> MessageToApi {
+    engine.process(client_id.to_string(), message);
+    sink()
+        .wait_for_reply(client_id, REPLY_TIMEOUT)
+        .expect("no reply from the engine")
+}
+
+/// Deposits 100 USDC for `user_id`, asks to withdraw 40 and approves it.
+fn approve_withdrawal(engine: &mut Engine, user_id: &str) -> Withdrawal {
+    request(
+        engine,
+        &format!("{}-deposit", user_id),
+        MessageFromApi::Deposit {
+            data: DepositPayload {
+                user_id: user_id.to_string(),
+                asset: String::from("USDC"),
+                amount: Decimal::from(100),
+                reference: format!("{}-chain-tx", user_id),
+            },
+        },
+    );
+    let MessageToApi::Withdrawal { payload } = request(
+        engine,
+        &format!("{}-withdraw", user_id),
+        MessageFromApi::Withdraw {
+            data: WithdrawPayload {
+                user_id: user_id.to_string(),
+                asset: String::from("USDC"),
+                amount: Decimal::from(40),
+                destination: String::from("wallet"),
+            },
+        },
+    ) else {
+        panic!("withdrawal rejected");
+    };
+
+    let MessageToApi::Withdrawal { payload } = request(
+        engine,
+        &format!("{}-approve", user_id),
+        MessageFromApi::ApproveWithdrawal {
+            data: WithdrawalActionPayload {
+                withdrawal_id: payload.id,
+                reason: None,
+            },
+        },
+    ) else {
+        panic!("approval rejected");
+    };
+    payload
+}
+
+fn withdrawal(engine: &mut Engine, user_id: &str) -> Withdrawal {
+    let MessageToApi::Withdrawals { payload } = request(
+        engine,
+        &format!("{}-withdrawals", user_id),
+        MessageFromApi::GetWithdrawals {
+            data: GetWithdrawalsPayload {
+                user_id: user_id.to_string(),
+            },
+        },
+    ) else {
+        panic!("no withdrawals");
+    };
+    payload.withdrawals.into_iter().next().unwrap()
+}
+
+/// USDC balance and locked balance of `user_id`.
+fn usdc(engine: &mut Engine, user_id: &str) -> (Decimal, Decimal) {
+    let MessageToApi::UserBalances { payload } = request(
+        engine,
+        &format!("{}-balances", user_id),
+        MessageFromApi::GetUserBalances {
+            data: GetUserBalancesPayload {
+                user_id: user_id.to_string(),
+            },
+        },
+    ) else {
+        panic!("no balances");
+    };
+    let usdc = payload
+        .balances
+        .into_iter()
+        .find(|b| b.ticker == "USDC")
+        .unwrap();
+    (usdc.balance, usdc.locked_balance)
+}
+
+#[test]
+fn approving_answers_before_the_chain_does() {
+    sink();
+    let mut engine = Engine::new();
+
+    let approved = approve_withdrawal(&mut engine, "alice");
+    assert_eq!(approved.status, WithdrawalStatus::Approved);
+    assert_eq!(
+        usdc(&mut engine, "alice"),
+        (Decimal::from(100), Decimal::from(40))
+    );
+    assert!(engine.check_invariants().violations.is_empty());
+
+    let receipt = engine
+        .chain
+        .wait_for_receipt(REPLY_TIMEOUT)
+        .expect("no receipt from the chain adapter");
+    assert_eq!(receipt.withdrawal_id, approved.id);
+    engine.settle_withdrawal(receipt);
+
+    let completed = withdrawal(&mut engine, "alice");
+    assert_eq!(completed.status, WithdrawalStatus::Completed);
+    assert!(completed.tx_signature.is_some());
+    assert_eq!(
+        usdc(&mut engine, "alice"),
+        (Decimal::from(60), Decimal::ZERO)
+    );
+    assert!(engine.check_invariants().violations.is_empty());
+}
+
+#[test]
+fn a_failed_submission_releases_the_hold() {
+    sink();
+    let mut engine = Engine::with_chain_adapter(Box::new(MockChainAdapter {
+        fail_with: Some(String::from("RPC unavailable")),
+        ..MockChainAdapter::new()
+    }));
+
+    approve_withdrawal(&mut engine, "bob");
+    let receipt = engine.chain.wait_for_receipt(REPLY_TIMEOUT).unwrap();
+    engine.settle_withdrawal(receipt);
+
+    let rejected = withdrawal(&mut engine, "bob");
+    assert_eq!(rejected.status, WithdrawalStatus::Rejected);
+    assert_eq!(rejected.reason.as_deref(), Some("RPC unavailable"));
+    assert_eq!(
+        usdc(&mut engine, "bob"),
+        (Decimal::from(100), Decimal::ZERO)
+    );
+}
+
+#[test]
+fn a_payout_the_ledger_cannot_settle_is_left_for_reconciliation() {
+    sink();
+    let mut engine = Engine::new();
+
+    approve_withdrawal(&mut engine, "carol");
+    let receipt = engine.chain.wait_for_receipt(REPLY_TIMEOUT).unwrap();
+
+    // The hold went missing while the withdrawal was on chain
+    {
+        let account = engine.accounts.get("carol").unwrap();
+        let mut user = account.lock().unwrap();
+        user.balances.get_mut("USDC").unwrap().locked_balance = Decimal::ZERO;
+    }
+    engine.settle_withdrawal(receipt);
+
+    let unsettled = withdrawal(&mut engine, "carol");
+    assert_eq!(unsettled.status, WithdrawalStatus::Unsettled);
+    assert!(unsettled.tx_signature.is_some());
+
+    let health = engine.health();
+    assert!(!health.healthy);
+    assert_eq!(health.unsettled_withdrawals, vec![unsettled.id]);
+}
@@ -0,0 +1,19 @@
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+
+/// The part of http-server's access token claims wss needs.
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+}
+
+/// User id of a valid, unexpired access token.
+pub fn verify_access_token(secret: &str, token: &str) -> Option<String> {
+    decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::new(Algorithm::HS256),
+    )
+    .map(|data| data.claims.sub)
+    .ok()
+}
@@ -1,10 +1,6 @@
 use crate::types::SharedState;
 use crate::websocket::handle_socket;
-use axum::{
-    extract::ws::{Message, WebSocket, WebSocketUpgrade},
-    extract::State,
-    response::IntoResponse,
-};
+use axum::{extract::ws::WebSocketUpgrade, extract::State, response::IntoResponse};
 
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
@@ -12,3 +8,8 @@ pub async fn ws_handler(
 ) -> impl IntoResponse {
     ws.on_upgrade(move |socket| handle_socket(socket, state))
 }
+
+/// Prometheus text exposition of everything wss records.
+pub async fn metrics_handler(State(state): State<SharedState>) -> String {
+    state.metrics.render()
+}
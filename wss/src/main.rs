@@ -1,29 +1,74 @@
 use axum::{routing::get, Router};
-use std::{net::SocketAddr, sync::Arc};
-use tracing::info;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info};
 
+mod auth;
 mod handlers;
 mod redis_manager;
 mod types;
 mod websocket;
 
+/// Longest clients get to be closed once shutdown starts.
+const SHUTDOWN_TIMEOUT_SECS: u64 = 10;
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
 
     let state = Arc::new(types::AppState::new());
 
+    // Histograms only drain their buckets during upkeep
+    let metrics = state.metrics.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(5));
+        loop {
+            interval.tick().await;
+            metrics.run_upkeep();
+        }
+    });
+
     let app = Router::new()
         .route("/ws", get(handlers::ws_handler))
-        .with_state(state);
+        .route("/metrics", get(handlers::metrics_handler))
+        .with_state(state.clone());
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 8081));
     info!("WebSocket server listening on ws://{}", addr);
 
+    let shutdown_state = state.clone();
     axum::serve(
         tokio::net::TcpListener::bind(addr).await.unwrap(),
         app.into_make_service(),
     )
+    .with_graceful_shutdown(async move {
+        shutdown_signal().await;
+        info!("Shutting down, closing client connections");
+        shutdown_state.shutdown.send_replace(true);
+    })
     .await
     .unwrap();
+
+    // Upgraded sockets aren't tracked by the server, wait for them separately
+    let timeout = Duration::from_secs(SHUTDOWN_TIMEOUT_SECS);
+    if tokio::time::timeout(timeout, state.shutdown.closed())
+        .await
+        .is_err()
+    {
+        error!(
+            "{} clients still open after {}s, exiting",
+            state.shutdown.receiver_count(),
+            SHUTDOWN_TIMEOUT_SECS
+        );
+    }
+    info!("Shutdown complete");
+}
+
+/// Resolves on Ctrl-C or SIGTERM.
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
 }
@@ -1,6 +1,8 @@
 use redis::{Client, Commands};
 use tracing::{error, info};
 
+use crate::types::listen_key_redis_key;
+
 pub struct RedisManager {
     client: Client,
 }
@@ -16,12 +18,23 @@ impl RedisManager {
             .get_connection()
             .map_err(|e| format!("Failed to get Redis connection: {}", e))?;
 
-        conn.publish(room, message)
+        conn.publish::<_, _, ()>(room, message)
             .map_err(|e| format!("Failed to publish message to Redis room '{}': {}", room, e))?;
 
         Ok(())
     }
 
+    /// User id the listen key was issued to, `None` if it is unknown or expired.
+    pub fn resolve_listen_key(&self, listen_key: &str) -> Result<Option<String>, String> {
+        let mut conn = self
+            .client
+            .get_connection()
+            .map_err(|e| format!("Failed to get Redis connection: {}", e))?;
+
+        conn.get(listen_key_redis_key(listen_key))
+            .map_err(|e| format!("Failed to resolve listen key: {}", e))
+    }
+
     pub fn start_listener(
         client: Client,
         room: String,
@@ -1,7 +1,8 @@
 use std::{collections::HashMap, sync::Arc};
 
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
 use serde::{Deserialize, Serialize};
-use tokio::sync::{broadcast, Mutex};
+use tokio::sync::{broadcast, watch, Mutex};
 
 #[derive(Debug, Deserialize)]
 pub struct SubscribePayload {
@@ -13,6 +14,15 @@ pub struct UnsubscribePayload {
     pub room: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct SubscribeUserPayload {
+    #[serde(rename = "listenKey")]
+    pub listen_key: Option<String>,
+    /// Access token from http-server's login, in place of a listen key
+    #[serde(rename = "accessToken")]
+    pub access_token: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct MessagePayload {
     pub room: String,
@@ -26,10 +36,32 @@ pub enum ClientRequest {
     Subscribe { payload: SubscribePayload },
     #[serde(rename = "UNSUBSCRIBE")]
     Unsubscribe { payload: UnsubscribePayload },
+    /// Subscribes to the private stream of whoever the listen key was issued
+    /// to, or the access token was
+    #[serde(rename = "SUBSCRIBE_USER")]
+    SubscribeUser { payload: SubscribeUserPayload },
     #[serde(rename = "SEND_MESSAGE")]
     SendMessage { payload: MessagePayload },
 }
 
+/// Prefix of the per-user rooms, only reachable through `SUBSCRIBE_USER`.
+pub const USER_ROOM_PREFIX: &str = "user@";
+
+/// Label a room is reported under in metrics. Private rooms share one label
+/// so user ids stay out of the metrics and their number stays bounded.
+pub fn room_metric_label(room: &str) -> String {
+    if room.starts_with(USER_ROOM_PREFIX) {
+        USER_ROOM_PREFIX.to_string()
+    } else {
+        room.to_string()
+    }
+}
+
+/// Redis key holding the user id a listen key was issued to.
+pub fn listen_key_redis_key(listen_key: &str) -> String {
+    format!("listenKey:{}", listen_key)
+}
+
 #[derive(Debug, Serialize)]
 pub struct ServerMessage {
     pub room: String,
@@ -39,6 +71,13 @@ pub struct ServerMessage {
 pub struct AppState {
     pub channels: Mutex<HashMap<String, ChannelInfo>>,
     pub redis_client: redis::Client,
+    pub metrics: PrometheusHandle,
+    /// Flipped to true on shutdown. Every open socket holds a receiver, so
+    /// `shutdown.closed()` resolves once all of them have closed.
+    pub shutdown: watch::Sender<bool>,
+    /// Checks access tokens issued by http-server. Without it only listen
+    /// keys open private streams.
+    pub jwt_secret: Option<String>,
 }
 
 impl AppState {
@@ -46,9 +85,16 @@ impl AppState {
         let redis_client = redis::Client::open("redis://127.0.0.1/")
             .expect("Failed to connect to Redis. Ensure Redis is running on redis://127.0.0.1/");
 
+        let metrics = PrometheusBuilder::new()
+            .install_recorder()
+            .expect("Failed to install metrics recorder");
+
         AppState {
             channels: Mutex::new(HashMap::new()),
             redis_client,
+            metrics,
+            shutdown: watch::Sender::new(false),
+            jwt_secret: std::env::var("JWT_SECRET").ok(),
         }
     }
 }
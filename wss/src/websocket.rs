@@ -1,46 +1,98 @@
-use axum::extract::ws::{Message, WebSocket};
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
 use futures::{sink::SinkExt, stream::StreamExt};
+use metrics::{counter, gauge};
 use std::{collections::HashMap, sync::Arc};
-use tokio::sync::Mutex;
+use tokio::sync::{watch, Mutex};
 use tokio::task::JoinHandle;
 use tracing::{error, info};
 
+use crate::auth::verify_access_token;
 use crate::redis_manager::RedisManager;
-use crate::types::{ChannelInfo, ClientRequest, ServerMessage, SharedState};
+use crate::types::{
+    room_metric_label, ChannelInfo, ClientRequest, ServerMessage, SharedState, USER_ROOM_PREFIX,
+};
 
 pub async fn handle_socket(socket: WebSocket, state: SharedState) {
+    let connections = gauge!("ws_connections");
+    connections.increment(1);
+
     let (websocket_sender, mut websocket_receiver) = socket.split();
     let shared_websocket_sender = Arc::new(Mutex::new(websocket_sender));
 
     let mut client_room_forwarding_tasks: HashMap<String, JoinHandle<()>> = HashMap::new();
     let redis_manager = RedisManager::new(state.redis_client.clone());
+    let mut shutdown = state.shutdown.subscribe();
+
+    loop {
+        let msg = tokio::select! {
+            msg = websocket_receiver.next() => match msg {
+                Some(Ok(msg)) => msg,
+                _ => break,
+            },
+            _ = shutdown_requested(&mut shutdown) => {
+                let close = Message::Close(Some(CloseFrame {
+                    code: close_code::AWAY,
+                    reason: "Server shutting down".into(),
+                }));
+                let _ = shared_websocket_sender.lock().await.send(close).await;
+                info!("Closed client connection for shutdown.");
+                break;
+            }
+        };
 
-    while let Some(Ok(msg)) = websocket_receiver.next().await {
         match msg {
             Message::Text(text) => match serde_json::from_str::<ClientRequest>(&text) {
                 Ok(request) => match request {
                     ClientRequest::Subscribe { payload } => {
-                        let room_name = payload.room.clone();
-                        if client_room_forwarding_tasks.contains_key(&room_name) {
-                            info!("Client already subscribed to room: {}", room_name);
+                        if payload.room.starts_with(USER_ROOM_PREFIX) {
+                            let error = "Private streams require SUBSCRIBE_USER with a listen key or access token";
+                            if send_error(&shared_websocket_sender, error).await.is_err() {
+                                break;
+                            }
                             continue;
                         }
 
-                        match subscribe_client_to_room(
-                            shared_websocket_sender.clone(),
-                            state.clone(),
-                            room_name.clone(),
+                        join_room(
+                            &mut client_room_forwarding_tasks,
+                            &shared_websocket_sender,
+                            &state,
+                            payload.room,
                         )
-                        .await
-                        {
-                            Ok(join_handle) => {
-                                client_room_forwarding_tasks.insert(room_name.clone(), join_handle);
-                                info!("Client subscribed to room: {}", room_name);
+                        .await;
+                    }
+                    ClientRequest::SubscribeUser { payload } => {
+                        let resolved = match (&payload.access_token, &payload.listen_key) {
+                            (Some(token), _) => Ok(state
+                                .jwt_secret
+                                .as_deref()
+                                .and_then(|secret| verify_access_token(secret, token))),
+                            (None, Some(listen_key)) => {
+                                redis_manager.resolve_listen_key(listen_key)
+                            }
+                            (None, None) => Ok(None),
+                        };
+                        let user_id = match resolved {
+                            Ok(Some(user_id)) => user_id,
+                            Ok(None) => {
+                                let error = "Invalid listen key or access token";
+                                if send_error(&shared_websocket_sender, error).await.is_err() {
+                                    break;
+                                }
+                                continue;
                             }
                             Err(e) => {
-                                error!("Failed to subscribe client to room {}: {}", room_name, e);
+                                error!("Failed to resolve listen key: {}", e);
+                                continue;
                             }
-                        }
+                        };
+
+                        join_room(
+                            &mut client_room_forwarding_tasks,
+                            &shared_websocket_sender,
+                            &state,
+                            format!("{}{}", USER_ROOM_PREFIX, user_id),
+                        )
+                        .await;
                     }
                     ClientRequest::Unsubscribe { payload } => {
                         let room_name = payload.room.clone();
@@ -59,6 +111,14 @@ pub async fn handle_socket(socket: WebSocket, state: SharedState) {
                         let room_name = payload.room.clone();
                         let message_content = payload.message.clone();
 
+                        if room_name.starts_with(USER_ROOM_PREFIX) {
+                            let error = "Cannot send messages to private streams";
+                            if send_error(&shared_websocket_sender, error).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+
                         if let Err(e) = redis_manager.publish_message(&room_name, &message_content)
                         {
                             error!("Failed to send message to room {}: {}", room_name, e);
@@ -114,17 +174,55 @@ pub async fn handle_socket(socket: WebSocket, state: SharedState) {
             room_name
         );
     }
+    connections.decrement(1);
     info!("Client cleanup complete.");
 }
 
+async fn shutdown_requested(shutdown: &mut watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|stopping| *stopping).await;
+}
+
+type WebSocketSender = Arc<Mutex<futures::stream::SplitSink<WebSocket, Message>>>;
+
+async fn send_error(websocket_tx: &WebSocketSender, error: &str) -> Result<(), axum::Error> {
+    let message = serde_json::json!({ "error": error }).to_string();
+    websocket_tx
+        .lock()
+        .await
+        .send(Message::Text(message.into()))
+        .await
+}
+
+async fn join_room(
+    client_room_forwarding_tasks: &mut HashMap<String, JoinHandle<()>>,
+    websocket_tx: &WebSocketSender,
+    state: &SharedState,
+    room_name: String,
+) {
+    if client_room_forwarding_tasks.contains_key(&room_name) {
+        info!("Client already subscribed to room: {}", room_name);
+        return;
+    }
+
+    match subscribe_client_to_room(websocket_tx.clone(), state.clone(), room_name.clone()).await {
+        Ok(join_handle) => {
+            client_room_forwarding_tasks.insert(room_name.clone(), join_handle);
+            info!("Client subscribed to room: {}", room_name);
+        }
+        Err(e) => {
+            error!("Failed to subscribe client to room {}: {}", room_name, e);
+        }
+    }
+}
+
 async fn subscribe_client_to_room(
-    websocket_tx: Arc<Mutex<futures::stream::SplitSink<WebSocket, Message>>>,
+    websocket_tx: WebSocketSender,
     state: SharedState,
     room: String,
 ) -> Result<JoinHandle<()>, String> {
     let broadcast_sender = {
         let mut channels = state.channels.lock().await;
-        if let Some(info) = channels.get_mut(&room) {
+        let sender = if let Some(info) = channels.get_mut(&room) {
             info.subscribers += 1;
             info.sender.clone()
         } else {
@@ -139,7 +237,10 @@ async fn subscribe_client_to_room(
 
             RedisManager::start_listener(state.redis_client.clone(), room.clone(), s.clone());
             s
-        }
+        };
+        gauge!("ws_rooms").set(channels.len() as f64);
+        gauge!("ws_room_subscribers", "room" => room_metric_label(&room)).increment(1);
+        sender
     };
 
     let mut broadcast_rx = broadcast_sender.subscribe();
@@ -170,6 +271,11 @@ async fn subscribe_client_to_room(
                     }
                 }
                 Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                    counter!(
+                        "ws_broadcast_lagged_messages_total",
+                        "room" => room_metric_label(&room_clone_for_task),
+                    )
+                    .increment(n);
                     error!(
                         "Forwarding task for room '{}': WebSocket sender lagged by {} messages. Skipping.", 
                         room_clone_for_task, n
@@ -195,6 +301,7 @@ async fn decrement_room_subscriber_count(state: SharedState, room: String) {
 
     if let Some(info) = channels.get_mut(&room) {
         info.subscribers = info.subscribers.saturating_sub(1);
+        gauge!("ws_room_subscribers", "room" => room_metric_label(&room)).decrement(1);
         if info.subscribers == 0 {
             remove_channel_fully = true;
         }
@@ -208,5 +315,6 @@ async fn decrement_room_subscriber_count(state: SharedState, room: String) {
 
     if remove_channel_fully {
         channels.remove(&room);
+        gauge!("ws_rooms").set(channels.len() as f64);
     }
 }